@@ -0,0 +1,105 @@
+#![feature(trivial_bounds)]
+#![feature(min_specialization)]
+
+mod common;
+
+use common::Bench;
+use orga::prelude::*;
+
+const TXS: u64 = 1_000;
+
+#[derive(State, Query)]
+pub struct Counter {
+    count: u64,
+}
+
+impl Call for Counter {
+    type Call = u64;
+
+    fn call(&mut self, amount: u64) -> Result<()> {
+        self.count += amount;
+
+        Ok(())
+    }
+}
+
+/// Encoded calls signed by `keystore` for `domain`, one per transaction.
+fn signed_calls(keystore: &dyn Keystore, domain: &SigningDomain) -> Vec<Vec<u8>> {
+    (0..TXS)
+        .map(|i| {
+            let call_bytes = i.encode().unwrap();
+            let expiry = Expiry::default();
+            let msg = domain.message(&expiry, call_bytes.as_slice()).unwrap();
+            SignerCall {
+                signature: Some(keystore.sign(msg.as_slice()).unwrap()),
+                pubkey: Some(keystore.pubkey().unwrap()),
+                multisig: None,
+                expiry,
+                call_bytes,
+            }
+            .encode()
+            .unwrap()
+        })
+        .collect()
+}
+
+fn signer_bench(bench: &Bench, name: &str, keystore: &dyn Keystore) {
+    let domain = SigningDomain::new("bench".into(), String::new());
+    let calls = signed_calls(keystore, &domain);
+    let store = Store::new(Shared::new(MapStore::new()).into());
+    let mut signer =
+        SignerPlugin::<Counter>::create(store, (domain, 0, Default::default())).unwrap();
+
+    bench.run(
+        name,
+        "tx",
+        TXS,
+        10,
+        || (),
+        |_| {
+            for call in calls.iter() {
+                let call = SignerCall::decode(call.as_slice()).unwrap();
+                signer.call(call).unwrap();
+            }
+        },
+    );
+}
+
+/// Measures full blocks of signed transactions, delivered through the
+/// `ABCIStateMachine` of a `TestChain` and committed to its `MerkStore`.
+/// `CheckTx` runs while filling the mempool, before measuring.
+fn deliver_tx_bench(bench: &Bench) {
+    let chain = TestChain::<SignerPlugin<Counter>>::new().unwrap();
+    chain.set_auto_block(false);
+
+    let keystore = MemKeystore::from_secret(&[1; 32]).unwrap();
+    let domain = SigningDomain::new(chain.chain_id(), String::new());
+    let calls = signed_calls(&keystore, &domain);
+
+    bench.run(
+        "deliver_tx_block",
+        "tx",
+        TXS,
+        10,
+        || {
+            for call in calls.iter() {
+                assert_eq!(chain.check_tx(call.clone()).unwrap().code, 0);
+            }
+        },
+        |_| {
+            let results = chain.make_block().unwrap();
+            assert!(results.iter().all(|res| res.code == 0));
+        },
+    );
+}
+
+fn main() {
+    let bench = Bench::from_args();
+
+    let ed25519 = MemKeystore::from_secret(&[1; 32]).unwrap();
+    signer_bench(&bench, "signer_verify_ed25519", &ed25519);
+    let secp256k1 = Secp256k1Keystore::from_secret(&[1; 32]).unwrap();
+    signer_bench(&bench, "signer_verify_secp256k1", &secp256k1);
+
+    deliver_tx_bench(&bench);
+}
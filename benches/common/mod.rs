@@ -0,0 +1,121 @@
+//! A minimal harness shared by the benchmarks which report throughput and
+//! allocations, which libtest's `#[bench]` can not measure.
+//!
+//! Each benchmark is a function of a setup step, which is not measured, and
+//! a measured step which performs a known number of operations. Results are
+//! printed as operations per second along with the time, allocation count
+//! and allocated bytes per operation. Benchmarks can be filtered by passing
+//! substrings of their names, e.g. `cargo bench --features merk,abci --bench
+//! state -- map_`.
+
+#![allow(dead_code)]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Wraps the system allocator, counting every allocation made by the
+/// benchmark process.
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn allocations() -> (u64, u64) {
+    (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+pub struct Bench {
+    filters: Vec<String>,
+}
+
+impl Bench {
+    /// Reads name filters from the command line, ignoring the flags cargo
+    /// passes to benchmark binaries.
+    pub fn from_args() -> Self {
+        let filters = std::env::args()
+            .skip(1)
+            .filter(|arg| !arg.starts_with('-'))
+            .collect();
+
+        println!(
+            "{:<32} {:>16} {:>12} {:>12} {:>12}",
+            "benchmark", "throughput", "time/op", "allocs/op", "bytes/op"
+        );
+
+        Bench { filters }
+    }
+
+    /// Runs `run` over the output of `setup` for `iterations` iterations after
+    /// one warm-up iteration, where each call to `run` performs `ops`
+    /// operations of the given `unit` (e.g. "tx").
+    pub fn run<T, S, F>(
+        &self,
+        name: &str,
+        unit: &str,
+        ops: u64,
+        iterations: u32,
+        mut setup: S,
+        mut run: F,
+    ) where
+        S: FnMut() -> T,
+        F: FnMut(T),
+    {
+        if !self.filters.is_empty() && !self.filters.iter().any(|f| name.contains(f.as_str())) {
+            return;
+        }
+
+        run(setup());
+
+        let mut elapsed = Duration::default();
+        let mut allocs = 0;
+        let mut bytes = 0;
+        for _ in 0..iterations {
+            let input = setup();
+
+            let (start_allocs, start_bytes) = allocations();
+            let start = Instant::now();
+            run(input);
+            elapsed += start.elapsed();
+            let (end_allocs, end_bytes) = allocations();
+
+            allocs += end_allocs - start_allocs;
+            bytes += end_bytes - start_bytes;
+        }
+
+        let total_ops = (ops * iterations as u64) as f64;
+        println!(
+            "{:<32} {:>11.0} {:>2}/s {:>9.0} ns {:>12.1} {:>12.0}",
+            name,
+            total_ops / elapsed.as_secs_f64(),
+            unit,
+            elapsed.as_nanos() as f64 / total_ops,
+            allocs as f64 / total_ops,
+            bytes as f64 / total_ops,
+        );
+    }
+}
@@ -0,0 +1,222 @@
+#![feature(trivial_bounds)]
+#![feature(min_specialization)]
+
+mod common;
+
+use common::Bench;
+use orga::merk::MerkStore;
+use orga::prelude::*;
+use tempdir::TempDir;
+
+const MAP_ENTRIES: u64 = 10_000;
+const POOL_CHILDREN: u64 = 10_000;
+const VALIDATORS: u64 = 10;
+const DELEGATORS: u64 = 1_000;
+
+#[derive(State, Debug, Clone)]
+pub struct Simp(());
+impl Symbol for Simp {}
+
+fn address(index: u64) -> Address {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&index.to_be_bytes());
+    bytes.into()
+}
+
+fn mem_store() -> Store {
+    Store::new(Shared::new(MapStore::new()).into())
+}
+
+/// A `MerkStore` in a temporary directory, which is removed once the store is
+/// dropped.
+struct TempMerk {
+    store: Shared<MerkStore>,
+    height: u64,
+    _dir: TempDir,
+}
+
+impl TempMerk {
+    fn new() -> Self {
+        let dir = TempDir::new("orga-bench").unwrap();
+        let store = Shared::new(MerkStore::new(dir.path().into()));
+        TempMerk {
+            store,
+            height: 0,
+            _dir: dir,
+        }
+    }
+
+    fn map(&self) -> Map<u64, u64, Shared<MerkStore>> {
+        Map::create(Store::new(self.store.clone()), ()).unwrap()
+    }
+
+    fn commit(&mut self) {
+        self.height += 1;
+        self.store.borrow_mut().commit(self.height).unwrap();
+    }
+
+    /// A store with `MAP_ENTRIES` entries committed.
+    fn filled() -> Self {
+        let mut merk = TempMerk::new();
+        let mut map = merk.map();
+        for i in 0..MAP_ENTRIES {
+            map.insert(i, i).unwrap();
+        }
+        map.flush().unwrap();
+        merk.commit();
+        merk
+    }
+}
+
+fn map_benches(bench: &Bench) {
+    bench.run(
+        "map_insert_merk",
+        "op",
+        MAP_ENTRIES,
+        5,
+        TempMerk::new,
+        |mut merk| {
+            let mut map = merk.map();
+            for i in 0..MAP_ENTRIES {
+                map.insert(i, i).unwrap();
+            }
+            map.flush().unwrap();
+            merk.commit();
+        },
+    );
+
+    let merk = TempMerk::filled();
+    bench.run(
+        "map_get_merk",
+        "op",
+        MAP_ENTRIES,
+        10,
+        || merk.map(),
+        |map| {
+            for i in 0..MAP_ENTRIES {
+                // visit keys out of order
+                let key = (i * 7919) % MAP_ENTRIES;
+                assert!(map.get(key).unwrap().is_some());
+            }
+        },
+    );
+
+    bench.run(
+        "map_iter_merk",
+        "op",
+        MAP_ENTRIES,
+        10,
+        || merk.map(),
+        |map| {
+            assert_eq!(map.iter().unwrap().count() as u64, MAP_ENTRIES);
+        },
+    );
+}
+
+type SharePool = Pool<Address, Share<Simp>, Simp>;
+
+/// A pool with `POOL_CHILDREN` children holding 10 coins each.
+fn filled_pool() -> SharePool {
+    let mut pool = SharePool::create(mem_store(), Default::default()).unwrap();
+    for i in 0..POOL_CHILDREN {
+        pool.get_mut(address(i)).unwrap().add(10).unwrap();
+    }
+    pool
+}
+
+fn pool_benches(bench: &Bench) {
+    bench.run("pool_give", "op", 1_000, 10, filled_pool, |mut pool| {
+        for _ in 0..1_000 {
+            pool.add(1).unwrap();
+        }
+    });
+
+    // Children are adjusted lazily the next time they are accessed after the
+    // pool's multiplier changes.
+    bench.run(
+        "pool_adjust_children",
+        "op",
+        POOL_CHILDREN,
+        5,
+        || {
+            let mut pool = filled_pool();
+            pool.add(POOL_CHILDREN).unwrap();
+            pool
+        },
+        |mut pool| {
+            for i in 0..POOL_CHILDREN {
+                pool.get_mut(address(i)).unwrap();
+            }
+        },
+    );
+}
+
+/// A staking instance with `VALIDATORS` declared validators.
+fn staking() -> Staking<Simp> {
+    Context::add(Validators::default());
+    Context::add(Time {
+        seconds: 0,
+        nanos: 0,
+    });
+
+    let mut staking = Staking::create(mem_store(), Default::default()).unwrap();
+    for i in 0..VALIDATORS {
+        let consensus_key = address(u64::MAX - i);
+        staking
+            .declare(address(i), consensus_key, Coin::mint(100))
+            .unwrap();
+    }
+    staking
+}
+
+fn delegated_staking() -> Staking<Simp> {
+    let mut staking = staking();
+    for i in 0..DELEGATORS {
+        let delegator = address(VALIDATORS + i);
+        staking
+            .delegate(address(i % VALIDATORS), delegator, Coin::mint(100))
+            .unwrap();
+    }
+    staking
+}
+
+fn staking_benches(bench: &Bench) {
+    bench.run(
+        "staking_delegate",
+        "op",
+        DELEGATORS,
+        5,
+        staking,
+        |mut staking| {
+            for i in 0..DELEGATORS {
+                let delegator = address(VALIDATORS + i);
+                staking
+                    .delegate(address(i % VALIDATORS), delegator, Coin::mint(100))
+                    .unwrap();
+            }
+        },
+    );
+
+    bench.run(
+        "staking_unbond",
+        "op",
+        DELEGATORS,
+        5,
+        delegated_staking,
+        |mut staking| {
+            for i in 0..DELEGATORS {
+                let delegator = address(VALIDATORS + i);
+                staking
+                    .unbond(address(i % VALIDATORS), delegator, 10)
+                    .unwrap();
+            }
+        },
+    );
+}
+
+fn main() {
+    let bench = Bench::from_args();
+    map_benches(&bench);
+    pool_benches(&bench);
+    staking_benches(&bench);
+}
@@ -14,6 +14,7 @@ impl BeginBlock for Counter {
     }
 }
 
+#[orga::methods(client)]
 impl Counter {
     #[call]
     pub fn increment(&mut self) {
@@ -14,20 +14,18 @@ pub type CounterApp = SignerPlugin<NoncePlugin<MultiCounter>>;
 type CounterQuery = counter_query::Query;
 type MultiCounterQuery = multi_counter_query::Query;
 type MapQuery = <Map<Address, Counter> as Query>::Query;
+type MapMethodQuery = orga::collections::map::map_methods::Query<Address>;
 
 pub async fn run_client() -> Result<()> {
     let mut client = TendermintClient::<CounterApp>::new("http://localhost:26657")?;
-
-    let my_address = [
-        194, 42, 183, 160, 59, 68, 203, 90, 200, 61, 123, 126, 110, 150, 217, 245, 196, 90, 179,
-        178, 179, 193, 107, 118, 13, 117, 195, 236, 191, 213, 145, 148,
-    ]
-    .into();
+    let my_address = client.keystore().address()?;
 
     let query_my_count = || {
         let count = CounterQuery::FieldCount(()).encode().unwrap();
-        let map_get = MapQuery::MethodGet(my_address, count);
-        MultiCounterQuery::FieldCounters(map_get)
+        let map_get = MapMethodQuery::MethodGet(my_address, count)
+            .encode()
+            .unwrap();
+        NonceQuery::Inner(MultiCounterQuery::FieldCounters(MapQuery::Method(map_get)))
     };
 
     println!(
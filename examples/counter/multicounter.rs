@@ -6,6 +6,7 @@ pub struct MultiCounter {
     pub counters: Map<Address, Counter>,
 }
 
+#[orga::methods(client)]
 impl MultiCounter {
     #[call]
     pub fn increment(&mut self) -> Result<()> {
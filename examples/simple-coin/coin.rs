@@ -2,7 +2,7 @@
 use orga::client::Client;
 use orga::coins::*;
 use orga::encoding::{Decode, Encode};
-use orga::plugins::load_keypair;
+use orga::plugins::{load_keypair, Pubkey};
 use orga::prelude::*;
 use orga::{Error, Result};
 
@@ -29,10 +29,9 @@ pub struct SimpleCoin {
 
 impl InitChain for SimpleCoin {
     fn init_chain(&mut self, _ctx: &InitChainCtx) -> Result<()> {
-        let my_address = load_keypair().unwrap().public.to_bytes();
+        let my_address = Pubkey::Ed25519(load_keypair().unwrap().public.to_bytes()).address();
         println!("my address: {:?}", my_address);
-        self.balances
-            .insert(my_address.into(), Simp::mint(100).into())?;
+        self.balances.insert(my_address, Simp::mint(100).into())?;
         Ok(())
     }
 }
@@ -49,6 +48,7 @@ impl BeginBlock for SimpleCoin {
     }
 }
 
+#[orga::methods(client)]
 impl SimpleCoin {
     #[call]
     pub fn transfer(&mut self, to: Address, amount: Amount) -> Result<()> {
@@ -22,23 +22,28 @@ impl InitChain for StakingApp {
     }
 }
 
-type MyApp = DefaultPlugins<StakingApp>;
+type MyApp = AuthBatchPlugins<StakingApp>;
 
-fn rpc_client() -> TendermintClient<MyApp> {
-    TendermintClient::new("http://localhost:26657").unwrap()
+async fn rpc_client() -> TendermintClient<MyApp> {
+    let mut client = TendermintClient::new("http://localhost:26657").unwrap();
+    client.set_expiry(Expiry::from_now(std::time::Duration::from_secs(60)).unwrap());
+
+    client
 }
 
 fn my_address() -> Address {
-    load_keypair().unwrap().public.to_bytes().into()
+    Pubkey::Ed25519(load_keypair().unwrap().public.to_bytes()).address()
 }
 
 async fn my_balance() -> Result<Amount> {
     let address = my_address();
-    let client = rpc_client();
-    type AppQuery = <MyApp as Query>::Query;
+    let client = rpc_client().await;
+    type AppQuery = <StakingApp as Query>::Query;
     type AcctQuery = <Accounts<MyCoin> as Query>::Query;
+    type AcctMethodQuery = <Accounts<MyCoin> as MethodQuery>::MethodQuery;
 
-    let q = AppQuery::FieldAccounts(AcctQuery::MethodBalance(address, vec![]));
+    let balance_query = AcctMethodQuery::MethodBalance(address, vec![]).encode()?;
+    let q = NonceQuery::Inner(AppQuery::FieldAccounts(AcctQuery::Method(balance_query)));
     let balance = client
         .query(q, |state| state.accounts.balance(address))
         .await?;
@@ -61,6 +66,7 @@ async fn main() {
     println!("My balance: {:?}", bal);
 
     rpc_client()
+        .await
         .accounts
         .transfer([0; 32].into(), 100.into())
         .await
@@ -70,6 +76,17 @@ async fn main() {
     println!("My balance: {:?}", bal);
 
     rpc_client()
+        .await
+        .batch(async move |mut client| {
+            client.accounts.transfer([1; 32].into(), 10.into()).await?;
+            client.accounts.transfer([2; 32].into(), 10.into()).await
+        })
+        .await
+        .unwrap();
+    println!("Sent coins in a batch");
+
+    rpc_client()
+        .await
         .pay_from(async move |mut client| client.accounts.take_as_funding(123.into()).await)
         .accounts
         .give_from_funding(122.into())
@@ -81,6 +98,7 @@ async fn main() {
         37, 162, 200, 239, 171, 237, 137, 24, 36, 69, 37,
     ];
     rpc_client()
+        .await
         .pay_from(async move |mut client| client.accounts.take_as_funding(350.into()).await)
         .staking
         .declare_self(my_tm_key.into(), 350.into())
@@ -88,6 +106,7 @@ async fn main() {
         .unwrap();
 
     rpc_client()
+        .await
         .pay_from(async move |mut client| client.accounts.take_as_funding(250.into()).await)
         .staking
         .delegate_from_self(my_address(), 250.into())
@@ -95,12 +114,14 @@ async fn main() {
         .unwrap();
 
     rpc_client()
+        .await
         .staking
         .unbond_self(my_address(), 100.into())
         .await
         .unwrap();
 
     rpc_client()
+        .await
         .pay_from(async move |mut client| client.accounts.take_as_funding(100.into()).await)
         .staking
         .delegate_from_self([0; 32].into(), 100.into())
@@ -108,6 +129,8 @@ async fn main() {
         .unwrap_or_else(|e| {
             println!("{:?}", e);
         });
+    // Make sure the local nonce matches the chain after the failed call
+    rpc_client().await.resync_nonce().await.unwrap();
 
     let bal = my_balance().await.unwrap();
     println!("My balance: {:?}", bal);
@@ -1,15 +1,13 @@
-use heck::{CamelCase, SnakeCase};
+use heck::SnakeCase;
 use proc_macro::TokenStream;
-use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
-use std::collections::HashSet;
 use syn::*;
 
 use super::utils::*;
 
 pub fn derive(item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as DeriveInput);
-    let source = parse_parent();
 
     let name = &item.ident;
     let modname = Ident::new(
@@ -17,8 +15,13 @@ pub fn derive(item: TokenStream) -> TokenStream {
         Span::call_site(),
     );
 
-    let (call_enum_tokens, call_enum) = create_call_enum(&item, &source);
-    let (call_impl_tokens, _) = create_call_impl(&item, &source, &call_enum);
+    let fields = match routed_fields(&item, "call") {
+        Ok(fields) => fields,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let (call_enum_tokens, call_enum) = create_call_enum(&fields);
+    let call_impl_tokens = create_call_impl(&item, &fields, &call_enum);
 
     let output = quote!(
         use ::orga::macros::*;
@@ -33,33 +36,23 @@ pub fn derive(item: TokenStream) -> TokenStream {
     output.into()
 }
 
-pub fn attr(_args: TokenStream, input: TokenStream) -> TokenStream {
+pub fn attr(args: TokenStream, input: TokenStream) -> TokenStream {
     let method = parse_macro_input!(input as ImplItemMethod);
 
-    if !matches!(method.vis, Visibility::Public(_)) {
-        panic!("Call methods must be public");
-    }
-
-    if method.sig.unsafety.is_some() {
-        panic!("Call methods cannot be unsafe");
-    }
-
-    if method.sig.asyncness.is_some() {
-        panic!("Call methods cannot be async");
-    }
-
-    if method.sig.abi.is_some() {
-        panic!("Call methods cannot specify ABI");
+    match validate_method(&method, args.into(), "Call") {
+        Ok(()) => quote!(#method).into(),
+        Err(err) => {
+            let err = err.to_compile_error();
+            quote!(#method #err).into()
+        }
     }
-
-    quote!(#method).into()
 }
 
-pub(super) fn create_call_impl(
+fn create_call_impl(
     item: &DeriveInput,
-    source: &File,
+    fields: &[RoutedField],
     call_enum: &ItemEnum,
-) -> (TokenStream2, ItemImpl) {
+) -> TokenStream2 {
     let name = &item.ident;
     let generics = &item.generics;
     let mut generics_sanitized = generics.clone();
@@ -71,123 +64,95 @@ pub(super) fn create_call_impl(
     let generic_params = gen_param_input(generics, true);
 
     let call_type = &call_enum.ident;
-    let call_generics = &call_enum.generics;
     let where_preds = item.generics.where_clause.as_ref().map(|w| &w.predicates);
 
-    let encoding_bounds = relevant_methods(name, "call", source)
-        .into_iter()
-        .flat_map(|(method, _)| {
-            let inputs: Vec<_> = method
-                .sig
-                .inputs
-                .iter()
-                .skip(1)
-                .map(|input| match input {
-                    FnArg::Typed(input) => *input.ty.clone(),
-                    _ => panic!("unexpected input"),
-                })
-                .collect();
+    let field_call_arms: Vec<_> = fields
+        .iter()
+        .map(|field| {
+            let variant_name = field.variant_name();
+            let binding = Ident::new("field", Span::call_site());
+            let call_field = field.with_field(
+                true,
+                &binding,
+                quote!(::orga::call::maybe_call(#binding, subcall)),
+                quote!(::orga::Error::Call),
+            );
 
-            get_generic_requirements(
-                inputs.iter().cloned(),
-                item.generics.params.iter().cloned(),
-            )
+            quote! {
+                Call::#variant_name(subcall) => #call_field
+            }
         })
-        .map(|p| quote!(#p: ::orga::encoding::Encode + ::orga::encoding::Decode + ::orga::encoding::Terminated,));
-    let encoding_bounds = quote!(#(#encoding_bounds)*);
+        .collect();
 
-    let call_bounds = relevant_methods(name, "call", source)
-        .into_iter()
-        .map(|(method, _)| {
-            let unit_tuple: Type = parse2(quote!(())).unwrap();
-            match method.sig.output {
-                ReturnType::Type(_, ref ty) => *(ty.clone()),
-                ReturnType::Default => unit_tuple,
+    quote! {
+        impl#generics_sanitized ::orga::call::Call for #name#generic_params
+        where #where_preds
+        {
+            type Call = #call_type;
+
+            fn call(&mut self, call: Self::Call) -> ::orga::Result<()> {
+                match call {
+                    #call_type::Noop => Ok(()),
+                    #(#field_call_arms,)*
+                    #call_type::Method(call_bytes) => {
+                        ::orga::call::maybe_method_call(self, call_bytes)
+                    }
+                }
             }
-        })
+        }
+    }
+}
+
+/// Generates the method call enum and `MethodCall` implementation for the
+/// `#[call]` methods of an `#[orga::methods]` impl block. The output is meant
+/// to be placed in the type's methods module.
+pub(super) fn create_method_call(item: &ItemImpl, methods: &[Method]) -> TokenStream2 {
+    let self_ty = &item.self_ty;
+    let impl_generics = &item.generics;
+    let where_preds = where_preds(&item.generics);
+
+    let encoding_bounds = method_encoding_bounds(item, methods);
+    let encoding_bounds = quote!(#(#encoding_bounds)*);
+
+    let call_bounds = methods
+        .iter()
+        .map(|method| method.output_type())
         .flat_map(|ty| {
             get_generic_requirements(
                 std::iter::once(&ty).cloned(),
-                generics.params.iter().cloned(),
+                impl_generics.params.iter().cloned(),
             )
         })
         .map(|t| quote!(#t: ::orga::call::Call,));
     let call_bounds = quote!(#(#call_bounds)*);
 
-    let fields = match &item.data {
-        Data::Struct(data) => data.fields.iter(),
-        Data::Enum(_) => todo!("Enums are not supported yet"),
-        Data::Union(_) => panic!("Unions are not supported"),
-    };
-    let field_call_arms: Vec<_> = fields
-        .filter(|field| matches!(field.vis, Visibility::Public(_)))
-        .enumerate()
-        .map(|(i, field)| {
-            let variant_name = field.ident.as_ref().map_or(
-                Ident::new(format!("Field{}", i).as_str(), Span::call_site()),
-                |f| {
-                    Ident::new(
-                        format!("Field{}", f.to_string().to_camel_case()).as_str(),
-                        Span::call_site(),
-                    )
-                },
-            );
-            let field_name = field.ident.as_ref().map_or_else(
-                || {
-                    let i = Literal::usize_unsuffixed(i);
-                    quote!(#i)
-                },
-                |f| quote!(#f),
-            );
-
-            quote! {
-                Call::#variant_name(subcall) => {
-                    ::orga::call::maybe_call(&mut self.#field_name, subcall)
-                }
-            }
-        })
-        .collect();
-
     let mut maybe_call_defs = vec![];
-    let method_call_arms: Vec<_> = relevant_methods(name, "call", source)
-        .into_iter()
-        .map(|(method, parent)| {
-            let method_name = &method.sig.ident;
-
-            let name_camel = method_name.to_string().to_camel_case();
-            let variant_name =
-                Ident::new(format!("Method{}", &name_camel).as_str(), Span::call_site());
+    let method_call_arms: Vec<_> = methods
+        .iter()
+        .map(|method| {
+            let variant_name = method.variant_name();
 
-            let inputs: Vec<_> = (1..method.sig.inputs.len())
-                .into_iter()
+            let input_types = method.input_types();
+            let inputs: Vec<_> = (1..=input_types.len())
                 .map(|i| Ident::new(format!("var{}", i).as_str(), Span::call_site()))
                 .collect();
-            let input_types: Vec<_> = method
-                .sig
-                .inputs
-                .iter()
-                .skip(1)
-                .filter_map(|input| match input {
-                    FnArg::Typed(input) => Some(*input.ty.clone()),
-                    _ => None,
-                })
-                .collect();
+            let input_bindings = method.input_bindings(&inputs);
             let full_inputs = quote! {
                 #(, #inputs: #input_types)*, subcall: Vec<u8>
             };
-
-            let unit_tuple: Type = parse2(quote!(())).unwrap();
-            let output_type = match method.sig.output {
-                ReturnType::Type(_, ref ty) => *(ty.clone()),
-                ReturnType::Default => unit_tuple,
+            let full_input_bindings = quote! {
+                #(, #input_bindings: #input_types)*, subcall: Vec<u8>
             };
+            let invocation = method.invocation(quote!(self), &inputs);
+
+            let output_type = method.output_type();
 
             let requirements = get_generic_requirements(
                 input_types
                     .iter()
                     .chain(std::iter::once(&output_type))
                     .cloned(),
-                generics.params.iter().cloned(),
+                impl_generics.params.iter().cloned(),
             );
             let generic_reqs = if requirements.is_empty() {
                 quote!()
@@ -195,8 +160,7 @@ pub(super) fn create_call_impl(
                 quote!(<#(#requirements),*>)
             };
 
-            let parent_generics = &parent.generics;
-            let parent_where_preds = &parent.generics.where_clause.as_ref().map(|w| &w.predicates);
+            let method_where_preds = method.where_preds();
 
             let trait_name = Ident::new(
                 format!("MaybeCall{}", &variant_name).as_str(),
@@ -211,11 +175,11 @@ pub(super) fn create_call_impl(
                         Err(::orga::Error::Call("This call cannot be called because not all bounds are met".into()))
                     }
                 }
-                impl#parent_generics #trait_name#generic_reqs for #name#generic_params
-                where #where_preds #encoding_bounds #call_bounds #parent_where_preds
+                impl#impl_generics #trait_name#generic_reqs for #self_ty
+                where #where_preds #encoding_bounds #call_bounds #method_where_preds
                 {
-                    fn maybe_call(&mut self #full_inputs) -> ::orga::Result<()> {
-                        let output = self.#method_name(#(#inputs),*);
+                    fn maybe_call(&mut self #full_input_bindings) -> ::orga::Result<()> {
+                        let output = #invocation;
                         ::orga::call::maybe_call(output, subcall)
                     }
                 }
@@ -235,121 +199,64 @@ pub(super) fn create_call_impl(
         })
         .collect();
 
-    let impl_output = quote! {
-        impl#generics_sanitized ::orga::call::Call for #name#generic_params
+    let method_variants: Vec<_> = methods
+        .iter()
+        .map(|method| {
+            let name = method.variant_name();
+            let inputs = method.input_types();
+
+            quote! {
+                #name(#(#inputs,)* Vec<u8>)
+            }
+        })
+        .collect();
+
+    let enum_params = method_enum_params(item, methods);
+
+    quote! {
+        #[derive(::orga::encoding::Encode, ::orga::encoding::Decode)]
+        pub enum Call#enum_params {
+            #(#method_variants,)*
+        }
+
+        impl#impl_generics ::orga::call::MethodCall for #self_ty
         where #where_preds #encoding_bounds
         {
-            type Call = #call_type#call_generics;
+            type MethodCall = Call#enum_params;
 
-            fn call(&mut self, call: Self::Call) -> ::orga::Result<()> {
+            fn method_call(&mut self, call: Self::MethodCall) -> ::orga::Result<()> {
                 match call {
-                    #call_type::Noop => Ok(()),
-                    #(#field_call_arms),*
                     #(#method_call_arms),*
                 }
             }
         }
-    };
 
-    let output = quote! {
-        #impl_output
         #(#maybe_call_defs)*
-    };
-
-    (output, syn::parse2(impl_output).unwrap())
+    }
 }
 
-pub(super) fn create_call_enum(item: &DeriveInput, source: &File) -> (TokenStream2, ItemEnum) {
-    let name = &item.ident;
-    let generics = &item.generics;
-
-    let mut generic_params = vec![];
-
-    let fields = match &item.data {
-        Data::Struct(data) => data.fields.iter(),
-        Data::Enum(_) => todo!("Enums are not supported yet"),
-        Data::Union(_) => panic!("Unions are not supported"),
-    };
+fn create_call_enum(fields: &[RoutedField]) -> (TokenStream2, ItemEnum) {
     let field_variants: Vec<_> = fields
-        .filter(|field| matches!(field.vis, Visibility::Public(_)))
-        .enumerate()
-        .map(|(i, field)| {
-            let name = field.ident.as_ref().map_or(
-                Ident::new(format!("Field{}", i).as_str(), Span::call_site()),
-                |f| {
-                    Ident::new(
-                        format!("Field{}", f.to_string().to_camel_case()).as_str(),
-                        Span::call_site(),
-                    )
-                },
-            );
-
-            let requirements = get_generic_requirements(
-                vec![field.ty.clone()].into_iter(),
-                generics.params.iter().cloned(),
-            );
-            generic_params.extend(requirements.clone());
-
+        .iter()
+        .map(|field| {
+            let name = field.variant_name();
             quote!(#name(Vec<u8>))
         })
         .collect();
 
-    let method_variants: Vec<_> = relevant_methods(name, "call", source)
-        .into_iter()
-        .map(|(method, _)| {
-            let name_camel = method.sig.ident.to_string().to_camel_case();
-            let name = Ident::new(format!("Method{}", &name_camel).as_str(), Span::call_site());
-
-            let fields = if method.sig.inputs.len() == 1 {
-                quote!()
-            } else {
-                let inputs: Vec<_> = method
-                    .sig
-                    .inputs
-                    .iter()
-                    .skip(1)
-                    .map(|input| match input {
-                        FnArg::Typed(input) => *input.ty.clone(),
-                        _ => panic!("unexpected input"),
-                    })
-                    .collect();
-
-                let requirements = get_generic_requirements(
-                    inputs.iter().cloned(),
-                    generics.params.iter().cloned(),
-                );
-                generic_params.extend(requirements);
-
-                quote! { #(#inputs),*, }
-            };
-
-            quote! {
-                #name(#fields Vec<u8>)
-            }
-        })
-        .collect();
-
-    let generic_params = if generic_params.is_empty() {
-        quote!()
-    } else {
-        let params: HashSet<_> = generic_params.into_iter().collect();
-        let params = params.into_iter();
-        quote!(<#(#params),*>)
-    };
-
     let struct_output = quote! {
         #[derive(::orga::encoding::Encode, ::orga::encoding::Decode)]
-        pub enum Call#generic_params {
+        pub enum Call {
             Noop,
             #(#field_variants,)*
-            #(#method_variants,)*
+            Method(Vec<u8>),
         }
     };
 
     let output = quote! {
         #struct_output
 
-        impl#generic_params Default for Call#generic_params {
+        impl Default for Call {
             fn default() -> Self {
                 Call::Noop
             }
@@ -358,28 +265,3 @@ pub(super) fn create_call_enum(item: &DeriveInput, source: &File) -> (TokenStrea
 
     (output, syn::parse2(struct_output).unwrap())
 }
-
-fn gen_param_input(generics: &Generics, bracketed: bool) -> TokenStream2 {
-    let gen_params = generics.params.iter().map(|p| match p {
-        GenericParam::Type(p) => {
-            let ident = &p.ident;
-            quote!(#ident)
-        }
-        GenericParam::Lifetime(p) => {
-            let ident = &p.lifetime.ident;
-            quote!(#ident)
-        }
-        GenericParam::Const(p) => {
-            let ident = &p.ident;
-            quote!(#ident)
-        }
-    });
-
-    if gen_params.len() == 0 {
-        quote!()
-    } else if bracketed {
-        quote!(<#(#gen_params),*>)
-    } else {
-        quote!(#(#gen_params),*)
-    }
-}
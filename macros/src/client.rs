@@ -1,4 +1,4 @@
-use heck::{CamelCase, SnakeCase};
+use heck::SnakeCase;
 use proc_macro::TokenStream;
 use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
 use quote::quote;
@@ -8,7 +8,6 @@ use super::utils::*;
 
 pub fn derive(item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as DeriveInput);
-    let source = parse_parent();
 
     let name = &item.ident;
     let modname = Ident::new(
@@ -16,9 +15,15 @@ pub fn derive(item: TokenStream) -> TokenStream {
         Span::call_site(),
     );
 
-    let field_adapters = create_field_adapters(&item);
+    // fields the call derive skips have no call variant to adapt
+    let fields = match routed_fields(&item, "call") {
+        Ok(fields) => fields,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let field_adapters = create_field_adapters(&item, fields);
     let client_impl = create_client_impl(&item, &modname);
-    let client_struct = create_client_struct(&item, field_adapters.1, &source);
+    let client_struct = create_client_struct(&item, field_adapters.1);
 
     let field_adapters = field_adapters.0;
 
@@ -69,14 +74,11 @@ fn create_client_impl(item: &DeriveInput, modname: &Ident) -> TokenStream2 {
 
 fn create_client_struct(
     item: &DeriveInput,
-    field_adapters: Vec<(&Field, ItemStruct)>,
-    source: &File,
+    field_adapters: Vec<(RoutedField<'_>, ItemStruct)>,
 ) -> TokenStream2 {
-    let name = &item.ident;
     let generics = &item.generics;
 
     let parent_ty: GenericParam = syn::parse2(quote!(__Parent)).unwrap();
-    let return_ty: GenericParam = syn::parse2(quote!(__Return)).unwrap();
 
     let mut generics_sanitized = generics.clone();
     generics_sanitized.params.iter_mut().for_each(|g| {
@@ -89,248 +91,57 @@ fn create_client_struct(
     let mut generics_with_parent = generics.clone();
     generics_with_parent.params.push(parent_ty.clone());
 
-    let mut generics_with_return_and_parent = generics.clone();
-    generics_with_return_and_parent
-        .params
-        .push(return_ty.clone());
-    generics_with_return_and_parent
-        .params
-        .push(parent_ty.clone());
-
-    let mut generics_sanitized_with_return = generics_sanitized.clone();
-    generics_sanitized_with_return.params.push(return_ty);
-
     let generic_params = gen_param_input(generics, false);
-    let generic_params_bracketed = gen_param_input(generics, true);
     let generic_params_bracketed_with_parent = gen_param_input(&generics_with_parent, true);
     let where_preds = item.generics.where_clause.as_ref().map(|w| &w.predicates);
 
-    let field_fields = field_adapters
-        .iter()
-        .enumerate()
-        .map(|(i, (field, adapter))| {
-            let field_name = field.ident.as_ref().map_or_else(
-                || {
-                    let i = Literal::usize_unsuffixed(i);
-                    quote!(#i)
-                },
-                |f| quote!(#f),
-            );
-            let field_ty = &field.ty;
-
-            let adapter_name = &adapter.ident;
-            let mut adapter_generics = adapter.generics.clone();
-            adapter_generics.params.iter_mut().for_each(|g| {
-                if let GenericParam::Type(ref mut t) = g {
-                    t.default = None;
-                    t.bounds = Default::default();
-                }
-            });
+    let field_fields = field_adapters.iter().map(|(field, adapter)| {
+        let field_name = field.client_name();
+        let field_ty = &field.field.ty;
 
-            quote!(pub #field_name: <#field_ty as ::orga::client::Client<#adapter_name#adapter_generics>>::Client)
+        let adapter_name = &adapter.ident;
+        let mut adapter_generics = adapter.generics.clone();
+        adapter_generics.params.iter_mut().for_each(|g| {
+            if let GenericParam::Type(ref mut t) = g {
+                t.default = None;
+                t.bounds = Default::default();
+            }
         });
 
-    let field_clones = field_adapters
-        .iter()
-        .enumerate()
-        .map(|(i, (field, adapter))| {
-            let field_name = field.ident.as_ref().map_or_else(
-                || {
-                    let i = Literal::usize_unsuffixed(i);
-                    quote!(#i)
-                },
-                |f| quote!(#f),
-            );
-
-            let mut adapter_generics = adapter.generics.clone();
-            adapter_generics.params.iter_mut().for_each(|g| {
-                if let GenericParam::Type(ref mut t) = g {
-                    t.default = None;
-                    t.bounds = Default::default();
-                }
-            });
+        quote!(pub #field_name: <#field_ty as ::orga::client::Client<#adapter_name#adapter_generics>>::Client)
+    });
 
-            quote!(#field_name: self.#field_name.clone())
-        });
+    let field_clones = field_adapters.iter().map(|(field, _)| {
+        let field_name = field.client_name();
+        quote!(#field_name: self.#field_name.clone())
+    });
 
-    let field_constructors = field_adapters
-        .iter()
-        .enumerate()
-        .map(|(i, (field, adapter))| {
-            let field_name = field.ident.as_ref().map_or_else(
-                || {
-                    let i = Literal::usize_unsuffixed(i);
-                    quote!(#i)
-                },
-                |f| quote!(#f),
-            );
-            let field_ty = &field.ty;
-            let field_ty = if let Type::Path(ref ty) = field_ty {
-                let mut without_params = ty.clone();
-                let params = without_params
-                    .path
-                    .segments
-                    .last()
-                    .unwrap()
-                    .arguments
-                    .clone();
-                if let PathArguments::AngleBracketed(ref params) = params {
-                    without_params.path.segments.last_mut().unwrap().arguments =
-                        PathArguments::None;
-                    quote!(#without_params::#params)
-                } else {
-                    quote!(#field_ty)
-                }
+    let field_constructors = field_adapters.iter().map(|(field, adapter)| {
+        let field_name = field.client_name();
+        let field_ty = &field.field.ty;
+        let field_ty = if let Type::Path(ref ty) = field_ty {
+            let mut without_params = ty.clone();
+            let params = without_params
+                .path
+                .segments
+                .last()
+                .unwrap()
+                .arguments
+                .clone();
+            if let PathArguments::AngleBracketed(ref params) = params {
+                without_params.path.segments.last_mut().unwrap().arguments = PathArguments::None;
+                quote!(#without_params::#params)
             } else {
                 quote!(#field_ty)
-            };
-
-            let adapter_name = &adapter.ident;
-
-            quote!(#field_name: #field_ty::create_client(#adapter_name::new(parent.clone())))
-        });
-
-    let call_method_impls_and_adapters =
-        relevant_methods(name, "call", source)
-            .into_iter()
-            .map(|(method, impl_item)| {
-                let method_name = &method.sig.ident;
-
-                let method_inputs = &method.sig.inputs;
-                let arg_types: Vec<_> = method_inputs
-                    .iter()
-                    .filter_map(|arg| match arg {
-                        FnArg::Typed(arg) => Some(arg.ty.clone()),
-                        _ => None,
-                    })
-                    .map(|ty| quote!(#ty))
-                    .collect();
-                let unrolled_args: Vec<_> = (0..arg_types.len()).map(|i| {
-                    let i = Literal::usize_unsuffixed(i);
-                    quote!(cloned_args.#i)
-                }).collect();
-                let method_input_names = method_inputs
-                    .iter()
-                    .filter_map(|arg| match arg {
-                        FnArg::Typed(arg) => Some(arg.pat.clone()),
-                        _ => None,
-                    })
-                    .map(|pat| quote!(#pat));
-
-                let adapter_name = Ident::new(
-                    format!("Method{}Adapter", method_name.to_string().to_camel_case()).as_str(),
-                    Span::call_site(),
-                );
-                let call_variant_name = Ident::new(
-                    format!("Method{}", method_name.to_string().to_camel_case()).as_str(),
-                    Span::call_site(),
-                );
-
-                let output_ty = match method.sig.output.clone() {
-                    ReturnType::Default => quote!(()),
-                    ReturnType::Type(_, mut ty) => {
-                        add_static_lifetimes(&mut ty);
-                        quote!(#ty)
-                    },
-                };
-                let method_output = quote!(
-                    ::orga::client::CallChain<
-                        <#output_ty as ::orga::client::Client<#adapter_name<#generic_params #output_ty, #parent_ty>>>::Client,
-                        #adapter_name<#generic_params #output_ty, #parent_ty>,
-                    >
-                );
-
-                let impl_preds = impl_item
-                    .generics
-                    .where_clause
-                    .as_ref()
-                    .map(|w| &w.predicates);
-
-                let source = parse_parent();
-                let call_enum = super::call::create_call_enum(&item, &source).1;
-                let call_impl = super::call::create_call_impl(&item, &source, &call_enum).1;
-                let call_preds = call_impl.generics.where_clause.as_ref().map(|w| &w.predicates);
-
-                quote! {
-                    pub struct #adapter_name#generics_with_return_and_parent
-                    where
-                        #parent_ty: Clone + Send,
-                        #parent_ty: ::orga::client::AsyncCall<Call = <#name#generic_params_bracketed as ::orga::call::Call>::Call>,
-                    {
-                        pub(super) parent: #parent_ty,
-                        args: (#(#arg_types,)*),
-                        _marker: std::marker::PhantomData<(#name#generic_params_bracketed, __Return)>,
-                    }
-
-                    unsafe impl#generics_sanitized_with_return Send for #adapter_name<#generic_params __Return, #parent_ty>
-                    where
-                        #parent_ty: Clone + Send,
-                        #parent_ty: ::orga::client::AsyncCall<Call = <#name#generic_params_bracketed as ::orga::call::Call>::Call>,
-                        #call_preds
-                    {}
-
-                    impl#generics_sanitized_with_return Clone for #adapter_name<#generic_params __Return, #parent_ty>
-                    where
-                        #parent_ty: Clone + Send,
-                        #parent_ty: ::orga::client::AsyncCall<Call = <#name#generic_params_bracketed as ::orga::call::Call>::Call>,
-                        #call_preds
-                    {
-                        fn clone(&self) -> Self {
-                            let encoded_args = ::orga::encoding::Encode::encode(&self.args).unwrap();
-                            let cloned_args = ::orga::encoding::Decode::decode(encoded_args.as_slice()).unwrap();
-                            Self {
-                                parent: self.parent.clone(),
-                                args: cloned_args,
-                                _marker: std::marker::PhantomData,
-                            }
-                        }
-                    }
+            }
+        } else {
+            quote!(#field_ty)
+        };
 
-                    #[::orga::async_trait]
-                    impl#generics_sanitized_with_return ::orga::client::AsyncCall for #adapter_name<#generic_params __Return, #parent_ty>
-                    where
-                        #parent_ty: Clone + Send,
-                        #parent_ty: ::orga::client::AsyncCall<Call = <#name#generic_params_bracketed as ::orga::call::Call>::Call>,
-                        __Return: ::orga::call::Call,
-                        __Return::Call: Send + Sync,
-                        #call_preds
-                    {
-                        type Call = <__Return as ::orga::call::Call>::Call;
-
-                        async fn call(&mut self, call: Self::Call) -> ::orga::Result<()> {
-                            let encoded_args = ::orga::encoding::Encode::encode(&self.args).unwrap();
-                            let cloned_args: (
-                                #(#arg_types,)*
-                            ) = ::orga::encoding::Decode::decode(encoded_args.as_slice()).unwrap();
-                            let call_bytes = ::orga::encoding::Encode::encode(&call)?;
-                            let parent_call = <#name#generic_params_bracketed as ::orga::call::Call>::Call::#call_variant_name(
-                                #(#unrolled_args,)*
-                                call_bytes
-                            );
-                            self.parent.call(parent_call).await
-                        }
-                    }
+        let adapter_name = &adapter.ident;
 
-                    impl#generics_sanitized Client<#generic_params #parent_ty>
-                    where
-                        #parent_ty: Clone + Send,
-                        #parent_ty: ::orga::client::AsyncCall<Call = <#name#generic_params_bracketed as ::orga::call::Call>::Call>,
-                        #where_preds
-                        #impl_preds
-                        #call_preds
-                    {
-                        pub fn #method_name(#method_inputs) -> #method_output {
-                            let adapter = #adapter_name {
-                                parent: self.parent.clone(),
-                                args: (#(#method_input_names,)*),
-                                _marker: std::marker::PhantomData,
-                            };
-                            let client = <#output_ty as ::orga::client::Client<#adapter_name<#generic_params _, #parent_ty>>>::create_client(adapter.clone());
-                            ::orga::client::CallChain::new(client, adapter)
-                        }
-                    }
-                }
-            });
+        quote!(#field_name: #field_ty::create_client(#adapter_name::new(parent.clone())))
+    });
 
     quote! {
         #[must_use]
@@ -373,15 +184,13 @@ fn create_client_struct(
             }
         }
 
-        #(#call_method_impls_and_adapters)*
     }
 }
 
-fn create_field_adapters(item: &DeriveInput) -> (TokenStream2, Vec<(&Field, ItemStruct)>) {
-    let fields: Vec<_> = struct_fields(&item)
-        .filter(|field| matches!(field.vis, Visibility::Public(_)))
-        .collect();
-
+fn create_field_adapters<'a>(
+    item: &DeriveInput,
+    fields: Vec<RoutedField<'a>>,
+) -> (TokenStream2, Vec<(RoutedField<'a>, ItemStruct)>) {
     let item_name = &item.ident;
     let item_generics = &item.generics;
 
@@ -416,29 +225,12 @@ fn create_field_adapters(item: &DeriveInput) -> (TokenStream2, Vec<(&Field, Item
 
     let adapters: Vec<_> = fields
         .iter()
-        .enumerate()
-        .map(|(i, field)| {
-            let struct_name = field.ident.as_ref().map_or(
-                Ident::new(format!("Field{}Adapter", i).as_str(), Span::call_site()),
-                |f| {
-                    Ident::new(
-                        format!("Field{}Adapter", f.to_string().to_camel_case()).as_str(),
-                        Span::call_site(),
-                    )
-                },
-            );
-            let variant_name = field.ident.as_ref().map_or(
-                Ident::new(format!("Field{}", i).as_str(), Span::call_site()),
-                |f| {
-                    Ident::new(
-                        format!("Field{}", f.to_string().to_camel_case()).as_str(),
-                        Span::call_site(),
-                    )
-                },
-            );
-            let field_ty = &field.ty;
+        .map(|field| {
+            let struct_name = field.adapter_name();
+            let variant_name = field.variant_name();
+            let field_ty = &field.field.ty;
             let parent_client_ty: GenericParam = syn::parse2(quote!(__Parent)).unwrap();
-        
+
             let struct_def = quote! {
                 #[derive(Clone)]
                 pub struct #struct_name#generics_with_parent
@@ -448,7 +240,7 @@ fn create_field_adapters(item: &DeriveInput) -> (TokenStream2, Vec<(&Field, Item
                     pub(super) parent: #parent_client_ty,
                 }
             };
-        
+
             let output = quote! {
                 #struct_def
                 impl#generics_sanitized #struct_name#generic_params_bracketed_with_parent
@@ -459,7 +251,7 @@ fn create_field_adapters(item: &DeriveInput) -> (TokenStream2, Vec<(&Field, Item
                         Self { parent }
                     }
                 }
-        
+
                 #[::orga::async_trait]
                 impl#generics_sanitized ::orga::client::AsyncCall for #struct_name#generic_params_bracketed_with_parent
                 where
@@ -467,7 +259,7 @@ fn create_field_adapters(item: &DeriveInput) -> (TokenStream2, Vec<(&Field, Item
                     #parent_client_ty: ::orga::client::AsyncCall<Call = <#item_ty as ::orga::call::Call>::Call>,
                 {
                     type Call = <#field_ty as ::orga::call::Call>::Call;
-        
+
                     async fn call(&mut self, call: Self::Call) -> ::orga::Result<()> {
                         // assumes that the call has a tuple variant called "Field" +
                         // the camel-cased name as the field
@@ -477,14 +269,13 @@ fn create_field_adapters(item: &DeriveInput) -> (TokenStream2, Vec<(&Field, Item
                     }
                 }
             };
-        
+
             (output, struct_def)
         })
         .collect();
     let adapter_outputs = adapters.clone().into_iter().map(|a| a.0);
     let adapter_items: Vec<_> = fields
-        .iter()
-        .map(|f| *f)
+        .into_iter()
         .zip(adapters.into_iter().map(|a| syn::parse2(a.1).unwrap()))
         .collect();
 
@@ -518,20 +309,6 @@ fn gen_param_input(generics: &Generics, bracketed: bool) -> TokenStream2 {
     }
 }
 
-fn struct_fields(item: &DeriveInput) -> impl Iterator<Item = &Field> {
-    let data = match item.data {
-        Data::Struct(ref data) => data,
-        Data::Enum(ref _data) => todo!("#[derive(Client)] does not yet support enums"),
-        Data::Union(_) => panic!("Unions are not supported"),
-    };
-
-    match data.fields {
-        Fields::Named(ref fields) => fields.named.iter(),
-        Fields::Unnamed(ref fields) => fields.unnamed.iter(),
-        Fields::Unit => panic!("Unit structs are not supported"),
-    }
-}
-
 fn add_static_lifetimes(ty: &mut Type) {
     match ty {
         Type::Path(path) => {
@@ -552,3 +329,164 @@ fn add_static_lifetimes(ty: &mut Type) {
         _ => {}
     }
 }
+
+/// Generates client methods and their call adapters for the `#[call]` methods
+/// of an `#[orga::methods(client)]` impl block. The methods are added to the
+/// client struct generated by `#[derive(Client)]`, and the output is meant to
+/// be placed in the type's methods module.
+pub(super) fn create_method_client(item: &ItemImpl, methods: &[Method]) -> Result<TokenStream2> {
+    let name = impl_type_name(item)?;
+    let self_ty = &item.self_ty;
+    let client_modname = Ident::new(
+        format!("{}_client", name).to_snake_case().as_str(),
+        Span::call_site(),
+    );
+    let type_args = impl_type_args(item);
+
+    let parent_ty: GenericParam = syn::parse2(quote!(__Parent)).unwrap();
+    let return_ty: GenericParam = syn::parse2(quote!(__Return)).unwrap();
+
+    let impl_params: Vec<_> = item.generics.params.iter().collect();
+    let impl_param_names = gen_param_input(&item.generics, false);
+    let where_preds = where_preds(&item.generics);
+    let encoding_bounds = method_encoding_bounds(item, methods);
+    let encoding_bounds = quote!(#(#encoding_bounds)*);
+    let parent_preds = quote! {
+        #parent_ty: Clone + Send,
+        #parent_ty: ::orga::client::AsyncCall<Call = <#self_ty as ::orga::call::Call>::Call>,
+    };
+
+    let method_impls_and_adapters = methods.iter().map(|method| {
+        let method_name = method.client_name();
+        let receiver = method.sig.receiver();
+
+        let input_names: Vec<_> = method
+            .typed_inputs()
+            .enumerate()
+            .map(|(i, input)| match *input.pat {
+                Pat::Ident(ref pat) => pat.ident.clone(),
+                _ => Ident::new(format!("var{}", i).as_str(), Span::call_site()),
+            })
+            .collect();
+        let input_tys: Vec<_> = method.typed_inputs().map(|input| &input.ty).collect();
+        let method_inputs = quote!(#receiver #(, #input_names: #input_tys)*);
+
+        let arg_types = method.input_types();
+        let unrolled_args: Vec<_> = (0..arg_types.len())
+            .map(|i| {
+                let i = Literal::usize_unsuffixed(i);
+                quote!(cloned_args.#i)
+            })
+            .collect();
+        let method_input_names = method
+            .typed_inputs()
+            .zip(input_names.iter())
+            .map(|(input, name)| match *input.ty {
+                Type::Reference(_) => quote!(::std::borrow::ToOwned::to_owned(&*#name)),
+                _ => quote!(#name),
+            });
+
+        let variant_name = method.variant_name();
+        let adapter_name = Ident::new(format!("{}Adapter", variant_name).as_str(), Span::call_site());
+
+        let output_ty = match method.sig.output.clone() {
+            ReturnType::Default => quote!(()),
+            ReturnType::Type(_, mut ty) => {
+                add_static_lifetimes(&mut ty);
+                quote!(#ty)
+            }
+        };
+        let adapter_ty = quote!(#adapter_name<#impl_param_names #output_ty, #parent_ty>);
+        let method_output = quote!(
+            ::orga::client::CallChain<
+                <#output_ty as ::orga::client::Client<#adapter_ty>>::Client,
+                #adapter_ty,
+            >
+        );
+
+        let method_where_preds = method.where_preds();
+
+        quote! {
+            pub struct #adapter_name<#(#impl_params,)* #return_ty, #parent_ty>
+            where
+                #parent_preds
+                #where_preds
+            {
+                pub(super) parent: #parent_ty,
+                args: (#(#arg_types,)*),
+                _marker: std::marker::PhantomData<(#self_ty, #return_ty)>,
+            }
+
+            unsafe impl<#(#impl_params,)* #return_ty, #parent_ty> Send for #adapter_name<#impl_param_names #return_ty, #parent_ty>
+            where
+                #parent_preds
+                #where_preds
+                #encoding_bounds
+            {}
+
+            impl<#(#impl_params,)* #return_ty, #parent_ty> Clone for #adapter_name<#impl_param_names #return_ty, #parent_ty>
+            where
+                #parent_preds
+                #where_preds
+                #encoding_bounds
+            {
+                fn clone(&self) -> Self {
+                    let encoded_args = ::orga::encoding::Encode::encode(&self.args).unwrap();
+                    let cloned_args = ::orga::encoding::Decode::decode(encoded_args.as_slice()).unwrap();
+                    Self {
+                        parent: self.parent.clone(),
+                        args: cloned_args,
+                        _marker: std::marker::PhantomData,
+                    }
+                }
+            }
+
+            #[::orga::async_trait]
+            impl<#(#impl_params,)* #return_ty, #parent_ty> ::orga::client::AsyncCall for #adapter_name<#impl_param_names #return_ty, #parent_ty>
+            where
+                #parent_preds
+                #where_preds
+                #encoding_bounds
+                #return_ty: ::orga::call::Call,
+                <#return_ty as ::orga::call::Call>::Call: Send + Sync,
+            {
+                type Call = <#return_ty as ::orga::call::Call>::Call;
+
+                async fn call(&mut self, call: Self::Call) -> ::orga::Result<()> {
+                    let encoded_args = ::orga::encoding::Encode::encode(&self.args).unwrap();
+                    let cloned_args: (
+                        #(#arg_types,)*
+                    ) = ::orga::encoding::Decode::decode(encoded_args.as_slice()).unwrap();
+                    let call_bytes = ::orga::encoding::Encode::encode(&call)?;
+                    let method_call = <#self_ty as ::orga::call::MethodCall>::MethodCall::#variant_name(
+                        #(#unrolled_args,)*
+                        call_bytes
+                    );
+                    let method_call_bytes = ::orga::encoding::Encode::encode(&method_call)?;
+                    let parent_call = <#self_ty as ::orga::call::Call>::Call::Method(method_call_bytes);
+                    self.parent.call(parent_call).await
+                }
+            }
+
+            impl<#(#impl_params,)* #parent_ty> #client_modname::Client<#(#type_args,)* #parent_ty>
+            where
+                #parent_preds
+                #where_preds
+                #encoding_bounds
+                #method_where_preds
+            {
+                pub fn #method_name(#method_inputs) -> #method_output {
+                    let adapter = #adapter_name {
+                        parent: self.parent.clone(),
+                        args: (#(#method_input_names,)*),
+                        _marker: std::marker::PhantomData,
+                    };
+                    let client = <#output_ty as ::orga::client::Client<#adapter_name<#impl_param_names _, #parent_ty>>>::create_client(adapter.clone());
+                    ::orga::client::CallChain::new(client, adapter)
+                }
+            }
+        }
+    });
+
+    Ok(quote!(#(#method_impls_and_adapters)*))
+}
@@ -1,16 +1,15 @@
-#![feature(proc_macro_span)]
-
 use proc_macro::TokenStream;
 
 mod call;
 mod client;
 mod entry;
+mod methods;
 mod next;
 mod query;
 mod state;
 mod utils;
 
-#[proc_macro_derive(State)]
+#[proc_macro_derive(State, attributes(state))]
 pub fn derive_state(item: TokenStream) -> TokenStream {
     state::derive(item)
 }
@@ -20,7 +19,7 @@ pub fn derive_entry(item: TokenStream) -> TokenStream {
     entry::derive(item)
 }
 
-#[proc_macro_derive(Query)]
+#[proc_macro_derive(Query, attributes(query))]
 pub fn derive_query(item: TokenStream) -> TokenStream {
     query::derive(item)
 }
@@ -30,7 +29,7 @@ pub fn query(args: TokenStream, input: TokenStream) -> TokenStream {
     query::attr(args, input)
 }
 
-#[proc_macro_derive(Call)]
+#[proc_macro_derive(Call, attributes(call))]
 pub fn derive_call(item: TokenStream) -> TokenStream {
     call::derive(item)
 }
@@ -45,6 +44,11 @@ pub fn derive_client(item: TokenStream) -> TokenStream {
     client::derive(item)
 }
 
+#[proc_macro_attribute]
+pub fn methods(args: TokenStream, input: TokenStream) -> TokenStream {
+    methods::attr(args, input)
+}
+
 #[proc_macro_derive(Next)]
 pub fn derive_next(item: TokenStream) -> TokenStream {
     next::derive(item)
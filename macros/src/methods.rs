@@ -0,0 +1,95 @@
+use heck::SnakeCase;
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::*;
+
+use super::utils::*;
+
+pub fn attr(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as MethodsAttr);
+    let item = parse_macro_input!(input as ItemImpl);
+
+    match expand(&args, item) {
+        Ok(output) => output.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(args: &MethodsAttr, mut item: ItemImpl) -> Result<TokenStream2> {
+    if let Some((_, ref path, _)) = item.trait_ {
+        return Err(Error::new_spanned(
+            path,
+            "#[orga::methods] must be used on an inherent impl block",
+        ));
+    }
+
+    let name = impl_type_name(&item)?;
+    let modname = Ident::new(
+        format!("{}_methods", name).to_snake_case().as_str(),
+        Span::call_site(),
+    );
+
+    let call_methods = relevant_methods(&item, "call")?;
+    let query_methods = relevant_methods(&item, "query")?;
+
+    // the method attributes have been handled here, so they are removed
+    // rather than expanded on their own
+    for impl_item in item.items.iter_mut() {
+        if let ImplItem::Method(method) = impl_item {
+            method
+                .attrs
+                .retain(|attr| !attr.path.is_ident("call") && !attr.path.is_ident("query"));
+        }
+    }
+
+    let method_call = if call_methods.is_empty() {
+        quote!()
+    } else {
+        super::call::create_method_call(&item, &call_methods)
+    };
+    let method_query = if query_methods.is_empty() {
+        quote!()
+    } else {
+        super::query::create_method_query(&item, &query_methods)
+    };
+    let method_client = if args.client && !call_methods.is_empty() {
+        super::client::create_method_client(&item, &call_methods)?
+    } else {
+        quote!()
+    };
+
+    Ok(quote! {
+        #item
+
+        pub mod #modname {
+            use super::*;
+            #method_call
+            #method_query
+            #method_client
+        }
+    })
+}
+
+/// Arguments given to `#[orga::methods(...)]`. Passing `client` adds the call
+/// methods to the client generated by `#[derive(Client)]`.
+#[derive(Default)]
+struct MethodsAttr {
+    client: bool,
+}
+
+impl parse::Parse for MethodsAttr {
+    fn parse(input: parse::ParseStream) -> Result<Self> {
+        let mut attr = MethodsAttr::default();
+        let args = punctuated::Punctuated::<Ident, syn::Token![,]>::parse_terminated(input)?;
+        for arg in args {
+            if arg == "client" {
+                attr.client = true;
+            } else {
+                return Err(Error::new(arg.span(), "Expected `client`"));
+            }
+        }
+
+        Ok(attr)
+    }
+}
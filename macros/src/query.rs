@@ -1,13 +1,13 @@
-use heck::{CamelCase, SnakeCase};
+use heck::SnakeCase;
 use proc_macro::TokenStream;
-use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
-use std::collections::HashSet;
 use syn::*;
 
+use super::utils::*;
+
 pub fn derive(item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as DeriveInput);
-    let source = parse_parent();
 
     let name = &item.ident;
     let modname = Ident::new(
@@ -15,8 +15,13 @@ pub fn derive(item: TokenStream) -> TokenStream {
         Span::call_site(),
     );
 
-    let query_enum = create_query_enum(&item, &source);
-    let query_impl = create_query_impl(&item, &source, &query_enum);
+    let fields = match routed_fields(&item, "query") {
+        Ok(fields) => fields,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let query_enum = create_query_enum(&item, &fields);
+    let query_impl = create_query_impl(&item, &fields, &query_enum);
 
     let output = quote!(
         use ::orga::macros::*;
@@ -31,29 +36,23 @@ pub fn derive(item: TokenStream) -> TokenStream {
     output.into()
 }
 
-pub fn attr(_args: TokenStream, input: TokenStream) -> TokenStream {
+pub fn attr(args: TokenStream, input: TokenStream) -> TokenStream {
     let method = parse_macro_input!(input as ImplItemMethod);
 
-    if !matches!(method.vis, Visibility::Public(_)) {
-        panic!("Query methods must be public");
-    }
-
-    if method.sig.unsafety.is_some() {
-        panic!("Query methods cannot be unsafe");
-    }
-
-    if method.sig.asyncness.is_some() {
-        panic!("Query methods cannot be async");
-    }
-
-    if method.sig.abi.is_some() {
-        panic!("Query methods cannot specify ABI");
+    match validate_method(&method, args.into(), "Query") {
+        Ok(()) => quote!(#method).into(),
+        Err(err) => {
+            let err = err.to_compile_error();
+            quote!(#method #err).into()
+        }
     }
-
-    quote!(#method).into()
 }
 
-fn create_query_impl(item: &DeriveInput, source: &File, query_enum: &ItemEnum) -> TokenStream2 {
+fn create_query_impl(
+    item: &DeriveInput,
+    fields: &[RoutedField],
+    query_enum: &ItemEnum,
+) -> TokenStream2 {
     let name = &item.ident;
     let generics = &item.generics;
     let mut generics_sanitized = generics.clone();
@@ -66,122 +65,96 @@ fn create_query_impl(item: &DeriveInput, source: &File, query_enum: &ItemEnum) -
 
     let query_type = &query_enum.ident;
     let query_generics = &query_enum.generics;
-    let where_preds = item.generics.where_clause.as_ref().map(|w| &w.predicates);
-
-    let encoding_bounds = relevant_methods(name, "query", source)
-        .into_iter()
-        .flat_map(|(method, _)| {
-            let inputs: Vec<_> = method
-                .sig
-                .inputs
-                .iter()
-                .skip(1)
-                .map(|input| match input {
-                    FnArg::Typed(input) => *input.ty.clone(),
-                    _ => panic!("unexpected input"),
-                })
-                .collect();
+    let query_preds = where_preds(query_generics);
+    let where_preds = where_preds(&item.generics);
 
-            get_generic_requirements(
-                inputs.iter().cloned(),
-                item.generics.params.iter().cloned(),
-            )
-        })
-        .map(|p| quote!(#p: ::orga::encoding::Encode + ::orga::encoding::Decode + ::orga::encoding::Terminated,));
-    let encoding_bounds = quote!(#(#encoding_bounds)*);
+    let field_query_arms: Vec<_> = fields
+        .iter()
+        .map(|field| {
+            let variant_name = field.variant_name();
+            let binding = Ident::new("field", Span::call_site());
+            let query_field = field.with_field(
+                false,
+                &binding,
+                quote!(::orga::query::Query::query(#binding, subquery)),
+                quote!(::orga::Error::Query),
+            );
 
-    let query_bounds = relevant_methods(name, "query", source)
-        .into_iter()
-        .map(|(method, _)| {
-            let unit_tuple: Type = parse2(quote!(())).unwrap();
-            match method.sig.output {
-                ReturnType::Type(_, ref ty) => *(ty.clone()),
-                ReturnType::Default => unit_tuple,
+            quote! {
+                Query::#variant_name(subquery) => #query_field
             }
         })
+        .collect();
+
+    quote! {
+        impl#generics_sanitized ::orga::query::Query for #name#generic_params
+        where #where_preds #query_preds
+        {
+            type Query = #query_type#query_generics;
+
+            fn query(&self, query: Self::Query) -> ::orga::Result<()> {
+                match query {
+                    Query::This => Ok(()),
+                    #(#field_query_arms,)*
+                    Query::Method(query_bytes) => {
+                        ::orga::query::maybe_method_query(self, query_bytes)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Generates the method query enum and `MethodQuery` implementation for the
+/// `#[query]` methods of an `#[orga::methods]` impl block. The output is
+/// meant to be placed in the type's methods module.
+pub(super) fn create_method_query(item: &ItemImpl, methods: &[Method]) -> TokenStream2 {
+    let self_ty = &item.self_ty;
+    let impl_generics = &item.generics;
+    let where_preds = where_preds(&item.generics);
+
+    let encoding_bounds = method_encoding_bounds(item, methods);
+    let encoding_bounds = quote!(#(#encoding_bounds)*);
+
+    let query_bounds = methods
+        .iter()
+        .map(|method| method.output_type())
         .flat_map(|ty| {
             get_generic_requirements(
                 std::iter::once(&ty).cloned(),
-                generics.params.iter().cloned(),
+                impl_generics.params.iter().cloned(),
             )
         })
         .map(|t| quote!(#t: ::orga::query::Query,));
     let query_bounds = quote!(#(#query_bounds)*);
 
-    let fields = match &item.data {
-        Data::Struct(data) => data.fields.iter(),
-        Data::Enum(_) => todo!("Enums are not supported yet"),
-        Data::Union(_) => panic!("Unions are not supported"),
-    };
-    let field_query_arms: Vec<_> = fields
-        .filter(|field| matches!(field.vis, Visibility::Public(_)))
-        .enumerate()
-        .map(|(i, field)| {
-            let variant_name = field.ident.as_ref().map_or(
-                Ident::new(format!("Field{}", i).as_str(), Span::call_site()),
-                |f| {
-                    Ident::new(
-                        format!("Field{}", f.to_string().to_camel_case()).as_str(),
-                        Span::call_site(),
-                    )
-                },
-            );
-            let field_name = field.ident.as_ref().map_or_else(
-                || {
-                    let i = Literal::usize_unsuffixed(i);
-                    quote!(#i)
-                },
-                |f| quote!(#f),
-            );
-
-            quote! {
-                Query::#variant_name(subquery) => {
-                    ::orga::query::Query::query(&self.#field_name, subquery)
-                }
-            }
-        })
-        .collect();
-
-    let mut maybe_call_defs = vec![];
-    let method_query_arms: Vec<_> = relevant_methods(name, "query", source)
-        .into_iter()
-        .map(|(method, parent)| {
-            let method_name = &method.sig.ident;
-
-            let name_camel = method_name.to_string().to_camel_case();
-            let variant_name =
-                Ident::new(format!("Method{}", &name_camel).as_str(), Span::call_site());
+    let mut maybe_query_defs = vec![];
+    let method_query_arms: Vec<_> = methods
+        .iter()
+        .map(|method| {
+            let variant_name = method.variant_name();
 
-            let inputs: Vec<_> = (1..method.sig.inputs.len())
-                .into_iter()
+            let input_types = method.input_types();
+            let inputs: Vec<_> = (1..=input_types.len())
                 .map(|i| Ident::new(format!("var{}", i).as_str(), Span::call_site()))
                 .collect();
-            let input_types: Vec<_> = method
-                .sig
-                .inputs
-                .iter()
-                .skip(1)
-                .filter_map(|input| match input {
-                    FnArg::Typed(input) => Some(*input.ty.clone()),
-                    _ => None,
-                })
-                .collect();
+            let input_bindings = method.input_bindings(&inputs);
             let full_inputs = quote! {
                 #(, #inputs: #input_types)*
             };
-
-            let unit_tuple: Type = parse2(quote!(())).unwrap();
-            let output_type = match method.sig.output {
-                ReturnType::Type(_, ref ty) => *(ty.clone()),
-                ReturnType::Default => unit_tuple,
+            let full_input_bindings = quote! {
+                #(, #input_bindings: #input_types)*
             };
+            let invocation = method.invocation(quote!(self), &inputs);
+
+            let output_type = method.output_type();
 
             let requirements = get_generic_requirements(
                 input_types
                     .iter()
                     .chain(std::iter::once(&output_type))
                     .cloned(),
-                generics.params.iter().cloned(),
+                impl_generics.params.iter().cloned(),
             );
             let generic_reqs = if requirements.is_empty() {
                 quote!()
@@ -189,27 +162,26 @@ fn create_query_impl(item: &DeriveInput, source: &File, query_enum: &ItemEnum) -
                 quote!(<#(#requirements),*>)
             };
 
-            let parent_generics = &parent.generics;
-            let parent_where_preds = &parent.generics.where_clause.as_ref().map(|w| &w.predicates);
+            let method_where_preds = method.where_preds();
 
             let trait_name = Ident::new(
-                format!("MaybeCall{}", &variant_name).as_str(),
+                format!("MaybeQuery{}", &variant_name).as_str(),
                 Span::call_site(),
             );
-            maybe_call_defs.push(quote! {
+            maybe_query_defs.push(quote! {
                 trait #trait_name#generic_reqs {
-                    fn maybe_call(&self #full_inputs) -> ::orga::Result<#output_type>;
+                    fn maybe_query(&self #full_inputs) -> ::orga::Result<#output_type>;
                 }
                 impl<__Self, #(#requirements),*> #trait_name#generic_reqs for __Self {
-                    default fn maybe_call(&self #full_inputs) -> ::orga::Result<#output_type> {
+                    default fn maybe_query(&self #full_inputs) -> ::orga::Result<#output_type> {
                         Err(::orga::Error::Query("This query cannot be called because not all bounds are met".into()))
                     }
                 }
-                impl#parent_generics #trait_name#generic_reqs for #name#generic_params
-                where #where_preds #encoding_bounds #query_bounds #parent_where_preds
+                impl#impl_generics #trait_name#generic_reqs for #self_ty
+                where #where_preds #encoding_bounds #query_bounds #method_where_preds
                 {
-                    fn maybe_call(&self #full_inputs) -> ::orga::Result<#output_type> {
-                        Ok(self.#method_name(#(#inputs),*))
+                    fn maybe_query(&self #full_input_bindings) -> ::orga::Result<#output_type> {
+                        Ok(#invocation)
                     }
                 }
             });
@@ -221,115 +193,87 @@ fn create_query_impl(item: &DeriveInput, source: &File, query_enum: &ItemEnum) -
             };
 
             quote! {
-                Query::#variant_name(#(#inputs,)* subquery) => {
-                    let subquery = ::orga::encoding::Decode::decode(subquery.as_slice())?;
-                    ::orga::query::Query::query(
-                        &#trait_name#dotted_generic_reqs::maybe_call(self, #(#inputs),*),
-                        subquery,
-                    )
+                Query::#variant_name(#(#inputs,)* subquery_bytes) => {
+                    let subquery = ::orga::encoding::Decode::decode(subquery_bytes.as_slice())?;
+                    let value = #trait_name#dotted_generic_reqs::maybe_query(self, #(#inputs),*);
+                    if subquery_bytes.is_empty() {
+                        ::orga::query::record_value(&value);
+                    }
+                    ::orga::query::Query::query(&value, subquery)
                 }
             }
         })
         .collect();
 
+    let method_variants: Vec<_> = methods
+        .iter()
+        .map(|method| {
+            let name = method.variant_name();
+            let inputs = method.input_types();
+
+            quote! {
+                #name(#(#inputs,)* Vec<u8>)
+            }
+        })
+        .collect();
+
+    let enum_params = method_enum_params(item, methods);
+
     quote! {
-        impl#generics_sanitized ::orga::query::Query for #name#generic_params
+        #[derive(::orga::encoding::Encode, ::orga::encoding::Decode)]
+        pub enum Query#enum_params {
+            #(#method_variants,)*
+        }
+
+        impl#impl_generics ::orga::query::MethodQuery for #self_ty
         where #where_preds #encoding_bounds #query_bounds
         {
-            type Query = #query_type#query_generics;
+            type MethodQuery = Query#enum_params;
 
-            fn query(&self, query: Self::Query) -> ::orga::Result<()> {
+            fn method_query(&self, query: Self::MethodQuery) -> ::orga::Result<()> {
                 match query {
-                    Query::This => Ok(()),
-                    #(#field_query_arms),*
                     #(#method_query_arms),*
                 }
             }
         }
-        #(#maybe_call_defs)*
+
+        #(#maybe_query_defs)*
     }
 }
 
-fn create_query_enum(item: &DeriveInput, source: &File) -> ItemEnum {
-    let name = &item.ident;
+fn create_query_enum(item: &DeriveInput, fields: &[RoutedField]) -> ItemEnum {
     let generics = &item.generics;
 
     let mut generic_params = vec![];
     let mut query_params = vec![];
 
-    let fields = match &item.data {
-        Data::Struct(data) => data.fields.iter(),
-        Data::Enum(_) => todo!("Enums are not supported yet"),
-        Data::Union(_) => panic!("Unions are not supported"),
-    };
     let field_variants: Vec<_> = fields
-        .filter(|field| matches!(field.vis, Visibility::Public(_)))
-        .enumerate()
-        .map(|(i, field)| {
-            let name = field.ident.as_ref().map_or(
-                Ident::new(format!("Field{}", i).as_str(), Span::call_site()),
-                |f| {
-                    Ident::new(
-                        format!("Field{}", f.to_string().to_camel_case()).as_str(),
-                        Span::call_site(),
-                    )
-                },
-            );
+        .iter()
+        .map(|field| {
+            let name = field.variant_name();
 
             let requirements = get_generic_requirements(
-                vec![field.ty.clone()].into_iter(),
+                vec![field.field.ty.clone()].into_iter(),
                 generics.params.iter().cloned(),
             );
             generic_params.extend(requirements.clone());
             query_params.extend(requirements);
 
-            let ty = &field.ty;
+            let ty = &field.field.ty;
 
             quote!(#name(<#ty as ::orga::query::Query>::Query))
         })
         .collect();
 
-    let method_variants: Vec<_> = relevant_methods(name, "query", source)
-        .into_iter()
-        .map(|(method, _)| {
-            let name_camel = method.sig.ident.to_string().to_camel_case();
-            let name = Ident::new(format!("Method{}", &name_camel).as_str(), Span::call_site());
-
-            let fields = if method.sig.inputs.len() == 1 {
-                quote!()
-            } else {
-                let inputs: Vec<_> = method
-                    .sig
-                    .inputs
-                    .iter()
-                    .skip(1)
-                    .map(|input| match input {
-                        FnArg::Typed(input) => *input.ty.clone(),
-                        _ => panic!("unexpected input"),
-                    })
-                    .collect();
-
-                let requirements = get_generic_requirements(
-                    inputs.iter().cloned(),
-                    generics.params.iter().cloned(),
-                );
-                generic_params.extend(requirements);
-
-                quote! { #(#inputs),*, }
-            };
-
-            quote! {
-                #name(#fields Vec<u8>)
-            }
-        })
+    let generic_params: Vec<_> = generics
+        .type_params()
+        .map(|p| &p.ident)
+        .filter(|ident| generic_params.contains(*ident))
         .collect();
-
     let generic_params = if generic_params.is_empty() {
         quote!()
     } else {
-        let params: HashSet<_> = generic_params.into_iter().collect();
-        let params = params.into_iter();
-        quote!(<#(#params),*>)
+        quote!(<#(#generic_params),*>)
     };
 
     let query_preds = quote!(#(#query_params: ::orga::query::Query),*);
@@ -341,156 +285,9 @@ fn create_query_enum(item: &DeriveInput, source: &File) -> ItemEnum {
         {
             This,
             #(#field_variants,)*
-            #(#method_variants,)*
+            Method(Vec<u8>),
         }
     };
 
     syn::parse2(output).unwrap()
 }
-
-fn parse_parent() -> File {
-    let path = proc_macro::Span::call_site().source_file().path();
-    let source = std::fs::read_to_string(path).unwrap();
-    parse_file(source.as_str()).unwrap()
-}
-
-fn get_generic_requirements<I, J>(inputs: I, params: J) -> Vec<Ident>
-where
-    I: Iterator<Item = Type>,
-    J: Iterator<Item = GenericParam>,
-{
-    let params = params.collect::<Vec<_>>();
-    let maybe_generic_inputs = inputs
-        .filter_map(|input| match input {
-            Type::Path(path) => Some(path),
-            Type::Reference(reference) => match *reference.elem {
-                Type::Path(path) => Some(path),
-                _ => None,
-            },
-            _ => None,
-        })
-        .flat_map(|path| {
-            let mut paths = vec![];
-            fn add_arguments(path: &TypePath, paths: &mut Vec<PathSegment>) {
-                let last = path.path.segments.last().unwrap();
-                paths.push(last.clone());
-                if let PathArguments::AngleBracketed(ref args) = last.arguments {
-                    for arg in args.args.iter() {
-                        if let GenericArgument::Type(ty) = arg {
-                            let maybe_path = match ty {
-                                Type::Path(path) => Some(path),
-                                Type::Reference(reference) => match *reference.elem {
-                                    Type::Path(ref path) => Some(path),
-                                    _ => None,
-                                },
-                                _ => None,
-                            };
-                            maybe_path.map(|path| add_arguments(path, paths));
-                        }
-                    }
-                }
-            }
-            add_arguments(&path, &mut paths);
-
-            paths
-        });
-    let mut requirements = vec![];
-    for input in maybe_generic_inputs {
-        params
-            .iter()
-            .filter_map(|param| match param {
-                GenericParam::Type(param) => Some(param),
-                _ => None,
-            })
-            .find(|param| param.ident == input.ident)
-            .map(|param| {
-                requirements.push(param.ident.clone());
-            });
-    }
-    requirements
-}
-
-fn relevant_impls(name: &Ident, source: &File) -> Vec<ItemImpl> {
-    source
-        .items
-        .iter()
-        .filter_map(|item| match item {
-            Item::Impl(item) => Some(item),
-            _ => None,
-        })
-        .filter(|item| item.trait_.is_none())
-        .filter(|item| {
-            let path = match &*item.self_ty {
-                Type::Path(path) => path,
-                _ => return false,
-            };
-
-            if path.qself.is_some() {
-                return false;
-            }
-            if path.path.segments.len() != 1 {
-                return false;
-            }
-            if path.path.segments[0].ident != *name {
-                return false;
-            }
-
-            true
-        })
-        .cloned()
-        .collect()
-}
-
-fn relevant_methods(name: &Ident, attr: &str, source: &File) -> Vec<(ImplItemMethod, ItemImpl)> {
-    let get_methods = |item: ItemImpl| -> Vec<_> {
-        item.items
-            .iter()
-            .filter_map(|item| match item {
-                ImplItem::Method(method) => Some(method),
-                _ => None,
-            })
-            .filter(|method| {
-                method
-                    .attrs
-                    .iter()
-                    .find(|a| a.path.is_ident(&attr))
-                    .is_some()
-            })
-            .filter(|method| matches!(method.vis, Visibility::Public(_)))
-            .filter(|method| method.sig.unsafety.is_none())
-            .filter(|method| method.sig.asyncness.is_none())
-            .filter(|method| method.sig.abi.is_none())
-            .map(|method| (method.clone(), item.clone()))
-            .collect()
-    };
-
-    relevant_impls(name, source)
-        .into_iter()
-        .flat_map(get_methods)
-        .collect()
-}
-
-fn gen_param_input(generics: &Generics, bracketed: bool) -> TokenStream2 {
-    let gen_params = generics.params.iter().map(|p| match p {
-        GenericParam::Type(p) => {
-            let ident = &p.ident;
-            quote!(#ident)
-        }
-        GenericParam::Lifetime(p) => {
-            let ident = &p.lifetime.ident;
-            quote!(#ident)
-        }
-        GenericParam::Const(p) => {
-            let ident = &p.ident;
-            quote!(#ident)
-        }
-    });
-
-    if gen_params.len() == 0 {
-        quote!()
-    } else if bracketed {
-        quote!(<#(#gen_params),*>)
-    } else {
-        quote!(#(#gen_params),*)
-    }
-}
@@ -1,135 +1,531 @@
 use super::utils::gen_param_input;
+use heck::SnakeCase;
 use proc_macro::TokenStream;
-use proc_macro2::TokenStream as TokenStream2;
+use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
 use quote::quote;
-use std::str::FromStr;
 use syn::*;
 
 pub fn derive(item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as DeriveInput);
 
-    let mut is_tuple_struct = false;
-    match &item.data {
-        Data::Struct(data) => {
-            if let Fields::Unnamed(_) = data.fields {
-                is_tuple_struct = true
-            }
-        }
-        _ => todo!("Currently only structs are supported"),
+    let output = match &item.data {
+        Data::Struct(data) => derive_struct(&item, data),
+        Data::Enum(data) => derive_enum(&item, data),
+        Data::Union(_) => panic!("Unions are not supported"),
+    };
+
+    match output {
+        Ok(output) => output.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn derive_struct(item: &DeriveInput, data: &DataStruct) -> Result<TokenStream2> {
+    if let Fields::Unit = data.fields {
+        panic!("Unit structs are not supported");
     }
 
-    let field_names = || struct_fields(&item).map(|field| &field.ident);
-    let field_types = || struct_fields(&item).map(|field| &field.ty);
-    let seq =
-        || (0..field_names().count()).map(|i| TokenStream2::from_str(&i.to_string()).unwrap());
+    let attr = StateAttr::parse(&item.attrs)?;
+    let fields = state_fields(&data.fields, &[])?;
 
     let name = &item.ident;
     let generics = &item.generics;
     let where_clause = &generics.where_clause;
-    let generic_params = gen_param_input(&generics, true);
+    let generic_params = gen_param_input(generics, true);
+
+    let persisted: Vec<_> = fields.iter().filter(|field| !field.skip).collect();
+    let skipped: Vec<_> = fields.iter().filter(|field| field.skip).collect();
+
+    let version = attr.version_encoding();
+    let version_type = attr.version_type();
+    let offset = if attr.version.is_some() { 1 } else { 0 };
+    let seq = || (0..persisted.len()).map(|i| Literal::usize_unsuffixed(i + offset));
+
+    let field_types = || persisted.iter().map(|field| &field.field.ty);
+    let members = || persisted.iter().map(|field| &field.member);
+    let prefixes = || persisted.iter().map(|field| field.prefix_tokens());
+    let skipped_members = skipped.iter().map(|field| &field.member);
 
     let field_types_encoding = field_types();
-    let seq_substore = seq();
-    let seq_data = seq();
+    let members_create = members();
+    let prefixes_create = prefixes();
+    let seq_create = seq();
+    let members_flush = members();
+    let members_from = members();
+    let field_types_prune = field_types();
+    let prefixes_prune = prefixes();
+    let seq_prune = seq();
 
-    let create_body = if is_tuple_struct {
-        quote!(
-            Ok(Self(
+    Ok(quote! {
+        impl#generics ::orga::state::State for #name#generic_params
+        #where_clause
+        {
+            type Encoding = (
+                #version_type
                 #(
-                    ::orga::state::State::create(
-                        store.sub(&[#seq_substore]),
-                        data.#seq_data,
-                    )?,
+                    <#field_types_encoding as ::orga::state::State>::Encoding,
                 )*
-            ))
-        )
-    } else {
-        let names = field_names();
-        quote!(
-            Ok(Self {
+            );
+
+            fn create(
+                store: ::orga::store::Store,
+                data: Self::Encoding,
+            ) -> ::orga::Result<Self> {
+                Ok(Self {
+                    #(
+                        #members_create: ::orga::state::State::create(
+                            store.sub(#prefixes_create),
+                            data.#seq_create,
+                        )?,
+                    )*
+                    #(#skipped_members: Default::default(),)*
+                })
+            }
+
+            fn flush(self) -> ::orga::Result<Self::Encoding> {
+                Ok((
+                    #version
+                    #(::orga::state::State::<::orga::store::DefaultBackingStore>::flush(self.#members_flush)?,)*
+                ))
+            }
+
+            fn prune(
+                store: ::orga::store::Store,
+                data: &Self::Encoding,
+            ) -> ::orga::Result<()> {
                 #(
-                    #names: ::orga::state::State::create(
-                        store.sub(&[#seq_substore]),
-                        data.#seq_data,
-                    )?,
+                    <#field_types_prune as ::orga::state::State>::prune(
+                        store.sub(#prefixes_prune),
+                        &data.#seq_prune,
+                    )?;
                 )*
-            })
-        )
+                Ok(())
+            }
+        }
+
+        impl#generics From<#name#generic_params> for <#name#generic_params as ::orga::state::State>::Encoding
+        #where_clause
+        {
+            fn from(value: #name#generic_params) -> Self {
+                (
+                    #version
+                    #(value.#members_from.into(),)*
+                )
+            }
+        }
+    })
+}
+
+/// Derives `State` for an enum. The enum is encoded as a generated
+/// `Encoding` enum (in a `{name}_state` module) with one variant per variant of
+/// the original enum, each holding its fields' encodings. Each variant's fields
+/// get their own substore, prefixed by the variant index then the field's
+/// prefix. `prune` records the index of the variant whose fields were written
+/// at the key `[255]` of the enum's store (which no variant prefix can reach),
+/// and when the variant changes, clears the substore of the previously recorded
+/// variant.
+fn derive_enum(item: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
+    let name = &item.ident;
+    let modname = Ident::new(
+        format!("{}_state", name).to_snake_case().as_str(),
+        Span::call_site(),
+    );
+
+    let attr = StateAttr::parse(&item.attrs)?;
+
+    let generics = &item.generics;
+    let mut generics_sanitized = generics.clone();
+    generics_sanitized.params.iter_mut().for_each(|g| {
+        if let GenericParam::Type(ref mut t) = g {
+            t.default = None;
+        }
+    });
+    let where_clause = &generics.where_clause;
+    let generic_params = gen_param_input(generics, true);
+
+    if data.variants.len() > u8::MAX as usize {
+        return Err(Error::new_spanned(
+            name,
+            "#[derive(State)] supports at most 255 variants",
+        ));
+    }
+
+    let variants = data
+        .variants
+        .iter()
+        .enumerate()
+        .map(|(v, variant)| Ok((variant, state_fields(&variant.fields, &[v as u8])?)))
+        .collect::<Result<Vec<_>>>()?;
+    let variant_count = Literal::u8_unsuffixed(variants.len() as u8);
+
+    fn persisted<'a>(fields: &[StateField<'a>]) -> Vec<StateField<'a>> {
+        fields.iter().filter(|field| !field.skip).cloned().collect()
+    }
+    let indexes = |fields: &[StateField]| -> Vec<Literal> {
+        (0..fields.len()).map(Literal::usize_unsuffixed).collect()
+    };
+    let bindings = |fields: &[StateField], prefix: &str| -> Vec<Ident> {
+        (0..fields.len())
+            .map(|i| Ident::new(format!("{}{}", prefix, i).as_str(), Span::call_site()))
+            .collect()
     };
 
-    let flush_body = if is_tuple_struct {
-        let indexes = seq();
-        quote!(
-            Ok((
-                #(::orga::state::State::<::orga::store::DefaultBackingStore>::flush(self.#indexes)?,)*
-            ))
-        )
-    } else {
-        let names = field_names();
-        quote!(
-            Ok((
+    let encoding_variants = variants.iter().map(|(variant, fields)| {
+        let ident = &variant.ident;
+        let fields = persisted(fields);
+        let types = fields.iter().map(|field| &field.field.ty);
+        if fields.is_empty() {
+            quote!(#ident)
+        } else {
+            quote!(#ident(#(<#types as ::orga::state::State>::Encoding),*))
+        }
+    });
+
+    let default_impl = variants
+        .first()
+        .filter(|(_, fields)| persisted(fields).is_empty())
+        .map(|(variant, _)| {
+            let ident = &variant.ident;
+            quote! {
+                impl#generics_sanitized Default for Encoding#generic_params
+                #where_clause
+                {
+                    fn default() -> Self {
+                        Encoding::#ident
+                    }
+                }
+            }
+        });
+
+    let create_arms = variants.iter().map(|(variant, fields)| {
+        let ident = &variant.ident;
+        let skipped_members = fields
+            .iter()
+            .filter(|field| field.skip)
+            .map(|field| &field.member);
+        let fields = persisted(fields);
+        let members = fields.iter().map(|field| &field.member);
+        let prefixes = fields.iter().map(|field| field.prefix_tokens());
+        let indexes = indexes(&fields);
+        let data = bindings(&fields, "data");
+        quote! {
+            #modname::Encoding::#ident { #(#indexes: #data),* } => Ok(Self::#ident {
+                #(
+                    #members: ::orga::state::State::create(store.sub(#prefixes), #data)?,
+                )*
+                #(#skipped_members: Default::default(),)*
+            })
+        }
+    });
 
-                #(::orga::state::State::<::orga::store::DefaultBackingStore>::flush(self.#names)?,)*
-            ))
-        )
+    let flush_arms = variants.iter().map(|(variant, fields)| {
+        let ident = &variant.ident;
+        let fields = persisted(fields);
+        let members = fields.iter().map(|field| &field.member);
+        let indexes = indexes(&fields);
+        let bindings = bindings(&fields, "field");
+        quote! {
+            Self::#ident { #(#members: #bindings,)* .. } => #modname::Encoding::#ident {
+                #(
+                    #indexes: ::orga::state::State::<::orga::store::DefaultBackingStore>::flush(#bindings)?,
+                )*
+            }
+        }
+    });
+
+    let prune_arms = variants.iter().enumerate().map(|(v, (variant, fields))| {
+        let ident = &variant.ident;
+        let v = Literal::u8_unsuffixed(v as u8);
+        let fields = persisted(fields);
+        let types = fields.iter().map(|field| &field.field.ty);
+        let prefixes = fields.iter().map(|field| field.prefix_tokens());
+        let indexes = indexes(&fields);
+        let data = bindings(&fields, "data");
+        quote! {
+            #modname::Encoding::#ident { #(#indexes: #data),* } => {
+                #(
+                    <#types as ::orga::state::State>::prune(store.sub(#prefixes), #data)?;
+                )*
+                #v
+            }
+        }
+    });
+
+    let from_arms = variants.iter().map(|(variant, fields)| {
+        let ident = &variant.ident;
+        let fields = persisted(fields);
+        let members = fields.iter().map(|field| &field.member);
+        let indexes = indexes(&fields);
+        let bindings = bindings(&fields, "field");
+        quote! {
+            #name::#ident { #(#members: #bindings,)* .. } => #modname::Encoding::#ident {
+                #(#indexes: #bindings.into(),)*
+            }
+        }
+    });
+
+    let encoding = quote!(#modname::Encoding#generic_params);
+    let (encoding_type, inner_data, wrap) = match attr.version {
+        Some(_) => {
+            let version_type = attr.version_type();
+            let version = attr.version_encoding();
+            (
+                quote!((#version_type #encoding)),
+                quote!(data.1),
+                quote!(|inner| (#version inner)),
+            )
+        }
+        None => (quote!(#encoding), quote!(data), quote!(|inner| inner)),
     };
 
-    let from_body = if is_tuple_struct {
-        let indexes = seq();
-        quote! (
-            #(value.#indexes.into(),)*
-        )
+    // variant indexes are at most 254, so this key is outside every variant's
+    // substore
+    let variant_key = Literal::u8_unsuffixed(u8::MAX);
+    let prune_arms = quote!(match &#inner_data {
+        #(#prune_arms,)*
+    });
+    // enums without persisted fields never write to their store
+    let has_children = variants
+        .iter()
+        .any(|(_, fields)| !persisted(fields).is_empty());
+    let prune_variants = if has_children {
+        quote! {
+            let active = #prune_arms;
+
+            // the variant whose fields are currently written to the store
+            let mut store = store;
+            let written = ::orga::store::Read::get(&store, &[#variant_key])?
+                .and_then(|bytes| bytes.first().copied())
+                .filter(|variant| *variant < #variant_count);
+            if written != Some(active) {
+                if let Some(written) = written {
+                    ::orga::store::Store::clear(&mut store.sub(&[written]))?;
+                }
+                ::orga::store::Write::put(&mut store, vec![#variant_key], vec![active])?;
+            }
+        }
     } else {
-        let names = field_names();
-        quote! (
-            #(value.#names.into(),)*
-        )
+        quote!(#prune_arms;)
     };
 
-    let output = quote! {
-        impl#generics ::orga::state::State for #name#generic_params
+    Ok(quote! {
+        pub mod #modname {
+            use super::*;
+
+            #[derive(::orga::encoding::Encode, ::orga::encoding::Decode)]
+            pub enum Encoding#generics
+            #where_clause
+            {
+                #(#encoding_variants,)*
+            }
+
+            #default_impl
+        }
+
+        impl#generics_sanitized ::orga::state::State for #name#generic_params
         #where_clause
         {
-            type Encoding = (
-                #(
-                    <#field_types_encoding as ::orga::state::State>::Encoding,
-                )*
-            );
+            type Encoding = #encoding_type;
 
             fn create(
                 store: ::orga::store::Store,
                 data: Self::Encoding,
             ) -> ::orga::Result<Self> {
-                #create_body
+                match #inner_data {
+                    #(#create_arms,)*
+                }
             }
 
             fn flush(self) -> ::orga::Result<Self::Encoding> {
-                #flush_body
+                let inner = match self {
+                    #(#flush_arms,)*
+                };
+                Ok((#wrap)(inner))
+            }
+
+            fn prune(
+                store: ::orga::store::Store,
+                data: &Self::Encoding,
+            ) -> ::orga::Result<()> {
+                #prune_variants
+                Ok(())
             }
         }
 
-        impl#generics From<#name#generic_params> for <#name#generic_params as ::orga::state::State>::Encoding
+        impl#generics_sanitized From<#name#generic_params> for #encoding_type
         #where_clause
         {
             fn from(value: #name#generic_params) -> Self {
-                (#from_body)
+                let inner = match value {
+                    #(#from_arms,)*
+                };
+                (#wrap)(inner)
             }
         }
-    };
+    })
+}
 
-    output.into()
+/// Options given to the `State` derive through `#[state(...)]` on the type
+/// itself.
+#[derive(Default)]
+struct StateAttr {
+    /// If set, the encoding is prefixed with this version byte, and data
+    /// written with any other version fails to decode.
+    version: Option<u8>,
 }
 
-fn struct_fields(item: &DeriveInput) -> impl Iterator<Item = &Field> {
-    let data = match item.data {
-        Data::Struct(ref data) => data,
-        Data::Enum(ref _data) => todo!("#[derive(State)] does not yet support enums"),
-        Data::Union(_) => panic!("Unions are not supported"),
-    };
+impl StateAttr {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut output = StateAttr::default();
+        for meta in state_metas(attrs)? {
+            match meta {
+                Meta::NameValue(ref meta) if meta.path.is_ident("version") => match meta.lit {
+                    Lit::Int(ref lit) => output.version = Some(lit.base10_parse()?),
+                    ref lit => return Err(Error::new_spanned(lit, "Expected an integer")),
+                },
+                meta => {
+                    return Err(Error::new_spanned(
+                        meta,
+                        "Unknown state attribute, expected `version = N`",
+                    ))
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    fn version_type(&self) -> TokenStream2 {
+        match self.version {
+            Some(version) => {
+                let version = Literal::u8_unsuffixed(version);
+                quote!(::orga::state::Version<#version>,)
+            }
+            None => quote!(),
+        }
+    }
+
+    fn version_encoding(&self) -> TokenStream2 {
+        match self.version {
+            Some(version) => {
+                let version = Literal::u8_unsuffixed(version);
+                quote!(::orga::state::Version::<#version>,)
+            }
+            None => quote!(),
+        }
+    }
+}
+
+/// A field of a struct or enum variant, along with the options given through
+/// `#[state(...)]` on it.
+#[derive(Clone)]
+struct StateField<'a> {
+    field: &'a Field,
+    member: TokenStream2,
+    /// The full substore prefix of the field, relative to the type's own store.
+    prefix: Vec<u8>,
+    /// Skipped fields are not persisted, and are created with
+    /// `Default::default()`.
+    skip: bool,
+}
+
+impl<'a> StateField<'a> {
+    fn prefix_tokens(&self) -> TokenStream2 {
+        let bytes = self.prefix.iter().map(|b| Literal::u8_unsuffixed(*b));
+        quote!(&[#(#bytes),*])
+    }
+}
 
-    match data.fields {
-        Fields::Named(ref fields) => fields.named.iter(),
-        Fields::Unnamed(ref fields) => fields.unnamed.iter(),
-        Fields::Unit => panic!("Unit structs are not supported"),
+/// Parses the `#[state(...)]` attributes of the given fields. Fields are
+/// prefixed by `base` followed by either their custom prefix (set with
+/// `#[state(prefix = b"...")]`) or their index. Returns an error if any two
+/// persisted fields' prefixes would overlap.
+fn state_fields<'a>(fields: &'a Fields, base: &[u8]) -> Result<Vec<StateField<'a>>> {
+    if fields.len() > u8::MAX as usize + 1 {
+        return Err(Error::new_spanned(
+            fields,
+            "#[derive(State)] supports at most 256 fields",
+        ));
+    }
+
+    let mut output: Vec<StateField> = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        let mut prefix = None;
+        let mut skip = false;
+        for meta in state_metas(&field.attrs)? {
+            match meta {
+                Meta::Path(ref path) if path.is_ident("skip") => skip = true,
+                Meta::NameValue(ref meta) if meta.path.is_ident("prefix") => match meta.lit {
+                    Lit::ByteStr(ref lit) if !lit.value().is_empty() => prefix = Some(lit.value()),
+                    Lit::Int(ref lit) => prefix = Some(vec![lit.base10_parse()?]),
+                    ref lit => {
+                        return Err(Error::new_spanned(
+                            lit,
+                            "Expected a non-empty byte string or a byte",
+                        ))
+                    }
+                },
+                meta => {
+                    return Err(Error::new_spanned(
+                        meta,
+                        "Unknown state attribute, expected `skip` or `prefix = ...`",
+                    ))
+                }
+            }
+        }
+
+        let member = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let i = Literal::usize_unsuffixed(i);
+                quote!(#i)
+            }
+        };
+        let mut full_prefix = base.to_vec();
+        full_prefix.extend(prefix.unwrap_or_else(|| vec![i as u8]));
+
+        if !skip {
+            let overlapping = output.iter().filter(|other| !other.skip).find(|other| {
+                other.prefix.starts_with(&full_prefix) || full_prefix.starts_with(&other.prefix)
+            });
+            if let Some(other) = overlapping {
+                return Err(Error::new_spanned(
+                    field,
+                    format!(
+                        "Store prefix of this field overlaps with the prefix of field `{}`",
+                        other.member
+                    ),
+                ));
+            }
+        }
+
+        output.push(StateField {
+            field,
+            member,
+            prefix: full_prefix,
+            skip,
+        });
+    }
+
+    Ok(output)
+}
+
+/// Returns the items of all `#[state(...)]` attributes in `attrs`.
+fn state_metas(attrs: &[Attribute]) -> Result<Vec<Meta>> {
+    let mut metas = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("state")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(meta) => metas.push(meta),
+                        NestedMeta::Lit(lit) => {
+                            return Err(Error::new_spanned(lit, "Unexpected literal"))
+                        }
+                    }
+                }
+            }
+            meta => return Err(Error::new_spanned(meta, "Expected #[state(...)]")),
+        }
     }
+    Ok(metas)
 }
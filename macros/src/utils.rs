@@ -1,14 +1,8 @@
-use proc_macro2::TokenStream;
+use heck::{CamelCase, SnakeCase};
+use proc_macro2::{Span, TokenStream};
 use quote::quote;
-use std::collections::HashSet;
 use syn::*;
 
-pub fn parse_parent() -> File {
-    let path = proc_macro::Span::call_site().source_file().path();
-    let source = std::fs::read_to_string(path).unwrap();
-    parse_file(source.as_str()).unwrap()
-}
-
 pub fn get_generic_requirements<I, J>(inputs: I, params: J) -> Vec<Ident>
 where
     I: Iterator<Item = Type>,
@@ -40,7 +34,9 @@ where
                                 },
                                 _ => None,
                             };
-                            maybe_path.map(|path| add_arguments(path, paths));
+                            if let Some(path) = maybe_path {
+                                add_arguments(path, paths);
+                            }
                         }
                     }
                 }
@@ -51,83 +47,20 @@ where
         });
     let mut requirements = vec![];
     for input in maybe_generic_inputs {
-        params
+        let param = params
             .iter()
             .filter_map(|param| match param {
                 GenericParam::Type(param) => Some(param),
                 _ => None,
             })
-            .find(|param| param.ident == input.ident)
-            .map(|param| {
+            .find(|param| param.ident == input.ident);
+        if let Some(param) = param {
+            if !requirements.contains(&param.ident) {
                 requirements.push(param.ident.clone());
-            });
-    }
-    let req_set: HashSet<_> = requirements.into_iter().collect();
-    req_set.into_iter().collect()
-}
-
-pub fn relevant_impls(name: &Ident, source: &File) -> Vec<ItemImpl> {
-    source
-        .items
-        .iter()
-        .filter_map(|item| match item {
-            Item::Impl(item) => Some(item),
-            _ => None,
-        })
-        .filter(|item| item.trait_.is_none())
-        .filter(|item| {
-            let path = match &*item.self_ty {
-                Type::Path(path) => path,
-                _ => return false,
-            };
-
-            if path.qself.is_some() {
-                return false;
-            }
-            if path.path.segments.len() != 1 {
-                return false;
-            }
-            if path.path.segments[0].ident != *name {
-                return false;
             }
-
-            true
-        })
-        .cloned()
-        .collect()
-}
-
-pub fn relevant_methods(
-    name: &Ident,
-    attr: &str,
-    source: &File,
-) -> Vec<(ImplItemMethod, ItemImpl)> {
-    let get_methods = |item: ItemImpl| -> Vec<_> {
-        item.items
-            .iter()
-            .filter_map(|item| match item {
-                ImplItem::Method(method) => Some(method),
-                _ => None,
-            })
-            .filter(|method| {
-                method
-                    .attrs
-                    .iter()
-                    .find(|a| a.path.is_ident(&attr))
-                    .is_some()
-            })
-            .filter(|method| matches!(method.vis, Visibility::Public(_)))
-            .filter(|method| method.sig.unsafety.is_none())
-            .filter(|method| method.sig.asyncness.is_none())
-            .filter(|method| method.sig.abi.is_none())
-            .map(|method| (method.clone(), item.clone()))
-            .collect()
-    };
-
-    relevant_impls(name, source)
-        .into_iter()
-        .flat_map(get_methods)
-        .collect()
+        }
+    }
+    requirements
 }
 
 pub fn gen_param_input(generics: &Generics, bracketed: bool) -> TokenStream {
@@ -154,3 +87,718 @@ pub fn gen_param_input(generics: &Generics, bracketed: bool) -> TokenStream {
         quote!(#(#gen_params),*)
     }
 }
+
+/// A field which can be reached through the derived call, query and client
+/// types. Fields of enum variants can only be reached while their variant is
+/// the active one.
+pub struct RoutedField<'a> {
+    pub field: &'a Field,
+    pub variant: Option<&'a Ident>,
+    index: usize,
+}
+
+/// Returns the fields of a struct or enum which are routed to by the derived
+/// call, query and client types: the public fields of a struct, or every field
+/// of every variant of an enum. Fields marked with `#[{attr}(skip)]` (e.g.
+/// `#[call(skip)]`) are left out.
+pub fn routed_fields<'a>(item: &'a DeriveInput, attr: &str) -> Result<Vec<RoutedField<'a>>> {
+    let fields: Vec<_> = match &item.data {
+        Data::Struct(data) => data
+            .fields
+            .iter()
+            .filter(|field| matches!(field.vis, Visibility::Public(_)))
+            .enumerate()
+            .map(|(index, field)| RoutedField {
+                field,
+                variant: None,
+                index,
+            })
+            .collect(),
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .flat_map(|variant| {
+                variant
+                    .fields
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, field)| RoutedField {
+                        field,
+                        variant: Some(&variant.ident),
+                        index,
+                    })
+            })
+            .collect(),
+        Data::Union(_) => panic!("Unions are not supported"),
+    };
+
+    let mut routed = vec![];
+    for field in fields {
+        if !is_skipped(&field.field.attrs, attr)? {
+            routed.push(field);
+        }
+    }
+    Ok(routed)
+}
+
+/// Returns whether the attributes include `#[{name}(skip)]`. Any other
+/// arguments given to `#[{name}(...)]` are an error.
+fn is_skipped(attrs: &[Attribute], name: &str) -> Result<bool> {
+    let mut skip = false;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident(name)) {
+        let ident: Ident = attr.parse_args()?;
+        if ident != "skip" {
+            return Err(Error::new_spanned(
+                ident,
+                format!("Unknown {} attribute, expected `skip`", name),
+            ));
+        }
+        skip = true;
+    }
+    Ok(skip)
+}
+
+impl<'a> RoutedField<'a> {
+    fn camel_name(&self) -> String {
+        let field = self
+            .field
+            .ident
+            .as_ref()
+            .map_or(self.index.to_string(), |f| f.to_string().to_camel_case());
+        match self.variant {
+            Some(variant) => format!("{}Field{}", variant, field),
+            None => format!("Field{}", field),
+        }
+    }
+
+    /// The name of the call and query enum variants for this field, e.g.
+    /// `FieldFoo` or, for enums, `PendingFieldFoo`.
+    pub fn variant_name(&self) -> Ident {
+        Ident::new(self.camel_name().as_str(), Span::call_site())
+    }
+
+    /// The name of the client adapter struct for this field.
+    pub fn adapter_name(&self) -> Ident {
+        Ident::new(
+            format!("{}Adapter", self.camel_name()).as_str(),
+            Span::call_site(),
+        )
+    }
+
+    /// The name of this field in the derived client struct, e.g. `foo` or,
+    /// for enums, `pending_foo`.
+    pub fn client_name(&self) -> TokenStream {
+        let member = self.member();
+        match self.variant {
+            Some(variant) => {
+                let name = format!("{}_{}", variant, quote!(#member)).to_snake_case();
+                let name = Ident::new(name.as_str(), Span::call_site());
+                quote!(#name)
+            }
+            None => quote!(#member),
+        }
+    }
+
+    /// The field's name, or its index if it is unnamed.
+    pub fn member(&self) -> TokenStream {
+        match self.field.ident.as_ref() {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = proc_macro2::Literal::usize_unsuffixed(self.index);
+                quote!(#index)
+            }
+        }
+    }
+
+    /// Expands to an expression which borrows the field from `self` (mutably
+    /// if `mutable` is set) and evaluates `body` with the borrow bound to
+    /// `binding`. For enum fields, the expression returns an error built with
+    /// `error` (e.g. `::orga::Error::Call`) if the variant is not active.
+    pub fn with_field(
+        &self,
+        mutable: bool,
+        binding: &Ident,
+        body: TokenStream,
+        error: TokenStream,
+    ) -> TokenStream {
+        let member = self.member();
+        let reference = if mutable { quote!(&mut) } else { quote!(&) };
+        match self.variant {
+            None => quote! {{
+                let #binding = #reference self.#member;
+                #body
+            }},
+            Some(variant) => {
+                let message = format!("Variant {} is not active", variant);
+                quote! {{
+                    #[allow(unreachable_patterns)]
+                    let res = match self {
+                        Self::#variant { #member: #binding, .. } => #body,
+                        _ => Err(#error(#message.into())),
+                    };
+                    res
+                }}
+            }
+        }
+    }
+}
+
+/// Collects the methods of an `#[orga::methods]` impl block which are marked
+/// with `attr` (`call` or `query`), validating each of them.
+pub fn relevant_methods(item: &ItemImpl, attr: &str) -> Result<Vec<Method>> {
+    let kind = attr.to_camel_case();
+    let mut methods = vec![];
+    for impl_item in item.items.iter() {
+        let method = match impl_item {
+            ImplItem::Method(method) => method,
+            _ => continue,
+        };
+        let method_attr = match method.attrs.iter().find(|a| a.path.is_ident(attr)) {
+            Some(method_attr) => method_attr,
+            None => continue,
+        };
+
+        let args = if method_attr.tokens.is_empty() {
+            TokenStream::new()
+        } else {
+            method_attr.parse_args()?
+        };
+        validate_method(method, args, kind.as_str())?;
+
+        methods.extend(Method::instantiate(method, attr)?);
+    }
+
+    Ok(methods)
+}
+
+/// Returns the name of the type an impl block is for, e.g. `Foo` for
+/// `impl<T> Foo<T>`.
+pub fn impl_type_name(item: &ItemImpl) -> Result<&Ident> {
+    match &*item.self_ty {
+        Type::Path(path) if path.qself.is_none() => Ok(&path.path.segments.last().unwrap().ident),
+        ty => Err(Error::new_spanned(ty, "Expected a path to a struct type")),
+    }
+}
+
+/// Returns the generic arguments an impl block passes to its type, e.g. `T`
+/// for `impl<T> Foo<T>`.
+pub fn impl_type_args(item: &ItemImpl) -> Vec<GenericArgument> {
+    match &*item.self_ty {
+        Type::Path(path) => match path.path.segments.last().unwrap().arguments {
+            PathArguments::AngleBracketed(ref args) => args.args.iter().cloned().collect(),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// The predicates of a where clause, each followed by a comma so more can be
+/// appended.
+pub fn where_preds(generics: &Generics) -> TokenStream {
+    let preds = generics
+        .where_clause
+        .iter()
+        .flat_map(|w| w.predicates.iter());
+    quote!(#(#preds,)*)
+}
+
+/// Bounds requiring the impl block's type parameters which appear in method
+/// arguments to be encodable.
+pub fn method_encoding_bounds(item: &ItemImpl, methods: &[Method]) -> Vec<TokenStream> {
+    let params = get_generic_requirements(
+        methods.iter().flat_map(|method| method.input_types()),
+        item.generics.params.iter().cloned(),
+    );
+
+    params
+        .into_iter()
+        .map(|p| quote!(#p: ::orga::encoding::Encode + ::orga::encoding::Decode + ::orga::encoding::Terminated,))
+        .collect()
+}
+
+/// The generic parameters of a method call or query enum: the impl block's
+/// type parameters which appear in method arguments, in declaration order.
+pub fn method_enum_params(item: &ItemImpl, methods: &[Method]) -> TokenStream {
+    let requirements = get_generic_requirements(
+        methods.iter().flat_map(|method| method.input_types()),
+        item.generics.params.iter().cloned(),
+    );
+
+    let params: Vec<_> = item
+        .generics
+        .type_params()
+        .map(|p| &p.ident)
+        .filter(|ident| requirements.contains(ident))
+        .collect();
+
+    if params.is_empty() {
+        quote!()
+    } else {
+        quote!(<#(#params),*>)
+    }
+}
+
+/// Checks that a method marked with `#[call]` or `#[query]` can be exposed,
+/// returning a spanned error otherwise. `kind` is used in error messages (e.g.
+/// "Call").
+pub fn validate_method(method: &ImplItemMethod, args: TokenStream, kind: &str) -> Result<()> {
+    if !matches!(method.vis, Visibility::Public(_)) {
+        return Err(Error::new_spanned(
+            &method.sig.ident,
+            format!("{} methods must be public", kind),
+        ));
+    }
+
+    if let Some(unsafety) = &method.sig.unsafety {
+        return Err(Error::new_spanned(
+            unsafety,
+            format!("{} methods cannot be unsafe", kind),
+        ));
+    }
+
+    if let Some(asyncness) = &method.sig.asyncness {
+        return Err(Error::new_spanned(
+            asyncness,
+            format!("{} methods cannot be async", kind),
+        ));
+    }
+
+    if let Some(abi) = &method.sig.abi {
+        return Err(Error::new_spanned(
+            abi,
+            format!("{} methods cannot specify ABI", kind),
+        ));
+    }
+
+    if let Some(receiver) = method.sig.inputs.first() {
+        if !matches!(receiver, FnArg::Receiver(_)) {
+            return Err(Error::new_spanned(
+                receiver,
+                format!("{} methods must take self by reference", kind),
+            ));
+        }
+    } else {
+        return Err(Error::new_spanned(
+            &method.sig,
+            format!("{} methods must take self by reference", kind),
+        ));
+    }
+
+    for input in method.sig.inputs.iter() {
+        if let FnArg::Typed(input) = input {
+            if is_str_ref(&input.ty) {
+                let pat = &input.pat;
+                return Err(Error::new_spanned(
+                    input,
+                    format!(
+                        "{} method argument `{}` cannot be a `&str` since `str` can not be encoded, take a `&[u8]` instead",
+                        kind,
+                        quote!(#pat),
+                    ),
+                ));
+            }
+        }
+    }
+
+    let method_attr: MethodAttr = if args.is_empty() {
+        Default::default()
+    } else {
+        parse2(args)?
+    };
+    Method::check_instances(method, &method_attr, kind)
+}
+
+/// Arguments given to a `#[call(...)]` or `#[query(...)]` attribute.
+///
+/// Generic methods must declare the concrete types they are exposed with,
+/// e.g. `#[call(instance(u32), instance(u64))]`. Types are given in order
+/// for the method's type parameters followed by any `impl Trait` arguments.
+#[derive(Default)]
+pub struct MethodAttr {
+    pub instances: Vec<Vec<Type>>,
+}
+
+impl parse::Parse for MethodAttr {
+    fn parse(input: parse::ParseStream) -> Result<Self> {
+        let mut instances = vec![];
+        while !input.is_empty() {
+            let ident: Ident = input.parse()?;
+            if ident != "instance" {
+                return Err(Error::new(ident.span(), "Expected `instance(...)`"));
+            }
+
+            let content;
+            syn::parenthesized!(content in input);
+            let types = punctuated::Punctuated::<Type, syn::Token![,]>::parse_terminated(&content)?;
+            instances.push(types.into_iter().collect());
+
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<syn::Token![,]>()?;
+        }
+
+        Ok(MethodAttr { instances })
+    }
+}
+
+impl MethodAttr {
+    fn from_method(method: &ImplItemMethod, attr: &str) -> Result<Self> {
+        match method.attrs.iter().find(|a| a.path.is_ident(attr)) {
+            Some(attr) if !attr.tokens.is_empty() => attr.parse_args(),
+            _ => Ok(Default::default()),
+        }
+    }
+}
+
+/// A `#[call]` or `#[query]` method, monomorphised for one of the
+/// instantiations declared in its attribute. Non-generic methods have exactly
+/// one `Method`.
+#[derive(Clone)]
+pub struct Method {
+    /// The method signature with all type parameters and `impl Trait`
+    /// arguments replaced by concrete types.
+    pub sig: Signature,
+    /// Concrete types for the method's named type parameters.
+    generic_args: Vec<Type>,
+    /// Whether the type arguments can be passed explicitly (not allowed for
+    /// methods with `impl Trait` arguments).
+    turbofish: bool,
+    /// Appended to the variant and client method names to tell apart
+    /// multiple instantiations of the same method.
+    suffix: String,
+}
+
+impl Method {
+    fn instantiate(method: &ImplItemMethod, attr: &str) -> Result<Vec<Self>> {
+        let method_attr = MethodAttr::from_method(method, attr)?;
+
+        let named = type_params(&method.sig);
+        if named.is_empty() && count_impl_traits(&method.sig) == 0 {
+            return Ok(vec![Method {
+                sig: method.sig.clone(),
+                generic_args: vec![],
+                turbofish: false,
+                suffix: String::new(),
+            }]);
+        }
+
+        let multiple = method_attr.instances.len() > 1;
+        let turbofish = count_impl_traits(&method.sig) == 0;
+        let methods = method_attr
+            .instances
+            .into_iter()
+            .map(|types| {
+                let named_args: Vec<_> = named.iter().cloned().zip(types.iter().cloned()).collect();
+                let mut anon_args = types.iter().skip(named.len()).cloned();
+
+                let mut sig = method.sig.clone();
+                for input in sig.inputs.iter_mut() {
+                    if let FnArg::Typed(input) = input {
+                        substitute_type(&mut input.ty, &named_args, &mut anon_args);
+                    }
+                }
+                if let ReturnType::Type(_, ref mut ty) = sig.output {
+                    substitute_type(ty, &named_args, &mut std::iter::empty::<Type>());
+                }
+                sig.generics.params = sig
+                    .generics
+                    .params
+                    .into_iter()
+                    .filter(|p| matches!(p, GenericParam::Lifetime(_)))
+                    .collect();
+                sig.generics.where_clause = None;
+
+                Method {
+                    sig,
+                    generic_args: types[..named.len()].to_vec(),
+                    turbofish,
+                    suffix: if multiple {
+                        type_suffix(&types)
+                    } else {
+                        String::new()
+                    },
+                }
+            })
+            .collect();
+
+        Ok(methods)
+    }
+
+    fn check_instances(
+        method: &ImplItemMethod,
+        method_attr: &MethodAttr,
+        kind: &str,
+    ) -> Result<()> {
+        if let Some(param) = method
+            .sig
+            .generics
+            .params
+            .iter()
+            .find(|p| matches!(p, GenericParam::Const(_)))
+        {
+            return Err(Error::new_spanned(
+                param,
+                format!("{} methods cannot have const parameters", kind),
+            ));
+        }
+
+        let arity = type_params(&method.sig).len() + count_impl_traits(&method.sig);
+        if arity == 0 {
+            if !method_attr.instances.is_empty() {
+                return Err(Error::new_spanned(
+                    &method.sig.ident,
+                    "Only generic methods can declare instances",
+                ));
+            }
+            return Ok(());
+        }
+
+        if method_attr.instances.is_empty() {
+            return Err(Error::new_spanned(
+                &method.sig.generics,
+                format!(
+                    "Generic {} methods must declare their instantiations, e.g. #[{}(instance(u64))]",
+                    kind.to_lowercase(),
+                    kind.to_lowercase(),
+                ),
+            ));
+        }
+
+        for instance in method_attr.instances.iter() {
+            if instance.len() != arity {
+                return Err(Error::new_spanned(
+                    &method.sig.ident,
+                    format!(
+                        "Expected {} type(s) per instance, found {}",
+                        arity,
+                        instance.len()
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The name of the call or query enum variant for this method.
+    pub fn variant_name(&self) -> Ident {
+        Ident::new(
+            format!(
+                "Method{}{}",
+                self.sig.ident.to_string().to_camel_case(),
+                self.suffix
+            )
+            .as_str(),
+            Span::call_site(),
+        )
+    }
+
+    /// The name of the generated client method for this method.
+    pub fn client_name(&self) -> Ident {
+        if self.suffix.is_empty() {
+            self.sig.ident.clone()
+        } else {
+            Ident::new(
+                format!("{}_{}", self.sig.ident, self.suffix.to_snake_case()).as_str(),
+                Span::call_site(),
+            )
+        }
+    }
+
+    /// The method's arguments, excluding the receiver.
+    pub fn typed_inputs(&self) -> impl Iterator<Item = &PatType> {
+        self.sig.inputs.iter().filter_map(|input| match input {
+            FnArg::Typed(input) => Some(input),
+            _ => None,
+        })
+    }
+
+    /// The owned types which the method's arguments are encoded as.
+    pub fn input_types(&self) -> Vec<Type> {
+        self.typed_inputs()
+            .map(|input| owned_type(&input.ty))
+            .collect()
+    }
+
+    /// The method's own where clause predicates, each followed by a comma.
+    pub fn where_preds(&self) -> TokenStream {
+        where_preds(&self.sig.generics)
+    }
+
+    pub fn output_type(&self) -> Type {
+        match self.sig.output {
+            ReturnType::Type(_, ref ty) => *(ty.clone()),
+            ReturnType::Default => syn::parse_quote!(()),
+        }
+    }
+
+    /// Bindings for the owned arguments named by `args`, made mutable where
+    /// the method takes a mutable reference.
+    pub fn input_bindings(&self, args: &[Ident]) -> Vec<TokenStream> {
+        self.typed_inputs()
+            .zip(args)
+            .map(|(input, arg)| match *input.ty {
+                Type::Reference(ref reference) if reference.mutability.is_some() => {
+                    quote!(mut #arg)
+                }
+                _ => quote!(#arg),
+            })
+            .collect()
+    }
+
+    /// Calls the method on `receiver` with the owned arguments named by
+    /// `args`, borrowing them where the method takes references.
+    pub fn invocation(&self, receiver: TokenStream, args: &[Ident]) -> TokenStream {
+        let name = &self.sig.ident;
+        let turbofish = if self.turbofish && !self.generic_args.is_empty() {
+            let generic_args = &self.generic_args;
+            quote!(::<#(#generic_args),*>)
+        } else {
+            quote!()
+        };
+        let arg_exprs = self
+            .typed_inputs()
+            .zip(args)
+            .map(|(input, arg)| match *input.ty {
+                Type::Reference(ref reference) if reference.mutability.is_some() => {
+                    quote!(&mut #arg)
+                }
+                Type::Reference(_) => quote!(&#arg),
+                _ => quote!(#arg),
+            });
+
+        quote!(#receiver.#name#turbofish(#(#arg_exprs),*))
+    }
+}
+
+/// Returns the type an argument is encoded as: references are replaced by the
+/// type they point to, and slice references by `Vec`s.
+pub fn owned_type(ty: &Type) -> Type {
+    match ty {
+        Type::Reference(reference) => match *reference.elem {
+            Type::Slice(ref slice) => {
+                let elem = &slice.elem;
+                syn::parse_quote!(Vec<#elem>)
+            }
+            ref elem => elem.clone(),
+        },
+        ty => ty.clone(),
+    }
+}
+
+fn is_str_ref(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) => match *reference.elem {
+            Type::Path(ref path) => path.qself.is_none() && path.path.is_ident("str"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn type_params(sig: &Signature) -> Vec<Ident> {
+    sig.generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(param.ident.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn count_impl_traits(sig: &Signature) -> usize {
+    let mut count = 0;
+    for input in sig.inputs.iter() {
+        if let FnArg::Typed(input) = input {
+            let mut ty = *input.ty.clone();
+            walk_type(&mut ty, &mut |ty: &mut Type| {
+                if let Type::ImplTrait(_) = ty {
+                    count += 1;
+                    return true;
+                }
+                false
+            });
+        }
+    }
+    count
+}
+
+/// Replaces the named type parameters in `ty`, and each `impl Trait` in order
+/// of appearance with the next type from `anon`.
+fn substitute_type(ty: &mut Type, named: &[(Ident, Type)], anon: &mut dyn Iterator<Item = Type>) {
+    walk_type(ty, &mut |ty: &mut Type| {
+        if let Type::ImplTrait(_) = ty {
+            if let Some(concrete) = anon.next() {
+                *ty = concrete;
+            }
+            return true;
+        }
+
+        if let Type::Path(path) = ty {
+            if path.qself.is_none() && path.path.segments.len() == 1 {
+                let segment = &path.path.segments[0];
+                if segment.arguments.is_empty() {
+                    if let Some((_, concrete)) = named.iter().find(|(i, _)| *i == segment.ident) {
+                        *ty = concrete.clone();
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    });
+}
+
+/// Visits `ty` and its nested types, stopping descent wherever `f` returns
+/// `true`.
+fn walk_type(ty: &mut Type, f: &mut dyn FnMut(&mut Type) -> bool) {
+    if f(ty) {
+        return;
+    }
+
+    match ty {
+        Type::Path(path) => {
+            if let Some(ref mut qself) = path.qself {
+                walk_type(&mut qself.ty, f);
+            }
+            for segment in path.path.segments.iter_mut() {
+                if let PathArguments::AngleBracketed(ref mut args) = segment.arguments {
+                    for arg in args.args.iter_mut() {
+                        if let GenericArgument::Type(ref mut ty) = arg {
+                            walk_type(ty, f);
+                        }
+                    }
+                }
+            }
+        }
+        Type::Reference(reference) => walk_type(&mut reference.elem, f),
+        Type::Array(array) => walk_type(&mut array.elem, f),
+        Type::Slice(slice) => walk_type(&mut slice.elem, f),
+        Type::Paren(paren) => walk_type(&mut paren.elem, f),
+        Type::Group(group) => walk_type(&mut group.elem, f),
+        Type::Tuple(tuple) => {
+            for elem in tuple.elems.iter_mut() {
+                walk_type(elem, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Builds a name suffix from a list of types, e.g. `[u32, Vec<u8>]` becomes
+/// `"U32VecU8"`.
+fn type_suffix(types: &[Type]) -> String {
+    types
+        .iter()
+        .map(|ty| quote!(#ty).to_string())
+        .flat_map(|s| {
+            s.split(|c: char| !c.is_alphanumeric())
+                .filter(|part| !part.is_empty())
+                .map(|part| part.to_camel_case())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
@@ -0,0 +1,150 @@
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tendermint::block::Height;
+use tendermint_rpc as tm;
+use tm::Client as _;
+
+use crate::{Error, Result};
+
+/// How long [`NodeHeaders`] sleeps between polls for a header which has not
+/// been committed yet.
+const HEADER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long [`NodeHeaders`] waits for a header before giving up. The header
+/// covering the latest state is only produced with the next block, so this
+/// should be longer than the chain's block time.
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A list of RPC endpoints which are used in turn, so requests can fail over
+/// to another node when one is unreachable or serves an invalid response.
+pub struct Endpoints {
+    addrs: Vec<String>,
+    clients: Vec<tm::HttpClient>,
+    current: AtomicUsize,
+}
+
+impl Endpoints {
+    pub fn new(addrs: &[&str]) -> Result<Self> {
+        if addrs.is_empty() {
+            return Err(Error::Tendermint("Must have at least one endpoint".into()));
+        }
+
+        let clients = addrs
+            .iter()
+            .map(|addr| tm::HttpClient::new(*addr))
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(Endpoints {
+            addrs: addrs.iter().map(|addr| addr.to_string()).collect(),
+            clients,
+            current: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// The client of the endpoint requests are currently sent to.
+    pub fn current(&self) -> tm::HttpClient {
+        let index = self.current.load(Ordering::SeqCst) % self.clients.len();
+        self.clients[index].clone()
+    }
+
+    /// The address of the endpoint requests are currently sent to.
+    pub fn current_addr(&self) -> &str {
+        let index = self.current.load(Ordering::SeqCst) % self.addrs.len();
+        self.addrs[index].as_str()
+    }
+
+    /// Moves on to the next endpoint, e.g. after the current one failed.
+    pub fn failover(&self) {
+        self.current.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Provides the app hashes of headers it has verified, so query proofs can be
+/// checked without trusting the node which served them.
+#[async_trait::async_trait]
+pub trait HeaderVerifier: Send + Sync {
+    /// Returns the app hash in the verified header at `height`, which commits
+    /// to the state after block `height - 1`.
+    async fn app_hash(&self, height: u64) -> Result<Vec<u8>>;
+}
+
+/// A [`HeaderVerifier`] which takes headers from the client's RPC endpoints
+/// without checking their signatures.
+///
+/// This keeps a node from forging a query result by sending a root hash which
+/// does not match its headers, but it still trusts the node's headers.
+pub struct NodeHeaders {
+    endpoints: Arc<Endpoints>,
+    timeout: Duration,
+}
+
+impl NodeHeaders {
+    pub fn new(endpoints: Arc<Endpoints>) -> Self {
+        NodeHeaders {
+            endpoints,
+            timeout: DEFAULT_HEADER_TIMEOUT,
+        }
+    }
+
+    /// Sets how long to wait for a header which has not been committed yet.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl HeaderVerifier for NodeHeaders {
+    async fn app_hash(&self, height: u64) -> Result<Vec<u8>> {
+        let height = Height::try_from(height).map_err(|err| Error::Tendermint(err.to_string()))?;
+
+        let start = Instant::now();
+        loop {
+            match self.endpoints.current().commit(height).await {
+                Ok(res) => {
+                    let header = res.signed_header.header();
+                    if header.height != height {
+                        return Err(Error::Tendermint(
+                            "Node returned a header for the wrong height".into(),
+                        ));
+                    }
+
+                    return Ok(header.app_hash.value());
+                }
+                Err(err) if start.elapsed() >= self.timeout => return Err(err.into()),
+                Err(_) => tokio::time::sleep(HEADER_POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_failover() {
+        assert!(Endpoints::new(&[]).is_err());
+
+        let endpoints =
+            Endpoints::new(&["http://localhost:26657", "http://localhost:36657"]).unwrap();
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints.current.load(Ordering::SeqCst) % 2, 0);
+        endpoints.failover();
+        assert_eq!(endpoints.current.load(Ordering::SeqCst) % 2, 1);
+        assert_eq!(endpoints.current_addr(), "http://localhost:36657");
+        endpoints.failover();
+        assert_eq!(endpoints.current.load(Ordering::SeqCst) % 2, 0);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tendermint::block::Height;
+use tendermint::hash::{Algorithm, Hash};
+use tendermint::{validator, Time};
+use tendermint_light_client::components::verifier::{ProdVerifier, Verdict, Verifier};
+use tendermint_light_client::types::{LightBlock, Options};
+use tendermint_rpc as tm;
+use tm::Client as _;
+
+use super::{Endpoints, HeaderVerifier};
+use crate::{Error, Result};
+
+/// How long headers stay trusted after they were signed, if not changed with
+/// [`LightClient::with_options`]. This must be shorter than the chain's
+/// unbonding period.
+const DEFAULT_TRUSTING_PERIOD: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// How far ahead of the local clock a header's time can be.
+const DEFAULT_CLOCK_DRIFT: Duration = Duration::from_secs(5);
+
+/// How long [`LightClient`] sleeps between polls for a header which has not
+/// been committed yet.
+const HEADER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long [`LightClient`] waits for a header before giving up.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many verified headers are kept to verify later headers from.
+const MAX_VERIFIED_HEADERS: usize = 100;
+
+/// A [`HeaderVerifier`] which checks the validator signatures on every header
+/// it returns, so query results can not be forged by the nodes serving them.
+///
+/// Verification starts from a header which is trusted out of band, given by
+/// the same height and hash as the `statesync` options set with
+/// [`Tendermint::trust_height`](crate::tendermint::Tendermint::trust_height)
+/// and [`Tendermint::trust_hash`](crate::tendermint::Tendermint::trust_hash).
+/// Later headers are verified by skipping ahead as far as the trusted
+/// validators allow, bisecting when too much of the validator set has
+/// changed.
+pub struct LightClient {
+    endpoints: Arc<Endpoints>,
+    verifier: ProdVerifier,
+    options: Options,
+    trust_height: u64,
+    trust_hash: Hash,
+    verified: Mutex<BTreeMap<u64, LightBlock>>,
+}
+
+impl LightClient {
+    /// Creates a light client which trusts the header with hash `trust_hash`,
+    /// given as hex, at `trust_height`.
+    pub fn new(endpoints: Arc<Endpoints>, trust_height: u32, trust_hash: &str) -> Result<Self> {
+        Ok(LightClient {
+            endpoints,
+            verifier: ProdVerifier::default(),
+            options: Options {
+                trust_threshold: Default::default(),
+                trusting_period: DEFAULT_TRUSTING_PERIOD,
+                clock_drift: DEFAULT_CLOCK_DRIFT,
+            },
+            trust_height: trust_height as u64,
+            trust_hash: parse_hash(trust_hash)?,
+            verified: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Sets the trust threshold, trusting period and clock drift used to
+    /// verify headers.
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the verified header at `height`, verifying it and any headers
+    /// needed to reach it from the closest header verified so far.
+    pub async fn verify_to(&self, height: u64) -> Result<LightBlock> {
+        if height < self.trust_height {
+            return Err(Error::Tendermint(
+                "Cannot verify headers below the trusted height".into(),
+            ));
+        }
+
+        loop {
+            let trusted = match self.closest_verified(height) {
+                Some(trusted) => trusted,
+                None => self.fetch_trusted().await?,
+            };
+            let trusted_height = trusted.height().value();
+            if trusted_height == height {
+                return Ok(trusted);
+            }
+
+            let mut pivot = height;
+            loop {
+                let untrusted = self.fetch(pivot).await?;
+                match self
+                    .verifier
+                    .verify(&untrusted, &trusted, &self.options, Time::now())
+                {
+                    Verdict::Success => {
+                        self.insert_verified(untrusted);
+                        break;
+                    }
+                    Verdict::NotEnoughTrust(_) if pivot > trusted_height + 1 => {
+                        pivot = bisect(trusted_height, pivot);
+                    }
+                    Verdict::NotEnoughTrust(tally) => {
+                        return Err(Error::Tendermint(format!(
+                            "Not enough trust to verify header: {:?}",
+                            tally
+                        )));
+                    }
+                    Verdict::Invalid(err) => {
+                        return Err(Error::Tendermint(format!("Invalid header: {:?}", err)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn closest_verified(&self, height: u64) -> Option<LightBlock> {
+        self.verified
+            .lock()
+            .unwrap()
+            .range(..=height)
+            .next_back()
+            .map(|(_, block)| block.clone())
+    }
+
+    fn insert_verified(&self, block: LightBlock) {
+        let mut verified = self.verified.lock().unwrap();
+        verified.insert(block.height().value(), block);
+        while verified.len() > MAX_VERIFIED_HEADERS {
+            let lowest = *verified.keys().next().unwrap();
+            verified.remove(&lowest);
+        }
+    }
+
+    async fn fetch_trusted(&self) -> Result<LightBlock> {
+        let block = self.fetch(self.trust_height).await?;
+        if block.signed_header.header().hash() != self.trust_hash {
+            return Err(Error::Tendermint(
+                "Header at trusted height does not match trusted hash".into(),
+            ));
+        }
+
+        self.insert_verified(block.clone());
+        Ok(block)
+    }
+
+    async fn fetch(&self, height: u64) -> Result<LightBlock> {
+        let client = self.endpoints.current();
+        let height = to_height(height)?;
+
+        let signed_header = client.commit(height).await?.signed_header;
+        let validators = fetch_validators(&client, height).await?;
+        let next_validators = fetch_validators(&client, height.increment()).await?;
+        let provider = client.status().await?.node_info.id;
+
+        Ok(LightBlock::new(
+            signed_header,
+            validators,
+            next_validators,
+            provider,
+        ))
+    }
+
+    /// Waits until the endpoint has committed the header at `height`.
+    async fn wait_for(&self, height: u64) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            let status = self.endpoints.current().status().await?;
+            if status.sync_info.latest_block_height.value() >= height {
+                return Ok(());
+            }
+            if start.elapsed() >= HEADER_TIMEOUT {
+                return Err(Error::Tendermint(format!(
+                    "Timed out waiting for header at height {}",
+                    height
+                )));
+            }
+
+            tokio::time::sleep(HEADER_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HeaderVerifier for LightClient {
+    async fn app_hash(&self, height: u64) -> Result<Vec<u8>> {
+        self.wait_for(height).await?;
+        let block = self.verify_to(height).await?;
+
+        Ok(block.signed_header.header().app_hash.value())
+    }
+}
+
+async fn fetch_validators(client: &tm::HttpClient, height: Height) -> Result<validator::Set> {
+    let res = client.validators(height, tm::Paging::All).await?;
+
+    Ok(validator::Set::without_proposer(res.validators))
+}
+
+fn to_height(height: u64) -> Result<Height> {
+    Height::try_from(height).map_err(|err| Error::Tendermint(err.to_string()))
+}
+
+fn parse_hash(hash: &str) -> Result<Hash> {
+    Hash::from_hex_upper(Algorithm::Sha256, hash.to_uppercase().as_str())
+        .map_err(|err| Error::Tendermint(format!("Invalid trust hash: {}", err)))
+}
+
+/// The height halfway between a trusted height and a header which could not
+/// be verified from it.
+fn bisect(trusted: u64, untrusted: u64) -> u64 {
+    trusted + (untrusted - trusted) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{MockRpc, TestChain};
+    use super::*;
+    use crate::plugins::{Keystore, MemKeystore, Pubkey};
+    use tendermint_rpc::Client as _;
+
+    const VALIDATOR_SECRETS: [[u8; 32]; 2] = [[1; 32], [2; 32]];
+
+    fn pubkey(secret: &[u8; 32]) -> [u8; 32] {
+        match MemKeystore::from_secret(secret).unwrap().pubkey().unwrap() {
+            Pubkey::Ed25519(pubkey) => pubkey,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Starts a chain with two validators, one with two thirds of the voting
+    /// power, whose headers are signed by the validators which are signing.
+    fn signed_chain() -> (TestChain<u64>, MockRpc) {
+        let chain = TestChain::<u64>::with_validators(&[
+            (pubkey(&VALIDATOR_SECRETS[0]), 10),
+            (pubkey(&VALIDATOR_SECRETS[1]), 5),
+        ])
+        .unwrap();
+        let rpc = MockRpc::start_signed(chain.clone(), &VALIDATOR_SECRETS).unwrap();
+
+        (chain, rpc)
+    }
+
+    /// A light client trusting the header at height 1, with a trusting period
+    /// long enough to cover the test chain's genesis time.
+    async fn light_client(rpc: &MockRpc) -> LightClient {
+        let client = tm::HttpClient::new(rpc.url().as_str()).unwrap();
+        let trusted = client.commit(to_height(1).unwrap()).await.unwrap();
+        let trust_hash = trusted.signed_header.header().hash().to_string();
+        let endpoints = Arc::new(Endpoints::new(&[rpc.url().as_str()]).unwrap());
+
+        LightClient::new(endpoints, 1, trust_hash.as_str())
+            .unwrap()
+            .with_options(Options {
+                trust_threshold: Default::default(),
+                trusting_period: Duration::from_secs(100 * 365 * 24 * 60 * 60),
+                clock_drift: DEFAULT_CLOCK_DRIFT,
+            })
+    }
+
+    #[tokio::test]
+    async fn signed_headers() {
+        let (chain, rpc) = signed_chain();
+        for _ in 0..4 {
+            chain.make_block().unwrap();
+        }
+        let light_client = light_client(&rpc).await;
+
+        let block = light_client.verify_to(4).await.unwrap();
+        assert_eq!(block.height().value(), 4);
+        assert_eq!(
+            light_client.app_hash(5).await.unwrap(),
+            chain.block(4).unwrap().app_hash
+        );
+        assert!(light_client.verify_to(0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn not_enough_signatures() {
+        let (chain, rpc) = signed_chain();
+        for _ in 0..4 {
+            chain.make_block().unwrap();
+        }
+        let light_client = light_client(&rpc).await;
+
+        // The validator left signing has a third of the voting power, which
+        // is not over the trust threshold needed to skip ahead from the
+        // trusted header, nor over the two thirds needed for each header.
+        chain.set_signing(pubkey(&VALIDATOR_SECRETS[0]), false);
+        assert!(light_client.verify_to(4).await.is_err());
+        assert!(light_client.verify_to(2).await.is_err());
+
+        chain.set_signing(pubkey(&VALIDATOR_SECRETS[0]), true);
+        assert!(light_client.verify_to(4).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn forged_header() {
+        let (chain, rpc) = signed_chain();
+        for _ in 0..4 {
+            chain.make_block().unwrap();
+        }
+
+        // A header with an app hash the validators did not sign is rejected,
+        // whether it is reached by skipping ahead or from the header before.
+        rpc.forge_header(3);
+        let light_client = light_client(&rpc).await;
+        assert!(light_client.verify_to(3).await.is_err());
+        assert!(light_client.app_hash(3).await.is_err());
+        assert!(light_client.verify_to(2).await.is_ok());
+        assert!(light_client.verify_to(3).await.is_err());
+
+        // The trusted header itself is checked against the trusted hash.
+        let light_client = light_client(&rpc).await;
+        rpc.forge_header(1);
+        assert!(light_client.verify_to(2).await.is_err());
+    }
+
+    #[test]
+    fn bisect_heights() {
+        assert_eq!(bisect(10, 30), 20);
+        assert_eq!(bisect(10, 13), 11);
+        assert_eq!(bisect(10, 12), 11);
+
+        let mut pivot = 1000;
+        while pivot > 11 {
+            pivot = bisect(10, pivot);
+        }
+        assert_eq!(pivot, 11);
+    }
+
+    #[test]
+    fn trust_hash() {
+        let hex = "6B68DC3C5B9A8DB3D0B0E3C1E9B1C5E7E4A0E0A7F8C1C2E5D7D3B4C5A6F7E8D9";
+        let upper = parse_hash(hex).unwrap();
+        assert_eq!(parse_hash(hex.to_lowercase().as_str()).unwrap(), upper);
+        assert!(parse_hash("not a hash").is_err());
+        assert!(parse_hash("6B68").is_err());
+    }
+}
@@ -0,0 +1,934 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use prost::Message;
+use serde_json::{json, Value};
+use subtle_encoding::base64;
+use tendermint::block::header::Version;
+use tendermint::block::signed_header::SignedHeader;
+use tendermint::block::{parts, Commit, CommitSig, Header, Height, Id, Round};
+use tendermint::hash::{AppHash, Hash};
+use tendermint::{account, validator, Time};
+use tendermint_proto::google::protobuf::Timestamp;
+use tendermint_proto::types::{
+    CanonicalBlockId, CanonicalPartSetHeader, CanonicalVote, SignedMsgType,
+};
+use tungstenite::Message as WsMessage;
+
+use super::test_chain::{tx_hash, validator_address};
+use super::{App, TestChain};
+use crate::plugins::{Keystore, MemKeystore, Pubkey};
+use crate::state::State;
+use crate::{Error, Result};
+
+/// The JSON-RPC error code of requests which could not be handled.
+const INTERNAL_ERROR: i64 = -32603;
+
+/// The query clients subscribe to over a websocket to be sent new blocks.
+const NEW_BLOCK_QUERY: &str = "tm.event = 'NewBlock'";
+
+/// How often websocket connections check for new blocks to send.
+const WEBSOCKET_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A local Tendermint RPC endpoint backed by a [`TestChain`], so clients such
+/// as [`TendermintClient`](super::TendermintClient) can be tested end to end
+/// without running Tendermint.
+///
+/// The server listens on a free port on the loopback interface and implements
+/// the methods clients use to query, send transactions and verify headers:
+/// `abci_query`, `broadcast_tx_async`, `broadcast_tx_sync`,
+/// `broadcast_tx_commit`, `status`, `commit`, `validators` and `tx`.
+/// Transactions are checked and delivered by the chain, so
+/// `broadcast_tx_commit` always makes a block, while the other broadcast
+/// methods only make one if the chain has auto blocks enabled.
+///
+/// The same methods can be called over a websocket at `/websocket`, where
+/// clients can also subscribe to `NewBlock` events. An event is sent for each
+/// block made after the subscription, and the connection is dropped once the
+/// `MockRpc` is.
+///
+/// Headers commit to the chain's current validator set at every height, and
+/// are signed by the validators whose keys are given to
+/// [`MockRpc::start_signed`] while they are signing, as set with
+/// [`TestChain::set_signing`]. The header of the next block is served and
+/// reported as the latest as soon as the app hash it commits to is known, so
+/// proofs of the latest state can be verified without waiting for a block.
+pub struct MockRpc {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    forged: Arc<Mutex<BTreeSet<u64>>>,
+}
+
+impl MockRpc {
+    /// Starts serving requests for `chain` on a background thread, until the
+    /// returned `MockRpc` is dropped. Headers are served without signatures.
+    pub fn start<A>(chain: TestChain<A>) -> Result<Self>
+    where
+        A: App + 'static,
+        <A as State>::Encoding: Default,
+    {
+        Self::start_signed(chain, &[])
+    }
+
+    /// Starts serving requests for `chain` like [`MockRpc::start`], signing
+    /// the commit of every header with the validators' ed25519 secret keys in
+    /// `secrets`, so headers can be verified by a
+    /// [`LightClient`](super::LightClient).
+    pub fn start_signed<A>(chain: TestChain<A>, secrets: &[[u8; 32]]) -> Result<Self>
+    where
+        A: App + 'static,
+        <A as State>::Encoding: Default,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let forged = Arc::new(Mutex::new(BTreeSet::new()));
+
+        let mut signers = BTreeMap::new();
+        for secret in secrets {
+            let keystore = MemKeystore::from_secret(secret)?;
+            let pubkey = match keystore.pubkey()? {
+                Pubkey::Ed25519(pubkey) => pubkey,
+                _ => return Err(Error::Test("Validator keys must be ed25519".into())),
+            };
+            signers.insert(pubkey, keystore);
+        }
+
+        let server = Server {
+            chain,
+            rpc_addr: format!("tcp://{}", addr),
+            signers: Arc::new(signers),
+            forged: forged.clone(),
+            stopped: stopped.clone(),
+        };
+        let stopping = stopped.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        log::warn!("Mock RPC failed to accept connection: {}", err);
+                        continue;
+                    }
+                };
+
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = server.serve(stream) {
+                        log::debug!("Mock RPC connection closed: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(MockRpc {
+            addr,
+            stopped,
+            forged,
+        })
+    }
+
+    /// The address to create RPC clients with.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Serves the header at `height` with an app hash which its commit does
+    /// not sign, like a node trying to forge query results would.
+    pub fn forge_header(&self, height: u64) {
+        self.forged.lock().unwrap().insert(height);
+    }
+}
+
+impl Drop for MockRpc {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes the listener up so it sees it has been stopped.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+struct Server<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    chain: TestChain<A>,
+    rpc_addr: String,
+    signers: Arc<BTreeMap<[u8; 32], MemKeystore>>,
+    forged: Arc<Mutex<BTreeSet<u64>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl<A> Clone for Server<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    fn clone(&self) -> Self {
+        Server {
+            chain: self.chain.clone(),
+            rpc_addr: self.rpc_addr.clone(),
+            signers: self.signers.clone(),
+            forged: self.forged.clone(),
+            stopped: self.stopped.clone(),
+        }
+    }
+}
+
+/// A websocket client's subscription to `NewBlock` events.
+struct Subscription {
+    id: Value,
+    /// The height of the last block the client has been sent.
+    height: u64,
+}
+
+impl<A> Server<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    /// Answers the requests sent over one connection until it is closed.
+    fn serve(&self, stream: TcpStream) -> Result<()> {
+        // Clients send their HTTP requests as POSTs, so only websocket
+        // handshakes start with a GET.
+        let mut method = [0; 4];
+        stream.peek(&mut method)?;
+        if &method == b"GET " {
+            return self.serve_websocket(stream);
+        }
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        while let Some(body) = read_request(&mut reader)? {
+            let res = serde_json::to_vec(&self.handle(body.as_slice())).map_err(json_error)?;
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                res.len()
+            )?;
+            writer.write_all(res.as_slice())?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, body: &[u8]) -> Value {
+        let req: Value = match serde_json::from_slice(body) {
+            Ok(req) => req,
+            Err(err) => return rpc_error(Value::Null, err.to_string()),
+        };
+        let id = req["id"].clone();
+        let method = req["method"].as_str().unwrap_or_default();
+
+        rpc_response(id, self.call(method, &req["params"]))
+    }
+
+    /// Answers the requests sent over a websocket, and sends each block made
+    /// while the client is subscribed, until the server is stopped.
+    ///
+    /// The connection is then dropped without a closing handshake, like a
+    /// node which has gone away.
+    fn serve_websocket(&self, stream: TcpStream) -> Result<()> {
+        let mut socket = tungstenite::accept(stream).map_err(|err| Error::Test(err.to_string()))?;
+        socket
+            .get_ref()
+            .set_read_timeout(Some(WEBSOCKET_POLL_INTERVAL))?;
+
+        let mut subscription = None;
+        while !self.stopped.load(Ordering::SeqCst) {
+            match socket.read_message() {
+                Ok(WsMessage::Text(body)) => {
+                    let res = self.handle_websocket(body.as_bytes(), &mut subscription);
+                    socket
+                        .write_message(WsMessage::Text(res.to_string()))
+                        .map_err(ws_error)?;
+                }
+                // Pings are answered by the socket itself.
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(err) => return Err(ws_error(err)),
+            }
+
+            if let Some(subscription) = subscription.as_mut() {
+                while subscription.height < self.chain.height() {
+                    subscription.height += 1;
+                    let event = self.new_block_event(subscription)?;
+                    socket
+                        .write_message(WsMessage::Text(event.to_string()))
+                        .map_err(ws_error)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_websocket(&self, body: &[u8], subscription: &mut Option<Subscription>) -> Value {
+        let req: Value = match serde_json::from_slice(body) {
+            Ok(req) => req,
+            Err(err) => return rpc_error(Value::Null, err.to_string()),
+        };
+        let id = req["id"].clone();
+        let params = &req["params"];
+
+        let result = match req["method"].as_str().unwrap_or_default() {
+            "subscribe" if params["query"] == NEW_BLOCK_QUERY => {
+                *subscription = Some(Subscription {
+                    id: id.clone(),
+                    height: self.chain.height(),
+                });
+                Ok(json!({}))
+            }
+            "subscribe" => Err(Error::Test(format!(
+                "Query not supported by mock RPC: {}",
+                params["query"]
+            ))),
+            "unsubscribe" | "unsubscribe_all" => {
+                *subscription = None;
+                Ok(json!({}))
+            }
+            method => self.call(method, params),
+        };
+
+        rpc_response(id, result)
+    }
+
+    /// Builds the `NewBlock` event for the block at the subscription's
+    /// height. The chain only keeps the results of transactions, so the
+    /// block is sent without them.
+    fn new_block_event(&self, subscription: &Subscription) -> Result<Value> {
+        let height = subscription.height;
+        let header = self.header(height)?;
+        let last_commit = if height > 1 {
+            serde_json::to_value(self.signed_header(height - 1)?.commit).map_err(json_error)?
+        } else {
+            Value::Null
+        };
+        let id = match &subscription.id {
+            Value::String(id) => id.clone(),
+            id => id.to_string(),
+        };
+
+        Ok(json!({
+            "jsonrpc": "2.0",
+            "id": format!("{}#event", id),
+            "result": {
+                "query": NEW_BLOCK_QUERY,
+                "data": {
+                    "type": "tendermint/event/NewBlock",
+                    "value": {
+                        "block": {
+                            "header": serde_json::to_value(header).map_err(json_error)?,
+                            "data": {"txs": []},
+                            "evidence": {"evidence": []},
+                            "last_commit": last_commit,
+                        },
+                        "result_begin_block": null,
+                        "result_end_block": null,
+                    },
+                },
+                "events": {
+                    "tm.event": ["NewBlock"],
+                },
+            },
+        }))
+    }
+
+    fn call(&self, method: &str, params: &Value) -> Result<Value> {
+        match method {
+            "abci_query" => self.abci_query(params),
+            "broadcast_tx_async" => self.broadcast_tx(params, false),
+            "broadcast_tx_sync" => self.broadcast_tx(params, true),
+            "broadcast_tx_commit" => self.broadcast_tx_commit(params),
+            "status" => self.status(),
+            "commit" => self.commit(params),
+            "validators" => self.validators(params),
+            "tx" => self.tx(params),
+            _ => Err(Error::Test(format!(
+                "Method not supported by mock RPC: {}",
+                method
+            ))),
+        }
+    }
+
+    fn abci_query(&self, params: &Value) -> Result<Value> {
+        let path = params["path"].as_str().unwrap_or_default().to_string();
+        let data = from_hex(params["data"].as_str().unwrap_or_default())?;
+        let height = params["height"]
+            .as_str()
+            .unwrap_or("0")
+            .parse()
+            .map_err(|_| Error::Test("Invalid query height".into()))?;
+        let res = self.chain.abci_query(path, data, height)?;
+
+        Ok(json!({
+            "response": {
+                "code": res.code,
+                "log": res.log,
+                "info": res.info,
+                "index": res.index.to_string(),
+                "key": to_base64(res.key.as_slice()),
+                "value": to_base64(res.value.as_slice()),
+                "proofOps": null,
+                "height": res.height.to_string(),
+                "codespace": res.codespace,
+            }
+        }))
+    }
+
+    /// Handles `broadcast_tx_sync`, which reports the result of `CheckTx`, and
+    /// `broadcast_tx_async`, which does not.
+    fn broadcast_tx(&self, params: &Value, sync: bool) -> Result<Value> {
+        let tx = tx_param(params)?;
+        let hash = tx_hash(tx.as_slice());
+        let res = self.chain.check_tx(tx)?;
+        if res.code == 0 && self.chain.auto_block() {
+            self.chain.make_block()?;
+        }
+
+        let (code, log) = if sync {
+            (res.code, res.log)
+        } else {
+            (0, String::new())
+        };
+        Ok(json!({
+            "code": code,
+            "data": "",
+            "log": log,
+            "hash": to_hex(&hash),
+        }))
+    }
+
+    fn broadcast_tx_commit(&self, params: &Value) -> Result<Value> {
+        let tx = tx_param(params)?;
+        let hash = tx_hash(tx.as_slice());
+        let check_tx = self.chain.check_tx(tx)?;
+        if check_tx.code != 0 {
+            return Ok(json!({
+                "check_tx": tx_result(check_tx.code, check_tx.log.as_str()),
+                "deliver_tx": tx_result(0, ""),
+                "hash": to_hex(&hash),
+                "height": "0",
+            }));
+        }
+
+        self.chain.make_block()?;
+        let (height, deliver_tx) = self
+            .chain
+            .tx_result(&hash)
+            .ok_or_else(|| Error::Test("Transaction was not delivered".into()))?;
+
+        Ok(json!({
+            "check_tx": tx_result(check_tx.code, check_tx.log.as_str()),
+            "deliver_tx": tx_result(deliver_tx.code, deliver_tx.log.as_str()),
+            "hash": to_hex(&hash),
+            "height": height.to_string(),
+        }))
+    }
+
+    /// Reports the next block as the latest, since its header is already
+    /// served.
+    fn status(&self) -> Result<Value> {
+        let height = self.chain.height() + 1;
+        let header = self.header(height)?;
+
+        Ok(json!({
+            "node_info": {
+                "protocol_version": {"p2p": "8", "block": "11", "app": "0"},
+                "id": to_hex(&[0; 20]),
+                "listen_addr": self.rpc_addr,
+                "network": self.chain.chain_id(),
+                "version": "0.34.0",
+                "channels": "40202122233038606100",
+                "moniker": "mock",
+                "other": {"tx_index": "on", "rpc_address": self.rpc_addr},
+            },
+            "sync_info": {
+                "latest_block_hash": serde_json::to_value(header.hash()).map_err(json_error)?,
+                "latest_app_hash": to_hex(self.chain.app_hash().as_slice()),
+                "latest_block_height": height.to_string(),
+                "latest_block_time": serde_json::to_value(header.time).map_err(json_error)?,
+                "catching_up": false,
+            },
+            "validator_info": {
+                "address": to_hex(&[0; 20]),
+                "pub_key": {
+                    "type": "tendermint/PubKeyEd25519",
+                    "value": to_base64(&[0; 32]),
+                },
+                "voting_power": "0",
+            },
+        }))
+    }
+
+    fn commit(&self, params: &Value) -> Result<Value> {
+        let height = match params["height"].as_str() {
+            Some(height) => height.parse()?,
+            None => self.chain.height() + 1,
+        };
+        let signed_header = self.signed_header(height)?;
+
+        Ok(json!({
+            "signed_header": serde_json::to_value(signed_header).map_err(json_error)?,
+            "canonical": true,
+        }))
+    }
+
+    /// Builds the header at `height` with the commit signing it, giving it an
+    /// unsigned app hash if it has been forged.
+    fn signed_header(&self, height: u64) -> Result<SignedHeader> {
+        let mut header = self.header(height)?;
+        let block_id = Id {
+            hash: header.hash(),
+            part_set_header: parts::Header::new(1, header.hash()).map_err(tm_error)?,
+        };
+        let signatures = self.commit_sigs(&header, &block_id)?;
+        let commit = Commit {
+            height: header.height,
+            round: Round::default(),
+            block_id,
+            signatures,
+        };
+        if self.forged.lock().unwrap().contains(&height) {
+            header.app_hash = AppHash::try_from(vec![0xff; 32]).map_err(tm_error)?;
+        }
+
+        SignedHeader::new(header, commit).map_err(tm_error)
+    }
+
+    fn validators(&self, params: &Value) -> Result<Value> {
+        let height = match params["height"].as_str() {
+            Some(height) => height.parse()?,
+            None => self.chain.height() + 1,
+        };
+        self.header(height)?;
+        let validators: Vec<_> = self
+            .chain
+            .validators()
+            .iter()
+            .map(|(pubkey, power)| validator_json(pubkey, *power))
+            .collect();
+
+        Ok(json!({
+            "block_height": height.to_string(),
+            "count": validators.len().to_string(),
+            "total": validators.len().to_string(),
+            "validators": validators,
+        }))
+    }
+
+    fn tx(&self, params: &Value) -> Result<Value> {
+        let hash_param = params["hash"].as_str().unwrap_or_default();
+        let hash_bytes = if hash_param.len() == 64 {
+            from_hex(hash_param)?
+        } else {
+            from_base64(hash_param)?
+        };
+        let hash = <[u8; 32]>::try_from(hash_bytes.as_slice())
+            .map_err(|_| Error::Test("Invalid transaction hash".into()))?;
+        let (height, deliver_tx) = self
+            .chain
+            .tx_result(&hash)
+            .ok_or_else(|| Error::Test(format!("Transaction {} not found", to_hex(&hash))))?;
+
+        // The chain only keeps the results of transactions, so the
+        // transaction itself is left empty.
+        Ok(json!({
+            "hash": to_hex(&hash),
+            "height": height.to_string(),
+            "index": 0,
+            "tx_result": tx_result(deliver_tx.code, deliver_tx.log.as_str()),
+            "tx": "",
+            "proof": null,
+        }))
+    }
+
+    /// Builds the header of the block at `height`, which may be the next
+    /// block if the app hash it commits to is already known.
+    fn header(&self, height: u64) -> Result<Header> {
+        let next_height = self.chain.height() + 1;
+        let time = if height == next_height {
+            self.chain.time()
+        } else if height > next_height || height == 0 {
+            return Err(Error::Test(format!("No header at height {}", height)));
+        } else {
+            self.chain
+                .block(height)
+                .ok_or_else(|| Error::Test(format!("No header at height {}", height)))?
+                .time
+        };
+        let app_hash = self
+            .chain
+            .block(height - 1)
+            .map(|block| block.app_hash)
+            .unwrap_or_default();
+        let validators_hash = self.validator_set()?.hash();
+
+        Ok(Header {
+            version: Version { block: 11, app: 0 },
+            chain_id: self.chain.chain_id().parse().map_err(tm_error)?,
+            height: Height::try_from(height).map_err(tm_error)?,
+            time: Time::try_from(Timestamp {
+                seconds: time,
+                nanos: 0,
+            })
+            .map_err(tm_error)?,
+            last_block_id: None,
+            last_commit_hash: None,
+            data_hash: None,
+            validators_hash,
+            next_validators_hash: validators_hash,
+            consensus_hash: Hash::None,
+            app_hash: AppHash::try_from(app_hash).map_err(tm_error)?,
+            last_results_hash: None,
+            evidence_hash: None,
+            proposer_address: account::Id::new([0; 20]),
+        })
+    }
+
+    fn validator_set(&self) -> Result<validator::Set> {
+        let validators = self
+            .chain
+            .validators()
+            .iter()
+            .map(|(pubkey, power)| serde_json::from_value(validator_json(pubkey, *power)))
+            .collect::<std::result::Result<_, _>>()
+            .map_err(json_error)?;
+
+        Ok(validator::Set::without_proposer(validators))
+    }
+
+    /// Signs a precommit for `block_id` with each validator in the set which
+    /// has a known key and is signing, in the order of the set.
+    fn commit_sigs(&self, header: &Header, block_id: &Id) -> Result<Vec<CommitSig>> {
+        let vote = CanonicalVote {
+            r#type: SignedMsgType::Precommit as i32,
+            height: header.height.value() as i64,
+            round: 0,
+            block_id: Some(CanonicalBlockId {
+                hash: block_id.hash.as_bytes().to_vec(),
+                part_set_header: Some(CanonicalPartSetHeader {
+                    total: block_id.part_set_header.total,
+                    hash: block_id.part_set_header.hash.as_bytes().to_vec(),
+                }),
+            }),
+            timestamp: Some(header.time.into()),
+            chain_id: header.chain_id.to_string(),
+        };
+        let mut sign_bytes = vec![];
+        vote.encode_length_delimited(&mut sign_bytes)
+            .map_err(|err| Error::Test(err.to_string()))?;
+
+        self.validator_set()?
+            .validators()
+            .iter()
+            .map(|info| {
+                let pubkey = <[u8; 32]>::try_from(info.pub_key.to_bytes().as_slice())
+                    .map_err(|_| Error::Test("Invalid validator key".into()))?;
+                let signer = match self.signers.get(&pubkey) {
+                    Some(signer) if self.chain.signing(pubkey) => signer,
+                    _ => return Ok(CommitSig::BlockIdFlagAbsent),
+                };
+
+                serde_json::from_value(json!({
+                    "block_id_flag": 2,
+                    "validator_address": to_hex(validator_address(&pubkey).as_slice()),
+                    "timestamp": serde_json::to_value(header.time).map_err(json_error)?,
+                    "signature": to_base64(&signer.sign(sign_bytes.as_slice())?),
+                }))
+                .map_err(json_error)
+            })
+            .collect()
+    }
+}
+
+/// A validator as the `validators` method returns it.
+fn validator_json(pubkey: &[u8; 32], power: u64) -> Value {
+    json!({
+        "address": to_hex(validator_address(pubkey).as_slice()),
+        "pub_key": {
+            "type": "tendermint/PubKeyEd25519",
+            "value": to_base64(pubkey),
+        },
+        "voting_power": power.to_string(),
+        "proposer_priority": "0",
+    })
+}
+
+/// Reads the body of the next HTTP request on a connection, or returns `None`
+/// once the client has closed it.
+fn read_request<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut content_length = 0;
+    let mut started = false;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            if started {
+                break;
+            }
+            continue;
+        }
+        started = true;
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(body.as_mut_slice())?;
+
+    Ok(Some(body))
+}
+
+fn rpc_error(id: Value, data: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": INTERNAL_ERROR,
+            "message": "Internal error",
+            "data": data,
+        },
+    })
+}
+
+fn rpc_response(id: Value, result: Result<Value>) -> Value {
+    match result {
+        Ok(result) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": result,
+        }),
+        Err(err) => rpc_error(id, err.to_string()),
+    }
+}
+
+fn tx_result(code: u32, log: &str) -> Value {
+    json!({
+        "code": code,
+        "data": "",
+        "log": log,
+        "info": "",
+        "gas_wanted": "0",
+        "gas_used": "0",
+        "events": [],
+        "codespace": "",
+    })
+}
+
+fn tx_param(params: &Value) -> Result<Vec<u8>> {
+    from_base64(params["tx"].as_str().unwrap_or_default())
+}
+
+fn json_error(err: serde_json::Error) -> Error {
+    Error::Test(err.to_string())
+}
+
+fn tm_error(err: tendermint::Error) -> Error {
+    Error::Tendermint(err.to_string())
+}
+
+fn ws_error(err: tungstenite::Error) -> Error {
+    Error::Test(err.to_string())
+}
+
+pub(super) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return Err(Error::Test("Hex string has odd length".into()));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| Error::Test("Invalid hex string".into()))
+        })
+        .collect()
+}
+
+fn to_base64(bytes: &[u8]) -> String {
+    String::from_utf8(base64::encode(bytes)).expect("Base64 is always valid UTF-8")
+}
+
+fn from_base64(encoded: &str) -> Result<Vec<u8>> {
+    base64::decode(encoded).map_err(|err| Error::Test(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{collect_tx_handles, BroadcastMode, TendermintClient, TxHandle, TxHash};
+    use super::*;
+    use crate::call::Call;
+    use crate::client::Client;
+    use crate::encoding::{Decode, Encode};
+    use crate::plugins::{MemKeystore, NoncePlugin, NonceQuery, SignerPlugin};
+    use crate::query::Query;
+    use futures_lite::StreamExt;
+    use std::time::{Duration, Instant};
+    use tendermint_rpc as tm;
+
+    #[derive(State, Encode, Decode, Call, Query, Client)]
+    pub struct Counter {
+        pub count: u64,
+    }
+
+    #[orga::methods(client)]
+    impl Counter {
+        #[call]
+        pub fn increment(&mut self) -> Result<()> {
+            self.count += 1;
+
+            Ok(())
+        }
+    }
+
+    type CounterApp = SignerPlugin<NoncePlugin<Counter>>;
+
+    #[test]
+    fn encodings() {
+        assert_eq!(to_hex(&[0, 1, 171, 255]), "0001ABFF");
+        assert_eq!(from_hex("0001abFF").unwrap(), vec![0, 1, 171, 255]);
+        assert!(from_hex("123").is_err());
+        assert!(from_hex("zz").is_err());
+        assert_eq!(
+            from_base64(to_base64(&[1, 2, 3]).as_str()).unwrap(),
+            vec![1, 2, 3]
+        );
+
+        let mut reader = "POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}".as_bytes();
+        assert_eq!(read_request(&mut reader).unwrap(), Some(b"{}".to_vec()));
+        assert_eq!(read_request(&mut reader).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn tendermint_client() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let rpc = MockRpc::start(chain.clone()).unwrap();
+        let mut client = TendermintClient::<CounterApp>::new(rpc.url().as_str()).unwrap();
+        client.set_nonce_dir(chain.nonce_dir());
+        assert_eq!(client.chain_id().await.unwrap(), chain.chain_id());
+
+        let keystore = Arc::new(MemKeystore::from_secret(&[1; 32]).unwrap());
+        client
+            .with_keystore(keystore.clone())
+            .increment()
+            .await
+            .unwrap();
+        let query = || NonceQuery::Inner(counter_query::Query::This);
+        let count = client.query(query(), |state| Ok(state.count)).await;
+        assert_eq!(count.unwrap(), 1);
+
+        // CheckTx failures are returned as errors.
+        assert!(client.broadcast(vec![1, 2, 3]).await.is_err());
+
+        client.set_broadcast_mode(BroadcastMode::Sync);
+        let mut signed_client = client.with_keystore(keystore);
+        let handles = collect_tx_handles(signed_client.increment()).await.unwrap();
+        assert_eq!(handles.len(), 1);
+        handles[0].wait(Duration::from_secs(5)).await.unwrap();
+        let count = client.query(query(), |state| Ok(state.count)).await;
+        assert_eq!(count.unwrap(), 2);
+
+        // Transactions which are not indexed yet are polled for until the
+        // timeout, but other errors are returned right away.
+        let unknown = TxHandle::new(
+            TxHash::new([0; 32]),
+            tm::HttpClient::new(rpc.url().as_str()).unwrap(),
+        );
+        assert!(unknown.wait(Duration::from_millis(100)).await.is_err());
+        let unreachable = TxHandle::new(
+            *handles[0].hash(),
+            tm::HttpClient::new("http://127.0.0.1:1").unwrap(),
+        );
+        let start = Instant::now();
+        assert!(unreachable.wait(Duration::from_secs(5)).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn broadcast_failover() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let rpc = MockRpc::start(chain.clone()).unwrap();
+        let mut client = TendermintClient::<CounterApp>::with_endpoints(&[
+            "http://127.0.0.1:1",
+            rpc.url().as_str(),
+        ])
+        .unwrap();
+        client.set_nonce_dir(chain.nonce_dir());
+
+        // The first endpoint refuses connections, so the call is sent to the
+        // second one.
+        let keystore = Arc::new(MemKeystore::from_secret(&[1; 32]).unwrap());
+        let mut signed_client = client.with_keystore(keystore);
+        let handles = collect_tx_handles(signed_client.increment()).await.unwrap();
+        assert_eq!(handles.len(), 1);
+        handles[0].wait(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(client.endpoints().current_addr(), rpc.url().as_str());
+    }
+
+    #[tokio::test]
+    async fn watch() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let rpc = MockRpc::start(chain.clone()).unwrap();
+        let client = TendermintClient::<CounterApp>::new(rpc.url().as_str()).unwrap();
+        let keystore = Arc::new(MemKeystore::from_secret(&[1; 32]).unwrap());
+        let mut signed_client = chain.signed_client(keystore);
+
+        let query = NonceQuery::Inner(counter_query::Query::This);
+        let counts = client.watch(query, |state| Ok(state.count)).await.unwrap();
+        futures_lite::pin!(counts);
+        let wait = Duration::from_millis(500);
+
+        // The current value is yielded right away, without waiting for a
+        // block.
+        assert_eq!(counts.next().await.unwrap().unwrap(), 0);
+
+        // Each change is yielded once, and blocks which leave the value
+        // unchanged yield nothing.
+        signed_client.increment().await.unwrap();
+        assert_eq!(counts.next().await.unwrap().unwrap(), 1);
+        chain.make_block().unwrap();
+        chain.make_block().unwrap();
+        assert!(tokio::time::timeout(wait, counts.next()).await.is_err());
+        signed_client.increment().await.unwrap();
+        assert_eq!(counts.next().await.unwrap().unwrap(), 2);
+
+        // Queries which fail are yielded as errors, without ending the
+        // stream.
+        rpc.forge_header(chain.height() + 2);
+        chain.make_block().unwrap();
+        assert!(counts.next().await.unwrap().is_err());
+        chain.make_block().unwrap();
+        assert!(tokio::time::timeout(wait, counts.next()).await.is_err());
+
+        // The stream ends once the subscription is closed.
+        drop(rpc);
+        assert!(counts.next().await.is_none());
+    }
+}
@@ -22,8 +22,25 @@ pub use tendermint_proto::abci as messages;
 use tendermint_proto::abci::request::Value as Req;
 use tendermint_proto::abci::response::Value as Res;
 
+mod headers;
+pub use headers::*;
+
+mod light_client;
+pub use light_client::*;
+
+mod test_chain;
+pub use test_chain::*;
+
+mod mock_rpc;
+pub use mock_rpc::*;
+
+mod simulation;
+pub use simulation::*;
+
 mod tendermint_client;
-pub use tendermint_client::TendermintClient;
+pub use tendermint_client::{
+    collect_tx_handles, BroadcastMode, TendermintClient, TxHandle, TxHash,
+};
 
 /// Top-level struct for running an ABCI application. Maintains an ABCI server,
 /// mempool, and handles committing data to the store.
@@ -1,13 +1,14 @@
 use super::{ABCIStateMachine, ABCIStore, App, Application, WrappedMerk};
 use crate::call::Call;
+use crate::context::Context;
 use crate::encoding::{Decode, Encode};
 use crate::merk::{BackingStore, MerkStore};
 use crate::plugins::{ABCICall, ABCIPlugin};
-use crate::query::Query;
+use crate::query::{Query, QueryBatch, QueryValue};
 use crate::state::State;
 use crate::store::{Read, Shared, Store, Write};
 use crate::tendermint::Tendermint;
-use crate::Result;
+use crate::{Error, Result};
 use home::home_dir;
 use std::borrow::Borrow;
 use std::marker::PhantomData;
@@ -15,6 +16,26 @@ use std::path::PathBuf;
 use std::process::Stdio;
 use tendermint_proto::abci::*;
 
+/// The ABCI query path of requests whose data is a [`QueryBatch`], which are
+/// answered with one proof covering every query in the batch.
+pub const BATCH_QUERY_PATH: &str = "batch";
+
+/// The ABCI query path of requests which are answered with the encoded result
+/// of the `#[query]` method the query reaches, instead of a proof. Nodes only
+/// answer these once enabled with [`Node::value_queries`], since the result
+/// can not be verified and computing it may be costly, so they should only be
+/// enabled on nodes serving trusted clients.
+pub const VALUE_QUERY_PATH: &str = "value";
+
+/// The `info` of responses to value queries, marking the response value as
+/// an unproven result rather than a proof.
+pub const UNVERIFIED_QUERY_INFO: &str = "unverified";
+
+/// The code of `CheckTx` responses rejecting a call with a nonce error, e.g.
+/// one signed with a nonce the chain already accepted, which tells clients to
+/// resync their local nonce.
+pub const INVALID_NONCE_CODE: u32 = 2;
+
 pub struct Node<A> {
     _app: PhantomData<A>,
     tm_home: PathBuf,
@@ -26,6 +47,7 @@ pub struct Node<A> {
     p2p_persistent_peers: Option<Vec<String>>,
     stdout: Stdio,
     stderr: Stdio,
+    value_queries: bool,
 }
 
 impl<A: App> Node<A>
@@ -57,6 +79,7 @@ where
             p2p_persistent_peers: None,
             stdout: Stdio::null(),
             stderr: Stdio::null(),
+            value_queries: false,
         }
     }
 
@@ -88,7 +111,8 @@ where
 
             tm_process.start();
         });
-        let app = InternalApp::<ABCIPlugin<A>>::new();
+        let mut app = InternalApp::<ABCIPlugin<A>>::new();
+        app.value_queries = self.value_queries;
         let store = MerkStore::new(self.merk_home.clone());
 
         // Start ABCI server
@@ -151,6 +175,14 @@ where
 
         self
     }
+
+    /// Sets whether queries sent to [`VALUE_QUERY_PATH`] are answered. They
+    /// are rejected by default.
+    pub fn value_queries(mut self, enabled: bool) -> Self {
+        self.value_queries = enabled;
+
+        self
+    }
 }
 
 impl<A> InternalApp<ABCIPlugin<A>>
@@ -173,6 +205,7 @@ where
         let mut state = <ABCIPlugin<A> as State>::create(store.clone(), data)?;
         let res = op(&mut state);
         let flushed = state.flush()?;
+        <ABCIPlugin<A> as State>::prune(store.clone(), &flushed)?;
         store.put(vec![], flushed.encode()?)?;
 
         Ok(res)
@@ -240,7 +273,10 @@ where
         })?;
         let mut check_tx_res = ResponseCheckTx::default();
         if let Err(err) = run_res {
-            check_tx_res.code = 1;
+            check_tx_res.code = match err {
+                Error::Nonce(_) => INVALID_NONCE_CODE,
+                _ => 1,
+            };
             check_tx_res.log = err.to_string();
         }
 
@@ -249,17 +285,42 @@ where
 
     fn query(&self, merk_store: Shared<MerkStore>, req: RequestQuery) -> Result<ResponseQuery> {
         let query_bytes = req.data;
-        let backing_store: BackingStore = merk_store.clone().into();
         let store_height = merk_store.borrow().height()?;
+
+        // Only the latest state is kept, which clients check against the app
+        // hash in the header of the next block.
+        if req.height != 0 && req.height as u64 != store_height {
+            return Ok(ResponseQuery {
+                code: 1,
+                height: req.height,
+                log: format!("State at height {} is not available", req.height),
+                ..Default::default()
+            });
+        }
+        let value_query = req.path == VALUE_QUERY_PATH;
+        if value_query && !self.value_queries {
+            return Ok(ResponseQuery {
+                code: 1,
+                height: store_height as i64,
+                log: "Value queries are not enabled on this node".into(),
+                ..Default::default()
+            });
+        }
+
+        let backing_store: BackingStore = merk_store.clone().into();
         let store = Store::new(backing_store.clone());
         let state_bytes = store.get(&[])?.unwrap();
         let data: <ABCIPlugin<A> as State>::Encoding = Decode::decode(state_bytes.as_slice())?;
         let state = <ABCIPlugin<A> as State>::create(store, data)?;
 
-        // Check which keys are accessed by the query and build a proof
-        let query_decode_res = Decode::decode(query_bytes.as_slice());
-        let query = match query_decode_res {
-            Ok(query) => query,
+        // Check which keys are accessed by the queries and build a proof
+        let query_decode_res = if req.path == BATCH_QUERY_PATH {
+            Decode::decode(query_bytes.as_slice())
+        } else {
+            Decode::decode(query_bytes.as_slice()).map(|query| QueryBatch(vec![query]))
+        };
+        let queries: QueryBatch<<ABCIPlugin<A> as Query>::Query> = match query_decode_res {
+            Ok(queries) => queries,
             Err(err) => {
                 return Ok(ResponseQuery {
                     code: 1,
@@ -270,7 +331,19 @@ where
             }
         };
 
-        if let Err(err) = state.query(query) {
+        if value_query {
+            Context::add(QueryValue::default());
+        }
+        let query_res = queries.query(&state);
+        let query_value = if value_query {
+            let value = Context::resolve::<QueryValue>().map_or(Ok(None), |ctx| ctx.take());
+            Context::remove::<QueryValue>();
+            value
+        } else {
+            Ok(None)
+        };
+
+        if let Err(err) = query_res {
             return Ok(ResponseQuery {
                 code: 1,
                 height: store_height as i64,
@@ -278,6 +351,31 @@ where
                 ..Default::default()
             });
         }
+
+        if value_query {
+            return Ok(match query_value {
+                Ok(Some(value)) => ResponseQuery {
+                    code: 0,
+                    height: store_height as i64,
+                    value,
+                    info: UNVERIFIED_QUERY_INFO.into(),
+                    ..Default::default()
+                },
+                Ok(None) => ResponseQuery {
+                    code: 1,
+                    height: store_height as i64,
+                    log: "Query did not reach a method which returns a value".into(),
+                    ..Default::default()
+                },
+                Err(err) => ResponseQuery {
+                    code: 1,
+                    height: store_height as i64,
+                    log: err.to_string(),
+                    ..Default::default()
+                },
+            });
+        }
+
         let proof_builder = backing_store.into_proof_builder()?;
         let root_hash = merk_store.borrow().root_hash()?;
         let proof_bytes = proof_builder.build()?;
@@ -298,8 +396,9 @@ where
     }
 }
 
-struct InternalApp<A> {
+pub(super) struct InternalApp<A> {
     _app: PhantomData<A>,
+    pub(super) value_queries: bool,
 }
 
 impl<A: App> InternalApp<ABCIPlugin<A>>
@@ -307,6 +406,20 @@ where
     <A as State>::Encoding: Default,
 {
     pub fn new() -> Self {
-        Self { _app: PhantomData }
+        Self {
+            _app: PhantomData,
+            value_queries: false,
+        }
+    }
+}
+
+/// Returns the error for a transaction rejected by `CheckTx` with `code`,
+/// which is a nonce error if the transaction was rejected for its nonce.
+pub(super) fn check_tx_error(code: u32, log: &str) -> Error {
+    let msg = format!("CheckTx failed: {}", log);
+    if code == INVALID_NONCE_CODE {
+        Error::Nonce(msg)
+    } else {
+        Error::ABCI(msg)
     }
 }
@@ -0,0 +1,349 @@
+use tendermint_proto::google::protobuf::Timestamp;
+
+use super::messages::*;
+use super::mock_rpc::to_hex;
+use super::test_chain::{unique_chain_id, validator_address, ChainState};
+use super::App;
+use crate::state::State;
+use crate::{Error, Result};
+
+/// Runs several instances of an app in the current process as the nodes of
+/// one network, giving each of them the same blocks and checking that they
+/// all reach the same state.
+///
+/// Each node has its own `MerkStore` in a temporary directory, like a
+/// [`TestChain`](super::TestChain). Blocks are made with
+/// [`make_block`](Self::make_block), which returns an error as soon as a node
+/// disagrees with the first one on a `DeliverTx` response, the validator set
+/// updates, or the committed app hash.
+///
+/// Consensus behavior which the app can observe is configurable: the
+/// proposer of each block, which validators are reported as having signed
+/// the previous block, and evidence of validators signing twice at a height.
+///
+/// ```ignore
+/// let mut sim = Simulation::<MyApp>::new(4, &[([1; 32], 10), ([2; 32], 10)])?;
+/// sim.set_signing([2; 32], false);
+/// for _ in 0..100 {
+///     sim.make_block()?;
+/// }
+/// sim.double_sign([1; 32])?;
+/// sim.make_block()?;
+/// ```
+pub struct Simulation<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    nodes: Vec<ChainState<A>>,
+}
+
+impl<A> Simulation<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    /// Starts `nodes` instances of the app with the given validator public
+    /// keys and voting powers in their genesis, and makes the first block.
+    pub fn new(nodes: usize, validators: &[([u8; 32], u64)]) -> Result<Self> {
+        if nodes == 0 {
+            return Err(Error::Test("Simulation must have at least one node".into()));
+        }
+
+        let chain_id = unique_chain_id()?;
+        let nodes = (0..nodes)
+            .map(|index| {
+                let home = std::env::temp_dir().join(format!("orga-{}-{}", chain_id, index));
+                ChainState::new(chain_id.clone(), home, validators)
+            })
+            .collect::<Result<_>>()?;
+
+        let mut sim = Simulation { nodes };
+        sim.make_block()?;
+
+        Ok(sim)
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn chain_id(&self) -> String {
+        self.nodes[0].chain_id.clone()
+    }
+
+    /// The height of the last committed block.
+    pub fn height(&self) -> u64 {
+        self.nodes[0].height
+    }
+
+    /// The app hash every node committed in the last block.
+    pub fn app_hash(&self) -> Vec<u8> {
+        self.nodes[0].app_hash.clone()
+    }
+
+    /// The time of the next block, in seconds since the Unix epoch.
+    pub fn time(&self) -> i64 {
+        self.nodes[0].time
+    }
+
+    pub fn set_time(&mut self, seconds: i64) {
+        for node in self.nodes.iter_mut() {
+            node.time = seconds;
+        }
+    }
+
+    pub fn advance_time(&mut self, seconds: i64) {
+        for node in self.nodes.iter_mut() {
+            node.time += seconds;
+        }
+    }
+
+    /// Sets how many seconds the block time moves forward after each block.
+    pub fn set_block_time(&mut self, seconds: i64) {
+        for node in self.nodes.iter_mut() {
+            node.block_time = seconds;
+        }
+    }
+
+    /// The current validator public keys and voting powers, including the
+    /// updates returned by the app at the end of each block.
+    pub fn validators(&self) -> Vec<([u8; 32], u64)> {
+        self.nodes[0]
+            .validators
+            .iter()
+            .map(|(pubkey, power)| (*pubkey, *power))
+            .collect()
+    }
+
+    /// Sets whether the validator with public key `pubkey` is reported as
+    /// having signed the previous block in the `BeginBlock` of later blocks.
+    /// Validators which stop signing can be used to trigger downtime
+    /// penalties.
+    pub fn set_signing(&mut self, pubkey: [u8; 32], signing: bool) {
+        for node in self.nodes.iter_mut() {
+            if signing {
+                node.absent.remove(&pubkey);
+            } else {
+                node.absent.insert(pubkey);
+            }
+        }
+    }
+
+    /// Sets the validator which proposes every later block. If `None`, as it
+    /// is by default, the validators take turns in order of public key.
+    pub fn set_proposer(&mut self, pubkey: Option<[u8; 32]>) {
+        for node in self.nodes.iter_mut() {
+            node.proposer = pubkey;
+        }
+    }
+
+    /// Reports the validator with public key `pubkey` as having signed two
+    /// different blocks at the last height, as duplicate vote evidence in the
+    /// `BeginBlock` of the next block.
+    pub fn double_sign(&mut self, pubkey: [u8; 32]) -> Result<()> {
+        let node = &self.nodes[0];
+        let power = *node
+            .validators
+            .get(&pubkey)
+            .ok_or_else(|| Error::Test("Only validators can double sign".into()))?;
+        let total_power: u64 = node.validators.values().sum();
+        let time = node.blocks[&node.height].time;
+
+        let evidence = Evidence {
+            r#type: EvidenceType::DuplicateVote as i32,
+            validator: Some(Validator {
+                address: validator_address(&pubkey),
+                power: power as i64,
+            }),
+            height: node.height as i64,
+            time: Some(Timestamp {
+                seconds: time,
+                nanos: 0,
+            }),
+            total_voting_power: total_power as i64,
+        };
+        for node in self.nodes.iter_mut() {
+            node.evidence.push(evidence.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Runs `CheckTx` on an encoded transaction on every node, adding it to
+    /// the mempool to be included in the next block if it passes. Returns the
+    /// response of the first node, or an error if the nodes' responses differ.
+    pub fn check_tx(&mut self, tx: Vec<u8>) -> Result<ResponseCheckTx> {
+        let mut responses = self
+            .nodes
+            .iter_mut()
+            .map(|node| node.check_tx(tx.clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (index, res) in responses.iter().enumerate().skip(1) {
+            if res.code != responses[0].code {
+                return Err(Error::Test(format!(
+                    "Node {} returned code {} for CheckTx, but node 0 returned {}",
+                    index, res.code, responses[0].code
+                )));
+            }
+        }
+
+        Ok(responses.swap_remove(0))
+    }
+
+    /// Makes and commits a block containing the transactions in the mempool
+    /// on every node, returning their `DeliverTx` responses in order.
+    ///
+    /// Returns an error if any node disagrees with the first one on the
+    /// responses, the resulting validator set, or the committed app hash.
+    pub fn make_block(&mut self) -> Result<Vec<ResponseDeliverTx>> {
+        let mut results = self
+            .nodes
+            .iter_mut()
+            .map(|node| node.make_block())
+            .collect::<Result<Vec<_>>>()?;
+
+        let first = &self.nodes[0];
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            if results[index] != results[0] {
+                return Err(Error::Test(format!(
+                    "Node {} delivered transactions differently than node 0 at height {}",
+                    index, node.height
+                )));
+            }
+            if node.validators != first.validators {
+                return Err(Error::Test(format!(
+                    "Node {} has a different validator set than node 0 at height {}",
+                    index, node.height
+                )));
+            }
+            if node.app_hash != first.app_hash {
+                return Err(Error::Test(format!(
+                    "Node {} committed app hash {} at height {}, but node 0 committed {}",
+                    index,
+                    to_hex(node.app_hash.as_slice()),
+                    node.height,
+                    to_hex(first.app_hash.as_slice()),
+                )));
+            }
+        }
+
+        Ok(results.swap_remove(0))
+    }
+
+    /// Queries the committed state of the node at `index`, running `check`
+    /// over the state reconstructed from the proof once it has been checked
+    /// against the node's last app hash.
+    pub fn query<F, R>(&mut self, index: usize, query: A::Query, check: F) -> Result<R>
+    where
+        F: Fn(&A) -> Result<R>,
+    {
+        self.nodes
+            .get_mut(index)
+            .ok_or_else(|| Error::Test(format!("No node with index {}", index)))?
+            .query(query, check)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::abci::{BeginBlock, InitChain};
+    use crate::call::Call;
+    use crate::coins::{Coin, Staking, Symbol};
+    use crate::encoding::{Decode, Encode};
+    use crate::plugins::{BeginBlockCtx, InitChainCtx};
+    use crate::query::Query;
+
+    #[derive(State, Debug, Clone)]
+    pub struct Simp(());
+    impl Symbol for Simp {}
+
+    #[derive(State, Call, Query)]
+    pub struct Network {
+        staking: Staking<Simp>,
+    }
+
+    impl InitChain for Network {
+        fn init_chain(&mut self, ctx: &InitChainCtx) -> Result<()> {
+            for (index, validator) in ctx.validators.iter().enumerate() {
+                let address = [100 + index as u8; 32].into();
+                let coins = Coin::mint(validator.power);
+                self.staking
+                    .declare(address, validator.pubkey.into(), coins)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl BeginBlock for Network {
+        fn begin_block(&mut self, ctx: &BeginBlockCtx) -> Result<()> {
+            self.staking.begin_block(ctx)
+        }
+    }
+
+    fn is_validator(sim: &Simulation<Network>, pubkey: [u8; 32]) -> bool {
+        sim.validators().iter().any(|(key, _)| *key == pubkey)
+    }
+
+    #[test]
+    fn slashing() {
+        let validators = [([1; 32], 10), ([2; 32], 10), ([3; 32], 10)];
+        let mut sim = Simulation::<Network>::new(3, &validators).unwrap();
+        assert_eq!(sim.nodes(), 3);
+        assert_eq!(sim.validators(), validators.to_vec());
+
+        sim.set_proposer(Some([1; 32]));
+        sim.make_block().unwrap();
+        sim.make_block().unwrap();
+
+        // Transactions which can not be decoded are rejected by every node.
+        assert_ne!(sim.check_tx(vec![123]).unwrap().code, 0);
+
+        sim.double_sign([3; 32]).unwrap();
+        sim.make_block().unwrap();
+        assert!(!is_validator(&sim, [3; 32]));
+        assert!(sim.double_sign([3; 32]).is_err());
+
+        // Validators are slashed once they have been offline for more than
+        // `MAX_OFFLINE_BLOCKS` (100) blocks.
+        sim.set_proposer(None);
+        sim.set_signing([2; 32], false);
+        for _ in 0..100 {
+            sim.make_block().unwrap();
+        }
+        assert!(is_validator(&sim, [2; 32]));
+        sim.make_block().unwrap();
+        assert!(!is_validator(&sim, [2; 32]));
+        assert_eq!(sim.validators(), vec![([1; 32], 10)]);
+        assert_eq!(sim.height(), 105);
+    }
+
+    static NEXT_VALUE: AtomicU64 = AtomicU64::new(0);
+
+    #[derive(State, Encode, Decode, Call, Query)]
+    pub struct Nondeterministic {
+        pub value: u64,
+    }
+
+    impl BeginBlock for Nondeterministic {
+        fn begin_block(&mut self, _ctx: &BeginBlockCtx) -> Result<()> {
+            self.value = NEXT_VALUE.fetch_add(1, Ordering::SeqCst);
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn disagreement() {
+        let mut sim = Simulation::<Nondeterministic>::new(1, &[]).unwrap();
+        sim.make_block().unwrap();
+
+        let err = Simulation::<Nondeterministic>::new(2, &[]).err().unwrap();
+        assert!(err.to_string().contains("committed app hash"));
+    }
+}
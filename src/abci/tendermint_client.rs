@@ -1,37 +1,223 @@
+use std::cell::RefCell;
 use std::convert::TryInto;
+use std::future::Future;
 use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures_lite::stream::{self, Stream, StreamExt};
 use tendermint_rpc as tm;
+use tm::query::EventType;
 use tm::Client as _;
+use tm::SubscriptionClient as _;
 
+use super::node::check_tx_error;
+use super::{
+    Endpoints, HeaderVerifier, LightClient, NodeHeaders, BATCH_QUERY_PATH, UNVERIFIED_QUERY_INFO,
+    VALUE_QUERY_PATH,
+};
 use crate::call::Call;
 use crate::client::{AsyncCall, Client};
+use crate::coins::Address;
 use crate::encoding::{Decode, Encode};
 use crate::merk::ABCIPrefixedProofStore;
-use crate::query::Query;
+use crate::plugins::{nonce_plugin_methods, NonceFile, NoncePlugin, NonceQuery, NonceSource};
+use crate::plugins::{ChainIdSource, SignerClient, SignerPlugin};
+use crate::query::{Query, QueryBatch};
 use crate::state::State;
 use crate::store::{Shared, Store};
 use crate::{Error, Result};
 
+pub use tendermint::abci::transaction::Hash as TxHash;
 pub use tm::endpoint::broadcast::tx_commit::Response as TxResponse;
 
+/// How long [`TxHandle::wait`] sleeps between polls for a transaction.
+const TX_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How many times a query is retried on the next endpoint by default.
+const DEFAULT_QUERY_RETRIES: usize = 3;
+
+/// How transactions are sent to the node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BroadcastMode {
+    /// Returns as soon as the node has received the transaction, without
+    /// checking it.
+    Async,
+    /// Returns once the transaction has passed `CheckTx` and entered the
+    /// mempool.
+    Sync,
+    /// Returns once the transaction has been included in a block, failing if
+    /// it could not be delivered.
+    Commit,
+}
+
+impl Default for BroadcastMode {
+    fn default() -> Self {
+        BroadcastMode::Commit
+    }
+}
+
+tokio::task_local! {
+    /// The handles of the transactions broadcast by the call running in
+    /// [`collect_tx_handles`].
+    static TX_HANDLES: RefCell<Vec<TxHandle>>;
+}
+
+/// A broadcast transaction, which can be awaited until it is included in a
+/// block. Transactions sent in [`BroadcastMode::Commit`] mode are already
+/// included by the time their handle is returned.
+#[derive(Clone)]
+pub struct TxHandle {
+    hash: TxHash,
+    client: tm::HttpClient,
+}
+
+impl TxHandle {
+    /// Returns a handle for the transaction with hash `hash`, e.g. one sent by
+    /// another process, which polls for it through `client`.
+    pub fn new(hash: TxHash, client: tm::HttpClient) -> Self {
+        TxHandle { hash, client }
+    }
+
+    pub fn hash(&self) -> &TxHash {
+        &self.hash
+    }
+
+    /// Polls the node until the transaction has been included in a block,
+    /// failing if it was not delivered successfully or is not found within
+    /// `timeout`.
+    pub async fn wait(&self, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            match self.client.tx(self.hash, false).await {
+                Ok(res) if res.tx_result.code.is_err() => {
+                    return Err(Error::ABCI(format!(
+                        "DeliverTx failed: {}",
+                        res.tx_result.log
+                    )));
+                }
+                Ok(_) => return Ok(()),
+                // The node returns an error until the transaction is indexed.
+                Err(err) if is_tx_not_found(&err) && start.elapsed() < timeout => {
+                    tokio::time::sleep(TX_POLL_INTERVAL).await
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Whether `err` is the error a node returns for a transaction it has not
+/// indexed, which may only mean it has not been included in a block yet.
+///
+/// Nodes answer with the JSON-RPC internal error code, which they use for any
+/// error raised while handling the request, so those are polled for until the
+/// timeout as well. Connection and response errors have other codes.
+fn is_tx_not_found(err: &tm::Error) -> bool {
+    matches!(err.code(), tm::error::Code::InternalError)
+}
+
+/// Runs `call`, which makes calls through a [`TendermintClient`], and returns
+/// the handles of the transactions it broadcast, in the order they were sent.
+///
+/// ```ignore
+/// client.set_broadcast_mode(BroadcastMode::Sync);
+/// let handles = collect_tx_handles(client.increment()).await?;
+/// handles[0].wait(Duration::from_secs(10)).await?;
+/// ```
+pub async fn collect_tx_handles<F>(call: F) -> Result<Vec<TxHandle>>
+where
+    F: Future<Output = Result<()>>,
+{
+    TX_HANDLES
+        .scope(RefCell::new(vec![]), async move {
+            call.await?;
+
+            Ok(TX_HANDLES.with(|handles| handles.take()))
+        })
+        .await
+}
+
 pub struct TendermintClient<T: Client<TendermintAdapter<T>>> {
     state_client: T::Client,
-    tm_client: tm::HttpClient,
+    adapter: TendermintAdapter<T>,
+    endpoints: Arc<Endpoints>,
 }
 
 impl<T: Client<TendermintAdapter<T>>> TendermintClient<T> {
     pub fn new(addr: &str) -> Result<Self> {
-        let tm_client = tm::HttpClient::new(addr)?;
-        let state_client = T::create_client(TendermintAdapter {
+        Self::with_endpoints(&[addr])
+    }
+
+    /// Creates a client which sends queries and calls to the given RPC
+    /// endpoints in turn, failing over to the next one when a request fails.
+    pub fn with_endpoints(addrs: &[&str]) -> Result<Self> {
+        let endpoints = Arc::new(Endpoints::new(addrs)?);
+        let adapter = TendermintAdapter {
             marker: std::marker::PhantomData,
-            client: tm_client.clone(),
-        });
+            endpoints: endpoints.clone(),
+            mode: Arc::new(Mutex::new(BroadcastMode::default())),
+            verifier: Arc::new(Mutex::new(Arc::new(NodeHeaders::new(endpoints.clone())))),
+            query_retries: Arc::new(Mutex::new(DEFAULT_QUERY_RETRIES)),
+        };
+        let state_client = T::create_client(adapter.clone());
         Ok(TendermintClient {
             state_client,
-            tm_client,
+            adapter,
+            endpoints,
         })
     }
+
+    pub fn endpoints(&self) -> &Arc<Endpoints> {
+        &self.endpoints
+    }
+
+    /// Sets where query proofs get the app hashes they are checked against.
+    /// Defaults to [`NodeHeaders`], which trusts the endpoints' headers.
+    pub fn set_header_verifier(&mut self, verifier: Arc<dyn HeaderVerifier>) {
+        *self.adapter.verifier.lock().unwrap() = verifier;
+    }
+
+    /// Verifies query proofs with a [`LightClient`] which trusts the header
+    /// with hash `trust_hash`, given as hex, at `trust_height`, instead of
+    /// trusting the endpoints' headers.
+    pub fn use_light_client(&mut self, trust_height: u32, trust_hash: &str) -> Result<()> {
+        let light_client = LightClient::new(self.endpoints.clone(), trust_height, trust_hash)?;
+        self.set_header_verifier(Arc::new(light_client));
+
+        Ok(())
+    }
+
+    /// Sets how many times a failed query is retried, moving on to the next
+    /// endpoint each time.
+    pub fn set_query_retries(&mut self, retries: usize) {
+        *self.adapter.query_retries.lock().unwrap() = retries;
+    }
+
+    /// Sets how calls made through the client are broadcast. In
+    /// [`BroadcastMode::Async`] and [`BroadcastMode::Sync`] mode calls return
+    /// before they are included in a block, and their handles can be
+    /// collected with [`collect_tx_handles`].
+    pub fn set_broadcast_mode(&mut self, mode: BroadcastMode) {
+        *self.adapter.mode.lock().unwrap() = mode;
+    }
+
+    pub fn broadcast_mode(&self) -> BroadcastMode {
+        *self.adapter.mode.lock().unwrap()
+    }
+
+    /// Fetches the id of the chain the node is running. Signed calls are bound
+    /// to it, and clients fetch it themselves before their first call.
+    pub async fn chain_id(&self) -> Result<String> {
+        self.adapter.clone().chain_id().await
+    }
+
+    /// Broadcasts a transaction which was encoded and signed elsewhere, e.g.
+    /// the encoding of a [`SignerCall`](crate::plugins::SignerCall) signed
+    /// offline, using the client's broadcast mode.
+    pub async fn broadcast(&self, tx: Vec<u8>) -> Result<TxHandle> {
+        self.adapter.broadcast(tx).await
+    }
 }
 
 impl<T: Client<TendermintAdapter<T>>> Deref for TendermintClient<T> {
@@ -53,50 +239,384 @@ impl<T: Client<TendermintAdapter<T>> + Query + State> TendermintClient<T> {
     where
         F: Fn(&T) -> Result<R>,
     {
+        self.adapter
+            .query_encoded(None, query.encode()?, &check)
+            .await
+    }
+
+    /// Sends several queries in one request, which the node answers with a
+    /// single proof covering all of them. `check` is run over the state
+    /// reconstructed from that proof, so it can compute the results of every
+    /// query at once, e.g. by collecting them into a `Vec` or a tuple.
+    pub async fn query_batch<F, R>(&self, queries: Vec<T::Query>, check: F) -> Result<R>
+    where
+        F: Fn(&T) -> Result<R>,
+    {
+        let query_bytes = QueryBatch(queries).encode()?;
+        self.adapter
+            .query_encoded(Some(BATCH_QUERY_PATH), query_bytes, &check)
+            .await
+    }
+
+    /// Asks the node for the encoded result of the `#[query]` method which
+    /// `query` reaches, e.g. an aggregate which would be costly to prove,
+    /// instead of a proof.
+    ///
+    /// The result is not verified in any way, so this should only be used
+    /// with nodes which are trusted, such as one run by the same operator.
+    /// Nodes only answer these queries once enabled with
+    /// [`Node::value_queries`](super::Node::value_queries).
+    pub async fn query_value<R: Decode>(&self, query: T::Query) -> Result<R> {
         let query_bytes = query.encode()?;
-        let res = self
-            .tm_client
-            .abci_query(None, query_bytes, None, true)
-            .await?;
-        let root_hash = match res.value[0..32].try_into() {
-            Ok(inner) => inner,
-            _ => {
-                return Err(Error::Tendermint(
-                    "Cannot convert result to fixed size array".into(),
-                ));
+        let path = VALUE_QUERY_PATH
+            .parse()
+            .map_err(|err: tendermint::Error| Error::Tendermint(err.to_string()))?;
+
+        let query_retries = *self.adapter.query_retries.lock().unwrap();
+        let mut retries = 0;
+        let res = loop {
+            let client = self.endpoints.current();
+            match client
+                .abci_query(Some(path.clone()), query_bytes.clone(), None, false)
+                .await
+            {
+                Ok(res) => break res,
+                Err(err) if retries >= query_retries => return Err(err.into()),
+                Err(_) => {
+                    self.endpoints.failover();
+                    retries += 1;
+                }
             }
         };
-        let proof_bytes = &res.value[32..];
 
-        let map = merk::proofs::query::verify(proof_bytes, root_hash)?;
-        let root_value = match map.get(&[])? {
-            Some(root_value) => root_value,
-            None => return Err(Error::ABCI("Missing root value".into())),
+        if res.code.is_err() {
+            return Err(Error::ABCI(format!("Query failed: {}", res.log)));
+        }
+        if res.info != UNVERIFIED_QUERY_INFO {
+            return Err(Error::ABCI("Node did not answer with a query value".into()));
+        }
+
+        Ok(R::decode(res.value.as_slice())?)
+    }
+
+    /// Returns a stream which yields the result of `check` over the proven
+    /// state whenever it changes. The query is run once right away, then
+    /// again each time a new block is committed, using the current endpoint's
+    /// websocket to be notified of new blocks.
+    ///
+    /// ```ignore
+    /// let mut balances = client
+    ///     .watch(query, |state| state.accounts.balance(address))
+    ///     .await?;
+    /// while let Some(balance) = balances.next().await {
+    ///     println!("balance: {}", balance?);
+    /// }
+    /// ```
+    pub async fn watch<'a, F, R>(
+        &'a self,
+        query: T::Query,
+        check: F,
+    ) -> Result<impl Stream<Item = Result<R>> + 'a>
+    where
+        F: Fn(&T) -> Result<R> + 'a,
+        R: Clone + PartialEq + 'a,
+    {
+        let url = websocket_url(self.endpoints.current_addr());
+        let (ws_client, driver) = tm::WebSocketClient::new(url.as_str()).await?;
+        tokio::spawn(async move {
+            if let Err(err) = driver.run().await {
+                log::warn!("Websocket client failed: {}", err);
+            }
+        });
+        let subscription = ws_client.subscribe(EventType::NewBlock.into()).await?;
+
+        let watch = Watch {
+            query_bytes: query.encode()?,
+            check,
+            last: None,
+            started: false,
+            subscription,
+            _ws_client: ws_client,
         };
-        let encoding = T::Encoding::decode(root_value)?;
-        let store: Shared<ABCIPrefixedProofStore> = Shared::new(ABCIPrefixedProofStore::new(map));
-        let state = T::create(Store::new(store.into()), encoding)?;
 
-        // TODO: retry logic
-        check(&state)
+        Ok(stream::unfold(watch, move |mut watch| async move {
+            loop {
+                if watch.started {
+                    match watch.subscription.next().await? {
+                        Ok(_) => {}
+                        Err(err) => return Some((Err(err.into()), watch)),
+                    }
+                }
+                watch.started = true;
+
+                let value = match self
+                    .adapter
+                    .query_encoded(None, watch.query_bytes.clone(), &watch.check)
+                    .await
+                {
+                    Ok(value) => value,
+                    Err(err) => return Some((Err(err), watch)),
+                };
+                if watch.last.as_ref() != Some(&value) {
+                    watch.last = Some(value.clone());
+                    return Some((Ok(value), watch));
+                }
+            }
+        }))
+    }
+}
+
+/// Checks a query proof against `app_hash` and reconstructs the app state it
+/// covers.
+pub(super) fn proven_state<T: State>(proof_bytes: &[u8], app_hash: &[u8]) -> Result<T> {
+    let root_hash = match app_hash.try_into() {
+        Ok(inner) => inner,
+        _ => {
+            return Err(Error::Tendermint(
+                "Cannot convert app hash to fixed size array".into(),
+            ));
+        }
+    };
+
+    let map = merk::proofs::query::verify(proof_bytes, root_hash)?;
+    let root_value = match map.get(&[])? {
+        Some(root_value) => root_value,
+        None => return Err(Error::ABCI("Missing root value".into())),
+    };
+    let encoding = T::Encoding::decode(root_value)?;
+    let store: Shared<ABCIPrefixedProofStore> = Shared::new(ABCIPrefixedProofStore::new(map));
+    T::create(Store::new(store.into()), encoding)
+}
+
+/// The state of a stream returned by [`TendermintClient::watch`].
+struct Watch<F, R> {
+    query_bytes: Vec<u8>,
+    check: F,
+    last: Option<R>,
+    started: bool,
+    subscription: tm::Subscription,
+    // Kept so the websocket connection stays open as long as the stream.
+    _ws_client: tm::WebSocketClient,
+}
+
+/// Converts the address of an HTTP RPC endpoint into the address of its
+/// websocket endpoint.
+fn websocket_url(addr: &str) -> String {
+    let addr = addr.trim_end_matches('/');
+    let addr = if let Some(rest) = addr.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = addr.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else if addr.contains("://") {
+        addr.to_string()
+    } else {
+        format!("ws://{}", addr)
+    };
+
+    format!("{}/websocket", addr)
+}
+
+impl<T> TendermintClient<SignerPlugin<NoncePlugin<T>>>
+where
+    T: Query + State,
+    NoncePlugin<T>:
+        Client<SignerClient<NoncePlugin<T>, TendermintAdapter<SignerPlugin<NoncePlugin<T>>>>>,
+{
+    /// Resets the local nonce of the client's account to the nonce of the last
+    /// call accepted on chain, e.g. after signed calls failed to be included
+    /// in a block.
+    ///
+    /// Clients also do this themselves when a node rejects a call for its
+    /// nonce.
+    pub async fn resync_nonce(&self) -> Result<u64> {
+        let address = self.state_client.keystore().address()?;
+        let nonce = self.adapter.clone().nonce(address).await?;
+
+        let domain = self.state_client.domain().await?;
+        NonceFile::open_under(self.state_client.nonce_dir(), domain.chain_id(), address)?
+            .set(nonce)?;
+
+        Ok(nonce)
     }
 }
 
 pub struct TendermintAdapter<T> {
     marker: std::marker::PhantomData<T>,
-    client: tm::HttpClient,
+    endpoints: Arc<Endpoints>,
+    mode: Arc<Mutex<BroadcastMode>>,
+    verifier: Arc<Mutex<Arc<dyn HeaderVerifier>>>,
+    query_retries: Arc<Mutex<usize>>,
+}
+
+impl<T> TendermintAdapter<T> {
+    /// Sends `tx` to the current endpoint, failing over to the next one if the
+    /// request fails. Transactions rejected by `CheckTx` or `DeliverTx` are
+    /// not retried.
+    async fn broadcast(&self, tx: Vec<u8>) -> Result<TxHandle> {
+        let mut retries = 0;
+        let (hash, client) = loop {
+            let client = self.endpoints.current();
+            match self.broadcast_to(&client, tx.clone()).await {
+                Ok(hash) => break (hash, client),
+                Err(Error::TendermintRPC(_)) if retries + 1 < self.endpoints.len() => {
+                    self.endpoints.failover();
+                    retries += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let handle = TxHandle::new(hash, client);
+        let _ = TX_HANDLES.try_with(|handles| handles.borrow_mut().push(handle.clone()));
+
+        Ok(handle)
+    }
+
+    async fn broadcast_to(&self, client: &tm::HttpClient, tx: Vec<u8>) -> Result<TxHash> {
+        let mode = *self.mode.lock().unwrap();
+        match mode {
+            BroadcastMode::Commit => {
+                let res = client.broadcast_tx_commit(tx.into()).await?;
+                if res.check_tx.code.is_err() {
+                    return Err(check_tx_error(
+                        res.check_tx.code.value(),
+                        res.check_tx.log.to_string().as_str(),
+                    ));
+                }
+                if res.deliver_tx.code.is_err() {
+                    return Err(Error::ABCI(format!(
+                        "DeliverTx failed: {}",
+                        res.deliver_tx.log
+                    )));
+                }
+
+                Ok(res.hash)
+            }
+            BroadcastMode::Sync => {
+                let res = client.broadcast_tx_sync(tx.into()).await?;
+                if res.code.is_err() {
+                    return Err(check_tx_error(
+                        res.code.value(),
+                        res.log.to_string().as_str(),
+                    ));
+                }
+
+                Ok(res.hash)
+            }
+            BroadcastMode::Async => Ok(client.broadcast_tx_async(tx.into()).await?.hash),
+        }
+    }
+}
+
+impl<T: State> TendermintAdapter<T> {
+    async fn query_encoded<F, R>(
+        &self,
+        path: Option<&str>,
+        query_bytes: Vec<u8>,
+        check: &F,
+    ) -> Result<R>
+    where
+        F: Fn(&T) -> Result<R>,
+    {
+        let query_retries = *self.query_retries.lock().unwrap();
+        let mut retries = 0;
+        let state = loop {
+            let client = self.endpoints.current();
+            match self.query_state(&client, path, query_bytes.clone()).await {
+                Ok(state) => break state,
+                Err(err) if retries >= query_retries => return Err(err),
+                Err(_) => {
+                    self.endpoints.failover();
+                    retries += 1;
+                }
+            }
+        };
+
+        check(&state)
+    }
+
+    /// Queries a single endpoint, returning the state reconstructed from the
+    /// proof once it has been checked against a verified app hash.
+    ///
+    /// The app hash for the latest state is in the header of the next block,
+    /// so this waits for that header to be verified.
+    async fn query_state(
+        &self,
+        client: &tm::HttpClient,
+        path: Option<&str>,
+        query_bytes: Vec<u8>,
+    ) -> Result<T> {
+        let path = match path {
+            Some(path) => Some(
+                path.parse()
+                    .map_err(|err: tendermint::Error| Error::Tendermint(err.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let res = client.abci_query(path, query_bytes, None, true).await?;
+        if res.code.is_err() {
+            return Err(Error::ABCI(format!("Query failed: {}", res.log)));
+        }
+
+        // The response starts with the root hash claimed by the node, which is
+        // skipped in favor of the app hash of the verified header which
+        // commits to the state at the response height.
+        if res.value.len() < 32 {
+            return Err(Error::ABCI("Query response is too short".into()));
+        }
+        let proof_bytes = &res.value[32..];
+        let verifier = self.verifier.lock().unwrap().clone();
+        let app_hash = verifier.app_hash(res.height.value() + 1).await?;
+
+        proven_state(proof_bytes, app_hash.as_slice())
+    }
 }
 
 impl<T> Clone for TendermintAdapter<T> {
     fn clone(&self) -> TendermintAdapter<T> {
         TendermintAdapter {
             marker: self.marker,
-            client: self.client.clone(),
+            endpoints: self.endpoints.clone(),
+            mode: self.mode.clone(),
+            verifier: self.verifier.clone(),
+            query_retries: self.query_retries.clone(),
         }
     }
 }
 
 unsafe impl<T> Send for TendermintAdapter<T> {}
+unsafe impl<T> Sync for TendermintAdapter<T> {}
+
+#[async_trait::async_trait]
+impl<T> ChainIdSource for TendermintAdapter<T> {
+    async fn chain_id(&mut self) -> Result<String> {
+        let mut retries = 0;
+        loop {
+            match self.endpoints.current().status().await {
+                Ok(status) => return Ok(status.node_info.network.to_string()),
+                Err(_) if retries + 1 < self.endpoints.len() => {
+                    self.endpoints.failover();
+                    retries += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Query + State> NonceSource for TendermintAdapter<SignerPlugin<NoncePlugin<T>>> {
+    async fn nonce(&mut self, address: Address) -> Result<u64> {
+        let nonce_query = nonce_plugin_methods::Query::MethodNonce(address, vec![]).encode()?;
+        let query_bytes = NonceQuery::<T::Query>::Method(nonce_query).encode()?;
+
+        self.query_encoded(None, query_bytes, &|state: &SignerPlugin<
+            NoncePlugin<T>,
+        >| { state.nonce(address) })
+            .await
+    }
+}
 
 #[async_trait::async_trait]
 impl<T: Call> AsyncCall for TendermintAdapter<T>
@@ -106,45 +626,33 @@ where
     type Call = T::Call;
 
     async fn call(&mut self, call: Self::Call) -> Result<()> {
-        let tx = call.encode()?.into();
-        let fut = self.client.broadcast_tx_commit(tx);
-        NoReturn(fut).await
-    }
-}
-
-pub struct NoReturn<'a>(
-    std::pin::Pin<Box<dyn std::future::Future<Output = tm::Result<TxResponse>> + Send + 'a>>,
-);
-
-impl<'a> std::future::Future for NoReturn<'a> {
-    type Output = Result<()>;
-
-    fn poll(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context,
-    ) -> std::task::Poll<Self::Output> {
-        unsafe {
-            let mut_ref = self.get_unchecked_mut();
-            let res = mut_ref.0.as_mut().poll(cx);
-            match res {
-                std::task::Poll::Ready(Ok(tx_res)) => {
-                    if tx_res.check_tx.code.is_err() {
-                        std::task::Poll::Ready(Err(Error::ABCI(format!(
-                            "CheckTx failed: {}",
-                            tx_res.check_tx.log
-                        ))))
-                    } else if tx_res.deliver_tx.code.is_err() {
-                        std::task::Poll::Ready(Err(Error::ABCI(format!(
-                            "DeliverTx failed: {}",
-                            tx_res.deliver_tx.log
-                        ))))
-                    } else {
-                        std::task::Poll::Ready(Ok(()))
-                    }
-                }
-                std::task::Poll::Ready(Err(e)) => std::task::Poll::Ready(Err(e.into())),
-                std::task::Poll::Pending => std::task::Poll::Pending,
-            }
-        }
+        self.broadcast(call.encode()?).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn websocket_urls() {
+        assert_eq!(
+            websocket_url("http://localhost:26657"),
+            "ws://localhost:26657/websocket"
+        );
+        assert_eq!(
+            websocket_url("https://rpc.example.com/"),
+            "wss://rpc.example.com/websocket"
+        );
+        assert_eq!(
+            websocket_url("localhost:26657"),
+            "ws://localhost:26657/websocket"
+        );
+        assert_eq!(
+            websocket_url("ws://localhost:26657"),
+            "ws://localhost:26657/websocket"
+        );
     }
 }
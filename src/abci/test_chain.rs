@@ -0,0 +1,822 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+use tendermint_proto::abci::request::Value as Req;
+use tendermint_proto::abci::response::Value as Res;
+use tendermint_proto::crypto::{public_key::Sum, PublicKey};
+use tendermint_proto::google::protobuf::Timestamp;
+use tendermint_proto::types::Header;
+
+use super::messages::*;
+use super::node::{check_tx_error, InternalApp};
+use super::tendermint_client::proven_state;
+use super::{ABCIStateMachine, App};
+use crate::call::Call;
+use crate::client::{AsyncCall, Client};
+use crate::coins::Address;
+use crate::encoding::Encode;
+use crate::merk::MerkStore;
+use crate::plugins::{
+    nonce_plugin_methods, ABCIPlugin, AppDomain, ChainIdSource, Expiry, Keystore, NoncePlugin,
+    NonceQuery, NonceSource, SignerClient, SignerPlugin, SigningDomain,
+};
+use crate::query::Query;
+use crate::state::State;
+use crate::{Error, Result};
+
+/// Used to give every chain in the process its own store directory and chain
+/// id, along with the time it was started.
+static NEXT_CHAIN: AtomicUsize = AtomicUsize::new(0);
+
+/// The block time of the first block, in seconds since the Unix epoch.
+const GENESIS_TIME: i64 = 1_600_000_000;
+
+/// How many seconds pass between blocks by default.
+const DEFAULT_BLOCK_TIME: i64 = 1;
+
+/// Runs an app with all of its plugins in the current process, making blocks
+/// on demand instead of running Tendermint.
+///
+/// Requests are sent to the same [`ABCIStateMachine`] a [`Node`](super::Node)
+/// runs, backed by a `MerkStore` in a temporary directory which is removed
+/// once the chain and its clients are dropped. The chain starts with
+/// `InitChain` and one committed block, and each later block is made with
+/// [`make_block`](Self::make_block), or right after each transaction when
+/// [`set_auto_block`](Self::set_auto_block) is enabled, as it is by default.
+///
+/// ```ignore
+/// let chain = TestChain::<DefaultPlugins<MyApp>>::new()?;
+/// let mut client = chain.signed_client(Arc::new(MemKeystore::from_secret(&[1; 32])?));
+/// client.do_something().await?;
+/// chain.advance_time(60);
+/// chain.make_block()?;
+/// let value = chain.query(query, |state| state.value())?;
+/// ```
+pub struct TestChain<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    state: Arc<Mutex<ChainState<A>>>,
+}
+
+pub(super) struct ChainState<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    machine: ABCIStateMachine<InternalApp<ABCIPlugin<A>>>,
+    pub(super) chain_id: String,
+    pub(super) height: u64,
+    pub(super) time: i64,
+    pub(super) block_time: i64,
+    pub(super) app_hash: Vec<u8>,
+    pub(super) validators: BTreeMap<[u8; 32], u64>,
+    pub(super) absent: BTreeSet<[u8; 32]>,
+    /// Proposes every block if set, otherwise validators take turns.
+    pub(super) proposer: Option<[u8; 32]>,
+    /// Misbehavior reported in the `BeginBlock` of the next block.
+    pub(super) evidence: Vec<Evidence>,
+    mempool: Vec<Vec<u8>>,
+    auto_block: bool,
+    pub(super) blocks: BTreeMap<u64, BlockInfo>,
+    txs: HashMap<[u8; 32], (u64, ResponseDeliverTx)>,
+    // Dropped after the machine, so the store is closed before its directory
+    // is removed.
+    home: TempHome,
+}
+
+// The store handles inside the state machine are never shared outside of it,
+// and the state is only used behind a mutex, so it can be moved to the
+// threads serving a `MockRpc`.
+unsafe impl<A> Send for ChainState<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+}
+
+/// A block committed by a [`TestChain`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockInfo {
+    /// The block time, in seconds since the Unix epoch.
+    pub time: i64,
+    /// The app hash committed at the end of the block.
+    pub app_hash: Vec<u8>,
+}
+
+struct TempHome(PathBuf);
+
+impl Drop for TempHome {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.0) {
+            log::warn!("Failed to remove test chain store: {}", err);
+        }
+    }
+}
+
+impl<A> TestChain<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    /// Starts a chain with no validators and a chain id which is unique
+    /// within the process.
+    pub fn new() -> Result<Self> {
+        Self::with_validators(&[])
+    }
+
+    /// Starts a chain with the given validator public keys and voting powers
+    /// in its genesis.
+    pub fn with_validators(validators: &[([u8; 32], u64)]) -> Result<Self> {
+        let chain_id = unique_chain_id()?;
+        let home = std::env::temp_dir().join(format!("orga-{}", chain_id));
+        let mut state = ChainState::new(chain_id, home, validators)?;
+        state.make_block()?;
+
+        Ok(TestChain {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    pub fn chain_id(&self) -> String {
+        self.state.lock().unwrap().chain_id.clone()
+    }
+
+    /// Where signed clients for the chain keep their nonces, inside the
+    /// chain's store directory so they are removed along with it. Clients
+    /// connected through a [`MockRpc`](super::MockRpc) can be given it with
+    /// [`SignerPluginClient::set_nonce_dir`](crate::plugins::SignerPluginClient::set_nonce_dir).
+    pub fn nonce_dir(&self) -> PathBuf {
+        self.state.lock().unwrap().home.0.join("nonces")
+    }
+
+    /// The height of the last committed block.
+    pub fn height(&self) -> u64 {
+        self.state.lock().unwrap().height
+    }
+
+    /// The app hash committed by the last block, which queries are verified
+    /// against.
+    pub fn app_hash(&self) -> Vec<u8> {
+        self.state.lock().unwrap().app_hash.clone()
+    }
+
+    /// The time of the next block, in seconds since the Unix epoch.
+    pub fn time(&self) -> i64 {
+        self.state.lock().unwrap().time
+    }
+
+    pub fn set_time(&self, seconds: i64) {
+        self.state.lock().unwrap().time = seconds;
+    }
+
+    pub fn advance_time(&self, seconds: i64) {
+        self.state.lock().unwrap().time += seconds;
+    }
+
+    /// Sets how many seconds the block time moves forward after each block.
+    pub fn set_block_time(&self, seconds: i64) {
+        self.state.lock().unwrap().block_time = seconds;
+    }
+
+    /// Sets whether every transaction is delivered in a block of its own as
+    /// soon as it passes `CheckTx`. When disabled, transactions wait in the
+    /// mempool until the next call to [`make_block`](Self::make_block).
+    pub fn set_auto_block(&self, auto_block: bool) {
+        self.state.lock().unwrap().auto_block = auto_block;
+    }
+
+    pub fn auto_block(&self) -> bool {
+        self.state.lock().unwrap().auto_block
+    }
+
+    /// The current validator public keys and voting powers, including the
+    /// updates returned by the app at the end of each block.
+    pub fn validators(&self) -> Vec<([u8; 32], u64)> {
+        let state = self.state.lock().unwrap();
+        state
+            .validators
+            .iter()
+            .map(|(pubkey, power)| (*pubkey, *power))
+            .collect()
+    }
+
+    /// Sets whether the validator with public key `pubkey` is reported as
+    /// having signed the previous block in the `BeginBlock` of later blocks.
+    pub fn set_signing(&self, pubkey: [u8; 32], signing: bool) {
+        let mut state = self.state.lock().unwrap();
+        if signing {
+            state.absent.remove(&pubkey);
+        } else {
+            state.absent.insert(pubkey);
+        }
+    }
+
+    /// Whether the validator with public key `pubkey` is reported as having
+    /// signed blocks, as set with [`TestChain::set_signing`].
+    pub fn signing(&self, pubkey: [u8; 32]) -> bool {
+        !self.state.lock().unwrap().absent.contains(&pubkey)
+    }
+
+    /// Runs `CheckTx` on an encoded transaction and adds it to the mempool,
+    /// delivering it right away if auto blocks are enabled.
+    pub fn send_tx(&self, tx: Vec<u8>) -> Result<()> {
+        self.state.lock().unwrap().send_tx(tx)
+    }
+
+    /// Runs `CheckTx` on an encoded transaction, adding it to the mempool if
+    /// it passes, and returns the app's response.
+    pub fn check_tx(&self, tx: Vec<u8>) -> Result<ResponseCheckTx> {
+        self.state.lock().unwrap().check_tx(tx)
+    }
+
+    /// Makes and commits a block containing the transactions in the mempool,
+    /// returning their `DeliverTx` responses in order.
+    pub fn make_block(&self) -> Result<Vec<ResponseDeliverTx>> {
+        self.state.lock().unwrap().make_block()
+    }
+
+    /// The committed block at `height`, if there is one.
+    pub fn block(&self, height: u64) -> Option<BlockInfo> {
+        self.state.lock().unwrap().blocks.get(&height).cloned()
+    }
+
+    /// The height and `DeliverTx` response of the delivered transaction with
+    /// the given hash.
+    pub fn tx_result(&self, hash: &[u8; 32]) -> Option<(u64, ResponseDeliverTx)> {
+        self.state.lock().unwrap().txs.get(hash).cloned()
+    }
+
+    /// Sends an ABCI query for the committed state at `height`, or the latest
+    /// state if it is zero, returning the app's response as a node would
+    /// receive it.
+    pub fn abci_query(&self, path: String, data: Vec<u8>, height: u64) -> Result<ResponseQuery> {
+        self.state.lock().unwrap().abci_query(path, data, height)
+    }
+
+    /// Queries the committed state like a node would, running `check` over
+    /// the state reconstructed from the proof once it has been checked
+    /// against the last app hash.
+    pub fn query<F, R>(&self, query: A::Query, check: F) -> Result<R>
+    where
+        F: Fn(&A) -> Result<R>,
+    {
+        self.state.lock().unwrap().query(query, check)
+    }
+
+    /// Returns an adapter which sends calls to the chain as transactions, for
+    /// building clients of the app.
+    pub fn adapter(&self) -> TestAdapter<A> {
+        TestAdapter {
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn client(&self) -> A::Client
+    where
+        A: Client<TestAdapter<A>>,
+    {
+        A::create_client(self.adapter())
+    }
+}
+
+impl<T> TestChain<SignerPlugin<T>>
+where
+    SignerPlugin<T>: App,
+    <SignerPlugin<T> as State>::Encoding: Default,
+{
+    /// Returns a client which signs its calls with `keystore` for this chain,
+    /// so they pass through the signer and the plugins below it like calls
+    /// sent to a node.
+    pub fn signed_client(
+        &self,
+        keystore: Arc<dyn Keystore>,
+    ) -> <T as Client<SignerClient<T, TestAdapter<SignerPlugin<T>>>>>::Client
+    where
+        T: Client<SignerClient<T, TestAdapter<SignerPlugin<T>>>>,
+    {
+        let domain = SigningDomain::new(self.chain_id(), T::app_domain().to_string());
+        T::create_client(
+            SignerClient::new(self.adapter(), keystore, domain, Expiry::default())
+                .with_nonce_dir(self.nonce_dir()),
+        )
+    }
+}
+
+impl<A> Clone for TestChain<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    fn clone(&self) -> Self {
+        TestChain {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<A> ChainState<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    /// Creates an app with its store in `home` and runs `InitChain`, leaving
+    /// the first block to be made by the caller.
+    pub(super) fn new(
+        chain_id: String,
+        home: PathBuf,
+        validators: &[([u8; 32], u64)],
+    ) -> Result<Self> {
+        if home.exists() {
+            std::fs::remove_dir_all(&home)?;
+        }
+
+        let app = InternalApp::<ABCIPlugin<A>>::new();
+        let machine = ABCIStateMachine::new(app, MerkStore::new(home.clone()));
+
+        let mut state = ChainState {
+            machine,
+            chain_id,
+            height: 0,
+            time: GENESIS_TIME,
+            block_time: DEFAULT_BLOCK_TIME,
+            app_hash: vec![],
+            validators: validators.iter().copied().collect(),
+            absent: BTreeSet::new(),
+            proposer: None,
+            evidence: vec![],
+            mempool: vec![],
+            auto_block: true,
+            blocks: BTreeMap::new(),
+            txs: HashMap::new(),
+            home: TempHome(home),
+        };
+        state.init_chain()?;
+
+        Ok(state)
+    }
+
+    fn request(&mut self, req: Req) -> Result<Res> {
+        self.machine.run(Request { value: Some(req) })
+    }
+
+    fn timestamp(&self) -> Timestamp {
+        Timestamp {
+            seconds: self.time,
+            nanos: 0,
+        }
+    }
+
+    fn init_chain(&mut self) -> Result<()> {
+        let validators = self
+            .validators
+            .iter()
+            .map(|(pubkey, power)| validator_update(*pubkey, *power))
+            .collect();
+        let req = RequestInitChain {
+            time: Some(self.timestamp()),
+            chain_id: self.chain_id.clone(),
+            validators,
+            initial_height: 1,
+            ..Default::default()
+        };
+        self.request(Req::InitChain(req))?;
+
+        Ok(())
+    }
+
+    pub(super) fn abci_query(
+        &mut self,
+        path: String,
+        data: Vec<u8>,
+        height: u64,
+    ) -> Result<ResponseQuery> {
+        let req = RequestQuery {
+            data,
+            path,
+            height: height as i64,
+            prove: true,
+            ..Default::default()
+        };
+        match self.request(Req::Query(req))? {
+            Res::Query(res) => Ok(res),
+            _ => Err(Error::ABCI("Unexpected response to Query".into())),
+        }
+    }
+
+    pub(super) fn query<F, R>(&mut self, query: A::Query, check: F) -> Result<R>
+    where
+        F: Fn(&A) -> Result<R>,
+    {
+        let res = self.abci_query(String::new(), query.encode()?, 0)?;
+        if res.code != 0 {
+            return Err(Error::ABCI(format!("Query failed: {}", res.log)));
+        }
+        if res.value.len() < 32 {
+            return Err(Error::ABCI("Query response is too short".into()));
+        }
+
+        let proven = proven_state(&res.value[32..], self.app_hash.as_slice())?;
+        check(&proven)
+    }
+
+    pub(super) fn check_tx(&mut self, tx: Vec<u8>) -> Result<ResponseCheckTx> {
+        let req = RequestCheckTx {
+            tx: tx.clone(),
+            ..Default::default()
+        };
+        let res = match self.request(Req::CheckTx(req))? {
+            Res::CheckTx(res) => res,
+            _ => return Err(Error::ABCI("Unexpected response to CheckTx".into())),
+        };
+        if res.code == 0 {
+            self.mempool.push(tx);
+        }
+
+        Ok(res)
+    }
+
+    fn send_tx(&mut self, tx: Vec<u8>) -> Result<()> {
+        let res = self.check_tx(tx)?;
+        if res.code != 0 {
+            return Err(check_tx_error(res.code, res.log.as_str()));
+        }
+        if !self.auto_block {
+            return Ok(());
+        }
+
+        let index = self.mempool.len() - 1;
+        let res = self.make_block()?.remove(index);
+        if res.code != 0 {
+            return Err(Error::ABCI(format!("DeliverTx failed: {}", res.log)));
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn make_block(&mut self) -> Result<Vec<ResponseDeliverTx>> {
+        let height = self.height + 1;
+
+        // Tendermint reports the votes for the previous block, which the
+        // first block does not have.
+        let votes = if height > 1 {
+            self.validators
+                .iter()
+                .map(|(pubkey, power)| VoteInfo {
+                    validator: Some(Validator {
+                        address: validator_address(pubkey),
+                        power: *power as i64,
+                    }),
+                    signed_last_block: !self.absent.contains(pubkey),
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let proposer = self.proposer.or_else(|| {
+            let index = height as usize % self.validators.len().max(1);
+            self.validators.keys().nth(index).copied()
+        });
+        let header = Header {
+            chain_id: self.chain_id.clone(),
+            height: height as i64,
+            time: Some(self.timestamp()),
+            app_hash: self.app_hash.clone(),
+            proposer_address: proposer
+                .map(|pubkey| validator_address(&pubkey))
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        self.request(Req::BeginBlock(RequestBeginBlock {
+            header: Some(header),
+            last_commit_info: Some(LastCommitInfo { round: 0, votes }),
+            byzantine_validators: std::mem::take(&mut self.evidence),
+            ..Default::default()
+        }))?;
+
+        let mut results = vec![];
+        for tx in std::mem::take(&mut self.mempool) {
+            let hash = tx_hash(tx.as_slice());
+            match self.request(Req::DeliverTx(RequestDeliverTx { tx }))? {
+                Res::DeliverTx(res) => {
+                    self.txs.insert(hash, (height, res.clone()));
+                    results.push(res);
+                }
+                _ => return Err(Error::ABCI("Unexpected response to DeliverTx".into())),
+            }
+        }
+
+        let req = RequestEndBlock {
+            height: height as i64,
+        };
+        if let Res::EndBlock(res) = self.request(Req::EndBlock(req))? {
+            for update in res.validator_updates {
+                let validator: crate::plugins::Validator = update.into();
+                if validator.power == 0 {
+                    self.validators.remove(&validator.pubkey);
+                } else {
+                    self.validators.insert(validator.pubkey, validator.power);
+                }
+            }
+        }
+
+        if let Res::Commit(res) = self.request(Req::Commit(Default::default()))? {
+            self.app_hash = res.data;
+        }
+        self.blocks.insert(
+            height,
+            BlockInfo {
+                time: self.time,
+                app_hash: self.app_hash.clone(),
+            },
+        );
+        self.height = height;
+        self.time += self.block_time;
+
+        Ok(results)
+    }
+}
+
+/// Returns a chain id which is unique within the process, along with the time
+/// it was created.
+pub(super) fn unique_chain_id() -> Result<String> {
+    // Chains keep their stores in the system's temporary directory, named
+    // after the chain id, so it must not repeat between runs or test processes
+    // running at the same time either.
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| Error::ABCI(err.to_string()))?
+        .as_nanos();
+    let index = NEXT_CHAIN.fetch_add(1, Ordering::SeqCst);
+
+    Ok(format!("test-chain-{}-{}", started, index))
+}
+
+fn validator_update(pubkey: [u8; 32], power: u64) -> ValidatorUpdate {
+    ValidatorUpdate {
+        pub_key: Some(PublicKey {
+            sum: Some(Sum::Ed25519(pubkey.to_vec())),
+        }),
+        power: power as i64,
+    }
+}
+
+/// The hash Tendermint identifies a transaction by.
+pub(super) fn tx_hash(tx: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(Sha256::digest(tx).as_slice());
+    hash
+}
+
+/// The address Tendermint gives an ed25519 validator, the first 20 bytes of
+/// the SHA-256 hash of its public key.
+pub(super) fn validator_address(pubkey: &[u8; 32]) -> Vec<u8> {
+    Sha256::digest(pubkey)[..20].to_vec()
+}
+
+/// Sends the calls of a client built with [`TestChain::client`] to the chain
+/// as encoded transactions.
+pub struct TestAdapter<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    state: Arc<Mutex<ChainState<A>>>,
+}
+
+impl<A> Clone for TestAdapter<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    fn clone(&self) -> Self {
+        TestAdapter {
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<A> AsyncCall for TestAdapter<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+    <A as Call>::Call: Send,
+{
+    type Call = <A as Call>::Call;
+
+    async fn call(&mut self, call: Self::Call) -> Result<()> {
+        let tx = call.encode()?;
+        self.state.lock().unwrap().send_tx(tx)
+    }
+}
+
+#[async_trait::async_trait]
+impl<A> ChainIdSource for TestAdapter<A>
+where
+    A: App,
+    <A as State>::Encoding: Default,
+{
+    async fn chain_id(&mut self) -> Result<String> {
+        Ok(self.state.lock().unwrap().chain_id.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> NonceSource for TestAdapter<SignerPlugin<NoncePlugin<T>>>
+where
+    T: Query + State,
+    SignerPlugin<NoncePlugin<T>>: App,
+    <SignerPlugin<NoncePlugin<T>> as State>::Encoding: Default,
+{
+    async fn nonce(&mut self, address: Address) -> Result<u64> {
+        let nonce_query = nonce_plugin_methods::Query::MethodNonce(address, vec![]).encode()?;
+        self.state
+            .lock()
+            .unwrap()
+            .query(NonceQuery::Method(nonce_query), |state| {
+                state.nonce(address)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::Decode;
+    use crate::plugins::{MemKeystore, NonceFile, NoncePlugin, NonceQuery, SignerCall};
+
+    #[derive(State, Encode, Decode, Call, Query, Client)]
+    pub struct Counter {
+        pub count: u64,
+    }
+
+    #[orga::methods(client)]
+    impl Counter {
+        #[call]
+        pub fn increment(&mut self) -> Result<()> {
+            self.count += 1;
+
+            Ok(())
+        }
+    }
+
+    type CounterApp = SignerPlugin<NoncePlugin<Counter>>;
+
+    fn count(chain: &TestChain<CounterApp>) -> u64 {
+        chain
+            .query(NonceQuery::Inner(counter_query::Query::This), |state| {
+                Ok(state.count)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn signed_calls() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        assert_eq!(chain.height(), 1);
+        assert_eq!(count(&chain), 0);
+
+        let keystore = Arc::new(MemKeystore::from_secret(&[1; 32]).unwrap());
+        let mut client = chain.signed_client(keystore);
+        futures_lite::future::block_on(client.increment()).unwrap();
+        assert_eq!(chain.height(), 2);
+        assert_eq!(count(&chain), 1);
+
+        chain.set_auto_block(false);
+        futures_lite::future::block_on(client.increment()).unwrap();
+        futures_lite::future::block_on(client.increment()).unwrap();
+        assert_eq!(chain.height(), 2);
+        let results = chain.make_block().unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|res| res.code == 0));
+        assert_eq!(count(&chain), 3);
+
+        // Calls which can not be decoded are rejected in CheckTx.
+        let call = SignerCall {
+            signature: None,
+            pubkey: None,
+            multisig: None,
+            expiry: Expiry::default(),
+            call_bytes: vec![],
+        };
+        assert!(futures_lite::future::block_on(chain.adapter().call(call)).is_err());
+    }
+
+    #[test]
+    fn query_height() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let keystore = Arc::new(MemKeystore::from_secret(&[1; 32]).unwrap());
+        let mut client = chain.signed_client(keystore);
+        futures_lite::future::block_on(client.increment()).unwrap();
+        assert_eq!(chain.height(), 2);
+
+        // The latest state is proven against the app hash which the next
+        // header commits to.
+        let query = NonceQuery::Inner(counter_query::Query::This)
+            .encode()
+            .unwrap();
+        let res = chain.abci_query(String::new(), query.clone(), 2).unwrap();
+        assert_eq!(res.code, 0);
+        assert_eq!(res.height, 2);
+        let app_hash = chain.block(2).unwrap().app_hash;
+        let state: CounterApp = proven_state(&res.value[32..], app_hash.as_slice()).unwrap();
+        assert_eq!(state.count, 1);
+
+        // Earlier states are not kept.
+        let res = chain.abci_query(String::new(), query, 1).unwrap();
+        assert_eq!(res.code, 1);
+    }
+
+    #[test]
+    fn value_queries_disabled() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let query = NonceQuery::Inner(counter_query::Query::This)
+            .encode()
+            .unwrap();
+        let res = chain
+            .abci_query(super::super::VALUE_QUERY_PATH.into(), query, 0)
+            .unwrap();
+        assert_eq!(res.code, 1);
+        assert!(res.value.is_empty());
+    }
+
+    #[test]
+    fn fetched_chain_id() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let keystore = Arc::new(MemKeystore::from_secret(&[2; 32]).unwrap());
+        let domain = SigningDomain::new(String::new(), String::new());
+        let mut client = NoncePlugin::<Counter>::create_client(
+            SignerClient::new(chain.adapter(), keystore, domain, Expiry::default())
+                .with_nonce_dir(chain.nonce_dir()),
+        );
+
+        // The chain id is fetched before the first call is signed.
+        futures_lite::future::block_on(client.increment()).unwrap();
+        assert_eq!(count(&chain), 1);
+    }
+
+    #[test]
+    fn nonce_files() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let keystore = Arc::new(MemKeystore::from_secret(&[1; 32]).unwrap());
+        let mut client = chain.signed_client(keystore);
+        futures_lite::future::block_on(client.increment()).unwrap();
+
+        // Nonces are kept with the chain's store and removed along with it.
+        let nonce_dir = chain.nonce_dir();
+        assert_eq!(std::fs::read_dir(&nonce_dir).unwrap().count(), 1);
+        drop(client);
+        drop(chain);
+        assert!(!nonce_dir.exists());
+    }
+
+    #[test]
+    fn nonce_resync() {
+        let chain = TestChain::<CounterApp>::new().unwrap();
+        let keystore = Arc::new(MemKeystore::from_secret(&[1; 32]).unwrap());
+        let address = keystore.address().unwrap();
+        let mut client = chain.signed_client(keystore);
+        futures_lite::future::block_on(client.increment()).unwrap();
+
+        // The local nonce falls behind the chain's, e.g. after another process
+        // signed a call for the same account.
+        let nonce_file =
+            NonceFile::open_under(Some(&chain.nonce_dir()), &chain.chain_id(), address).unwrap();
+        nonce_file.set(0).unwrap();
+
+        // The rejected call resyncs the local nonce, so the next one passes.
+        let err = futures_lite::future::block_on(client.increment()).unwrap_err();
+        assert!(matches!(err, Error::Nonce(_)));
+        assert_eq!(nonce_file.get().unwrap(), 1);
+        futures_lite::future::block_on(client.increment()).unwrap();
+        assert_eq!(count(&chain), 2);
+    }
+
+    #[test]
+    fn blocks() {
+        let chain =
+            TestChain::<CounterApp>::with_validators(&[([1; 32], 10), ([2; 32], 5)]).unwrap();
+        assert_eq!(chain.validators(), vec![([1; 32], 10), ([2; 32], 5)]);
+        assert_ne!(
+            chain.chain_id(),
+            TestChain::<CounterApp>::new().unwrap().chain_id()
+        );
+
+        chain.set_signing([2; 32], false);
+        chain.set_block_time(5);
+        let time = chain.time();
+        chain.make_block().unwrap();
+        chain.make_block().unwrap();
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.time(), time + 10);
+        chain.advance_time(100);
+        assert_eq!(chain.time(), time + 110);
+        assert_eq!(chain.app_hash().len(), 32);
+    }
+}
@@ -16,6 +16,15 @@ pub trait Call {
     fn call(&mut self, call: Self::Call) -> Result<()>;
 }
 
+/// Dispatches calls to the `#[call]` methods of a type. Implemented by
+/// `#[orga::methods]`, and reached through the `Method` variant of the call
+/// enum generated by `#[derive(Call)]`.
+pub trait MethodCall {
+    type MethodCall: Encode + Decode;
+
+    fn method_call(&mut self, call: Self::MethodCall) -> Result<()>;
+}
+
 impl<T: Call> Call for &mut T {
     type Call = T::Call;
 
@@ -220,3 +229,25 @@ impl<T: Call> MaybeCall for MaybeCallWrapper<T> {
         self.0.call(call)
     }
 }
+
+#[cfg_attr(test, mutate)]
+pub fn maybe_method_call<T>(value: &mut T, call_bytes: Vec<u8>) -> Result<()> {
+    MaybeMethodCall::maybe_method_call(value, call_bytes)
+}
+
+trait MaybeMethodCall {
+    fn maybe_method_call(&mut self, call_bytes: Vec<u8>) -> Result<()>;
+}
+
+impl<T> MaybeMethodCall for T {
+    default fn maybe_method_call(&mut self, _call_bytes: Vec<u8>) -> Result<()> {
+        Err(Error::Call("Type has no call methods".into()))
+    }
+}
+
+impl<T: MethodCall> MaybeMethodCall for T {
+    fn maybe_method_call(&mut self, call_bytes: Vec<u8>) -> Result<()> {
+        let call = Decode::decode(call_bytes.as_slice())?;
+        self.method_call(call)
+    }
+}
@@ -1,11 +1,13 @@
 use crate::call::Call;
 use crate::client::Client;
-use crate::coins::{Address, Amount, Coin, Give, Symbol, Take};
+use crate::coins::{Address, Allowance, Amount, Coin, Give, Symbol, Take};
 use crate::collections::Map;
 use crate::context::GetContext;
 use crate::encoding::{Decode, Encode};
 use crate::plugins::Paid;
+use crate::plugins::Session;
 use crate::plugins::Signer;
+use crate::plugins::Time;
 use crate::query::Query;
 use crate::state::State;
 use crate::{Error, Result};
@@ -13,8 +15,10 @@ use crate::{Error, Result};
 #[derive(State, Encode, Decode, Call, Query, Client)]
 pub struct Accounts<S: Symbol> {
     accounts: Map<Address, Coin<S>>,
+    allowances: Map<(Address, Address), Allowance>,
 }
 
+#[orga::methods(client)]
 impl<S: Symbol> Accounts<S> {
     #[call]
     pub fn transfer(&mut self, to: Address, amount: Amount) -> Result<()> {
@@ -36,8 +40,72 @@ impl<S: Symbol> Accounts<S> {
         paid.give::<S, _>(taken_coins.amount)
     }
 
+    /// Lets `grantee` fund its paid calls from the signer's balance, replacing
+    /// any allowance it already had. Session keys can not grant allowances,
+    /// since the grantee could spend past the key's own limit.
+    #[call]
+    pub fn grant_allowance(&mut self, grantee: Address, allowance: Allowance) -> Result<()> {
+        if self.context::<Session>().is_some() {
+            return Err(Error::Coins("Session keys can not grant allowances".into()));
+        }
+
+        let granter = self.signer()?;
+        self.allowances.insert((granter, grantee), allowance)
+    }
+
+    #[call]
+    pub fn revoke_allowance(&mut self, grantee: Address) -> Result<()> {
+        let granter = self.signer()?;
+        self.allowances.remove((granter, grantee))?;
+
+        Ok(())
+    }
+
+    /// Funds the paid call from `granter`'s balance, counting `amount`
+    /// against the allowance it granted to the signer.
+    #[call]
+    pub fn take_as_granted_funding(&mut self, granter: Address, amount: Amount) -> Result<()> {
+        let grantee = self.signer()?;
+        let now = self.context::<Time>().map(|time| time.seconds);
+        let call_bytes = self
+            .context::<Paid>()
+            .ok_or_else(|| Error::Coins("No Paid context found".into()))?
+            .call_bytes()
+            .to_vec();
+
+        let mut allowance = self
+            .allowances
+            .get((granter, grantee))?
+            .ok_or_else(|| Error::Coins("No allowance granted".into()))?
+            .clone();
+        allowance.spend(amount, &call_bytes, now)?;
+        self.allowances.insert((granter, grantee), allowance)?;
+        // The coins are spent on the signer's behalf, so they count against
+        // its session key like its own.
+        Session::charge(amount)?;
+
+        let taken_coins = self
+            .accounts
+            .get_mut(granter)?
+            .ok_or_else(|| Error::Coins("Insufficient funds".into()))?
+            .take(amount)?;
+
+        self.context::<Paid>()
+            .ok_or_else(|| Error::Coins("No Paid context found".into()))?
+            .give::<S, _>(taken_coins.amount)
+    }
+
+    #[query]
+    pub fn allowance(&self, granter: Address, grantee: Address) -> Result<Option<Allowance>> {
+        Ok(self
+            .allowances
+            .get((granter, grantee))?
+            .map(|allowance| allowance.clone()))
+    }
+
     fn take_own_coins(&mut self, amount: Amount) -> Result<Coin<S>> {
         let signer = self.signer()?;
+        Session::charge(amount)?;
 
         let taken_coins = self
             .accounts
@@ -96,3 +164,64 @@ impl<S: Symbol> Accounts<S> {
         account.take(amount)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::plugins::{AuthCall, AuthPlugin, PolicyCall, SessionKey};
+    use crate::store::{MapStore, Shared, Store};
+
+    #[derive(State, Debug, Clone)]
+    struct Simp(());
+    impl Symbol for Simp {}
+
+    #[test]
+    fn session_key_allowance_escalation() {
+        let store = Store::new(Shared::new(MapStore::new()).into());
+        let mut state: AuthPlugin<Accounts<Simp>> =
+            AuthPlugin::create(store, Default::default()).unwrap();
+        let owner: Address = [1; 32].into();
+        let key: Address = [2; 32].into();
+        let attacker: Address = [3; 32].into();
+
+        let call = |state: &mut AuthPlugin<Accounts<Simp>>, signer: Address, call| {
+            Context::add(Signer {
+                signer: Some(signer),
+            });
+            let res = state.call(call);
+            Context::remove::<Signer>();
+            res
+        };
+        let grant = || {
+            let allowance = Allowance {
+                spend_limit: 1_000.into(),
+                expires_at: None,
+                allowed_calls: vec![],
+            };
+            let call =
+                accounts_methods::Call::<Simp>::MethodGrantAllowance(attacker, allowance, vec![]);
+            accounts_call::Call::Method(call.encode().unwrap())
+        };
+
+        Context::add(Time::from_seconds(100));
+        let add_key = PolicyCall::AddSessionKey(
+            key,
+            SessionKey {
+                expires_at: 200,
+                spend_limit: 10.into(),
+            },
+        );
+        call(&mut state, owner, AuthCall::Policy(add_key)).unwrap();
+
+        // A session key can not hand the account's funds to another key
+        // through an allowance, which would bypass its spending limit.
+        let escalation = call(&mut state, key, AuthCall::OnBehalf(owner, grant()));
+        assert!(escalation.is_err());
+        assert!(state.allowance(owner, attacker).unwrap().is_none());
+
+        call(&mut state, owner, AuthCall::Direct(grant())).unwrap();
+        assert!(state.allowance(owner, attacker).unwrap().is_some());
+        Context::remove::<Time>();
+    }
+}
@@ -0,0 +1,153 @@
+use super::Amount;
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::query::Query;
+use crate::state::State;
+use crate::store::Store;
+use crate::{Error, Result};
+
+/// A prefix of an encoded call, used to restrict which calls an
+/// [`Allowance`] can pay for.
+///
+/// Calls are matched against the encoding of the paid call as seen by
+/// [`PayablePlugin`](crate::plugins::PayablePlugin), so a prefix made from the
+/// leading bytes of a call allows every call to the same method, while the
+/// full encoding only allows that exact call.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallPrefix(Vec<u8>);
+
+impl CallPrefix {
+    pub fn new(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() > u8::MAX as usize {
+            return Err(Error::Coins("Call prefix is too long".into()));
+        }
+
+        Ok(CallPrefix(bytes))
+    }
+
+    pub fn matches(&self, call_bytes: &[u8]) -> bool {
+        call_bytes.starts_with(&self.0)
+    }
+}
+
+impl Encode for CallPrefix {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        Ok(1 + self.0.len())
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        (self.0.len() as u8).encode_into(dest)?;
+        dest.write_all(&self.0)?;
+
+        Ok(())
+    }
+}
+
+impl Decode for CallPrefix {
+    fn decode<R: std::io::Read>(mut input: R) -> ed::Result<Self> {
+        let len = u8::decode(&mut input)?;
+        let mut bytes = vec![0; len as usize];
+        input.read_exact(&mut bytes)?;
+
+        Ok(CallPrefix(bytes))
+    }
+}
+
+impl Terminated for CallPrefix {}
+
+/// An allowance granted by one account to another, letting the grantee fund
+/// its paid calls from the granter's balance.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct Allowance {
+    /// The amount the grantee can still spend.
+    pub spend_limit: Amount,
+    /// The block time after which the allowance can no longer be used, in
+    /// seconds since the Unix epoch.
+    pub expires_at: Option<i64>,
+    /// The calls the allowance can pay for. Any call can be paid for if this
+    /// is empty.
+    pub allowed_calls: Vec<CallPrefix>,
+}
+
+impl State for Allowance {
+    type Encoding = Self;
+
+    fn create(_: Store, data: Self::Encoding) -> Result<Self> {
+        Ok(data)
+    }
+
+    fn flush(self) -> Result<Self::Encoding> {
+        Ok(self)
+    }
+}
+
+impl Query for Allowance {
+    type Query = ();
+
+    fn query(&self, _: ()) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Allowance {
+    /// Counts `amount` against the allowance to pay for the call encoded as
+    /// `call_bytes` at block time `now`, failing if the allowance has expired,
+    /// does not cover the call, or would be exceeded.
+    pub fn spend(&mut self, amount: Amount, call_bytes: &[u8], now: Option<i64>) -> Result<()> {
+        if let Some(expires_at) = self.expires_at {
+            let now = now.ok_or_else(|| Error::Coins("No Time context available".into()))?;
+            if now > expires_at {
+                return Err(Error::Coins("Allowance has expired".into()));
+            }
+        }
+
+        if !self.allowed_calls.is_empty()
+            && !self
+                .allowed_calls
+                .iter()
+                .any(|prefix| prefix.matches(call_bytes))
+        {
+            return Err(Error::Coins("Allowance does not cover this call".into()));
+        }
+
+        if amount > self.spend_limit {
+            return Err(Error::Coins("Allowance spending limit exceeded".into()));
+        }
+        self.spend_limit = (self.spend_limit - amount)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowance_spend() {
+        let mut allowance = Allowance {
+            spend_limit: 10.into(),
+            expires_at: Some(100),
+            allowed_calls: vec![CallPrefix::new(vec![1, 2]).unwrap()],
+        };
+
+        assert!(allowance.spend(1.into(), &[1, 3], Some(50)).is_err());
+        assert!(allowance.spend(1.into(), &[1, 2, 3], Some(101)).is_err());
+        assert!(allowance.spend(1.into(), &[1, 2, 3], None).is_err());
+        assert!(allowance.spend(11.into(), &[1, 2, 3], Some(50)).is_err());
+        allowance.spend(6.into(), &[1, 2, 3], Some(100)).unwrap();
+        allowance.spend(4.into(), &[1, 2], Some(100)).unwrap();
+        assert_eq!(allowance.spend_limit, 0.into());
+        assert!(allowance.spend(1.into(), &[1, 2], Some(100)).is_err());
+
+        let mut allowance = Allowance {
+            spend_limit: 10.into(),
+            expires_at: None,
+            allowed_calls: vec![],
+        };
+        allowance.spend(10.into(), &[5], None).unwrap();
+
+        let bytes = allowance.encode().unwrap();
+        assert_eq!(Allowance::decode(bytes.as_slice()).unwrap(), allowance);
+        assert!(CallPrefix::new(vec![0; 256]).is_err());
+    }
+}
@@ -22,6 +22,7 @@ impl<S: Symbol> Default for Coin<S> {
     }
 }
 
+#[orga::methods]
 impl<S: Symbol> Coin<S> {
     pub fn new() -> Self {
         Coin {
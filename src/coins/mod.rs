@@ -30,6 +30,9 @@ pub use staking::*;
 pub mod accounts;
 pub use accounts::*;
 
+pub mod allowance;
+pub use allowance::*;
+
 pub mod adjust;
 pub use adjust::*;
 
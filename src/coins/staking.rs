@@ -6,7 +6,7 @@ use crate::client::Client;
 use crate::collections::{Deque, Map};
 use crate::context::GetContext;
 use crate::encoding::{Decode, Encode};
-use crate::plugins::{BeginBlockCtx, Paid, Signer, Time, Validators};
+use crate::plugins::{BeginBlockCtx, Paid, Session, Signer, Time, Validators};
 use crate::query::Query;
 use crate::state::State;
 use crate::store::Store;
@@ -142,6 +142,7 @@ impl<S: Symbol> Default for StakingEncoding<S> {
     }
 }
 
+#[orga::methods(client)]
 impl<S: Symbol> Staking<S> {
     pub fn delegate(
         &mut self,
@@ -311,6 +312,7 @@ impl<S: Symbol> Staking<S> {
     #[call]
     pub fn unbond_self(&mut self, val_address: Address, amount: Amount) -> Result<()> {
         let signer = self.signer()?;
+        Session::charge(amount)?;
         self.unbond(val_address, signer, amount)
     }
 
@@ -331,6 +333,7 @@ impl<S: Symbol> Staking<S> {
     #[call]
     pub fn take_as_funding(&mut self, validator_address: Address, amount: Amount) -> Result<()> {
         let signer = self.signer()?;
+        Session::charge(amount)?;
         let taken_coins = self.withdraw(validator_address, signer, amount)?;
         self.paid()?.give::<S, _>(taken_coins.amount)
     }
@@ -70,6 +70,7 @@ impl<T: State<S>, S: Read> State<S> for Deque<T, S> {
     }
 }
 
+#[orga::methods]
 impl<T: State<S>, S: Read> Deque<T, S> {
     #[query]
     #[cfg_attr(test, mutate)]
@@ -53,6 +53,7 @@ where
 // TODO: add a get_mut method (maybe just takes in T::Key?) so we can add
 // #[call] to it to route calls to children
 
+#[orga::methods]
 impl<T, S> EntryMap<T, S>
 where
     T: Entry,
@@ -72,34 +73,14 @@ where
         let (key, _) = entry.into_entry();
         self.map.contains_key(key)
     }
-}
 
-impl<T, S> EntryMap<T, S>
-where
-    T: Entry,
-    T::Key: Encode + Terminated + Clone,
-    T::Value: State<S>,
-    S: Read,
-{
-    #[cfg_attr(test, mutate)]
-    pub fn delete(&mut self, entry: T) -> Result<()> {
-        let (key, _) = entry.into_entry();
-        self.map.remove(key)?;
-
-        Ok(())
-    }
-}
-
-impl<T, S> EntryMap<T, S>
-where
-    T: Entry,
-    T::Key: Encode + Terminated + Clone,
-    T::Value: State<S> + Eq,
-    S: Read,
-{
     #[query]
     #[cfg_attr(test, mutate)]
-    pub fn contains(&self, entry: T) -> Result<bool> {
+    pub fn contains(&self, entry: T) -> Result<bool>
+    where
+        T::Key: Clone,
+        T::Value: Eq,
+    {
         let (key, value) = entry.into_entry();
 
         match self.map.contains_key(key.clone())? {
@@ -118,6 +99,22 @@ where
     }
 }
 
+impl<T, S> EntryMap<T, S>
+where
+    T: Entry,
+    T::Key: Encode + Terminated + Clone,
+    T::Value: State<S>,
+    S: Read,
+{
+    #[cfg_attr(test, mutate)]
+    pub fn delete(&mut self, entry: T) -> Result<()> {
+        let (key, _) = entry.into_entry();
+        self.map.remove(key)?;
+
+        Ok(())
+    }
+}
+
 impl<'a, T: Entry, S> EntryMap<T, S>
 where
     T::Key: Next + Decode + Encode + Terminated + Clone,
@@ -190,11 +190,13 @@ where
 
         let subcall_bytes = subcall.encode()?;
 
-        let call = <Map<K, V> as Call>::Call::MethodGetMut(key, subcall_bytes);
+        let method_call = map_methods::Call::MethodGetMut(key, subcall_bytes);
+        let call = <Map<K, V> as Call>::Call::Method(method_call.encode()?);
         self.parent.call(call).await
     }
 }
 
+#[orga::methods]
 impl<K, V, S> Map<K, V, S>
 where
     K: Encode + Terminated,
@@ -268,6 +270,24 @@ where
             self.get_from_store(&map_key.inner)?.map(Ref::Owned)
         })
     }
+
+    /// Gets a mutable reference to the value in the map for the given key, or
+    /// `None` if the key has no value.
+    ///
+    /// If the value is mutated, it will be retained in memory until the map is
+    /// flushed.
+    ///
+    /// The returned value will reference the latest changes to the data even if
+    /// the value was inserted, modified, or deleted since the last time the map
+    /// was flushed.
+    #[call]
+    #[cfg_attr(test, mutate)]
+    pub fn get_mut(&mut self, key: K) -> Result<Option<ChildMut<K, V, S>>>
+    where
+        K: Clone,
+    {
+        Ok(self.entry(key)?.into())
+    }
 }
 
 impl<K, V, S, D> Map<K, V, S>
@@ -300,26 +320,11 @@ where
     V: State<S>,
     S: Read,
 {
-    /// Gets a mutable reference to the value in the map for the given key, or
-    /// `None` if the key has no value.
-    ///
-    /// If the value is mutated, it will be retained in memory until the map is
-    /// flushed.
-    ///
-    /// The returned value will reference the latest changes to the data even if
-    /// the value was inserted, modified, or deleted since the last time the map
-    /// was flushed.
-    #[call]
-    #[cfg_attr(test, mutate)]
-    pub fn get_mut(&mut self, key: K) -> Result<Option<ChildMut<K, V, S>>> {
-        Ok(self.entry(key)?.into())
-    }
-
     /// Returns a mutable reference to the key/value entry for the given key.
     #[cfg_attr(test, mutate)]
     pub fn entry(&mut self, key: K) -> Result<Entry<K, V, S>> {
         let map_key = MapKey::<K>::new(key)?;
-        Ok(if self.children.contains_key(&map_key) {
+        Ok(if let Some(Some(_)) = self.children.get(&map_key) {
             // value is already retained in memory (was modified)
             let entry = match self.children.entry(map_key) {
                 btree_map::Entry::Occupied(entry) => entry,
@@ -327,6 +332,12 @@ where
             };
             let child = ChildMut::Modified(entry);
             Entry::Occupied { child }
+        } else if self.children.contains_key(&map_key) {
+            // value was removed since the last flush
+            Entry::Vacant {
+                key: map_key.inner,
+                parent: self,
+            }
         } else {
             // value is not in memory, try to get from store
             match self.get_from_store(&map_key.inner)? {
@@ -436,7 +447,8 @@ where
 
     /// Writes a change to the key/value store for the given key. If
     /// `maybe_value` is `Some`, the value's `State::flush` implementation is
-    /// called then its binary encoding is written to `key`. If `maybe_value` is
+    /// called, its `State::prune` implementation is given the value's substore
+    /// and its binary encoding is written to `key`. If `maybe_value` is
     /// `None`, the value is removed by deleting all entries which start with
     /// `key`.
     fn apply_change(store: &mut Store<S>, key: &K, maybe_value: Option<V>) -> Result<()> {
@@ -445,8 +457,9 @@ where
         match maybe_value {
             Some(value) => {
                 // insert/update
-                let value_bytes = value.flush()?.encode()?;
-                store.put(key_bytes, value_bytes)?;
+                let value_encoding = value.flush()?;
+                V::prune(store.sub(key_bytes.as_slice()), &value_encoding)?;
+                store.put(key_bytes, value_encoding.encode()?)?;
             }
             None => {
                 // delete
@@ -1081,6 +1094,22 @@ mod tests {
         assert!(store.get(&enc(16)).unwrap().is_none());
     }
 
+    #[test]
+    fn entry_after_remove() {
+        let store = Store::new(MapStore::new());
+        let mut map: Map<u32, u32> = Map::create(store.clone(), ()).unwrap();
+
+        map.insert(18, 19).unwrap();
+        map.remove(18).unwrap();
+        assert!(map.get_mut(18).unwrap().is_none());
+        assert!(!map.entry(18).unwrap().remove().unwrap());
+
+        *map.entry(18).unwrap().or_insert(20).unwrap() += 1;
+        assert_eq!(*map.get(18).unwrap().unwrap(), 21);
+        map.flush().unwrap();
+        assert_eq!(store.get(&enc(18)).unwrap().unwrap(), enc(21));
+    }
+
     #[test]
     fn iter_merge_next_map_only() {
         let store = Store::new(MapStore::new());
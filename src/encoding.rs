@@ -1 +1,50 @@
 pub use ed::*;
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+/// The length of a list written by [`encode_list`].
+pub(crate) fn list_encoding_length<T: Encode>(items: &[T]) -> Result<usize> {
+    items
+        .iter()
+        .try_fold(2, |len, item| Ok(len + 2 + item.encoding_length()?))
+}
+
+/// Writes a list of up to `u16::MAX` items, each prefixed with its length so
+/// it can be decoded without consuming the rest of the list, as used by query
+/// and call batches.
+pub(crate) fn encode_list<T: Encode, W: Write>(items: &[T], dest: &mut W) -> Result<()> {
+    dest.write_all(&encode_len(items.len())?)?;
+    for item in items {
+        let item_bytes = item.encode()?;
+        dest.write_all(&encode_len(item_bytes.len())?)?;
+        dest.write_all(&item_bytes)?;
+    }
+
+    Ok(())
+}
+
+/// Reads a list written by [`encode_list`].
+pub(crate) fn decode_list<T: Decode, R: Read>(mut reader: R) -> Result<Vec<T>> {
+    let count = u16::decode(&mut reader)?;
+    let mut items = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let item_len = u16::decode(&mut reader)?;
+        let mut item_bytes = vec![0u8; item_len as usize];
+        reader.read_exact(&mut item_bytes)?;
+        items.push(T::decode(item_bytes.as_slice())?);
+    }
+
+    Ok(items)
+}
+
+fn encode_len(len: usize) -> Result<Vec<u8>> {
+    let len: u16 = len.try_into().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Batch is too large to encode",
+        )
+    })?;
+
+    len.encode()
+}
@@ -10,6 +10,10 @@ pub enum Error {
     ABCI2(#[from] abci2::Error),
     #[error("App Error: {0}")]
     App(String),
+    #[error("Auth Error: {0}")]
+    Auth(String),
+    #[error("Batch Error: {0}")]
+    Batch(String),
     #[error("Call Error: {0}")]
     Call(String),
     #[error("Client Error: {0}")]
@@ -29,6 +33,8 @@ pub enum Error {
     InvalidID,
     #[error(transparent)]
     IO(#[from] std::io::Error),
+    #[error("Keystore Error: {0}")]
+    Keystore(String),
     #[cfg(feature = "merk")]
     #[error(transparent)]
     Merk(#[from] merk::Error),
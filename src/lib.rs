@@ -51,6 +51,9 @@ pub mod tendermint;
 
 pub mod plugins;
 
+/// Randomized model checking for state types.
+pub mod testing;
+
 #[cfg(feature = "abci")]
 pub mod coins;
 
@@ -63,6 +66,7 @@ pub use async_trait::async_trait;
 pub use error::*;
 pub use futures_lite::future::Boxed as BoxFuture;
 pub use orga_macros as macros;
+pub use orga_macros::methods;
 
 pub mod prelude {
     #[cfg(feature = "abci")]
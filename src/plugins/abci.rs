@@ -135,6 +135,12 @@ impl Time {
     }
 }
 
+/// Added to the context while a transaction is checked for the mempool, as
+/// opposed to being delivered in a block. Checks run against the state of the
+/// last block, so the transaction can be included in the next one at the
+/// earliest.
+pub struct CheckTxCtx;
+
 #[derive(Encode, Decode)]
 pub enum ABCICall<C> {
     InitChain(Adapter<RequestInitChain>),
@@ -217,7 +223,13 @@ impl<T: App> Call for ABCIPlugin<T> {
                 Ok(())
             }
             DeliverTx(inner_call) => self.inner.call(inner_call),
-            CheckTx(inner_call) => self.inner.call(inner_call),
+            CheckTx(inner_call) => {
+                Context::add(CheckTxCtx);
+                let res = self.inner.call(inner_call);
+                Context::remove::<CheckTxCtx>();
+
+                res
+            }
         }?;
 
         let validators = Context::resolve::<Validators>().unwrap();
@@ -256,6 +268,10 @@ where
         self.updates.flush()?;
         Ok((self.inner.flush()?,))
     }
+
+    fn prune(store: Store, data: &Self::Encoding) -> Result<()> {
+        T::prune(store.sub(&[0]), &data.0)
+    }
 }
 
 impl<T> From<ABCIPlugin<T>> for (T::Encoding,)
@@ -0,0 +1,629 @@
+use super::{AppDomain, BeginBlockCtx, EndBlockCtx, InitChainCtx, Signer, Time};
+use crate::abci::{BeginBlock, EndBlock, InitChain};
+use crate::call::Call;
+use crate::client::{AsyncCall, Client};
+use crate::coins::{Address, Amount};
+use crate::collections::Map;
+use crate::context::Context;
+use crate::encoding::{Decode, Encode};
+use crate::query::Query;
+use crate::state::State;
+use crate::store::Store;
+use crate::{Error, Result};
+use std::ops::{Deref, DerefMut};
+
+/// A plugin which lets accounts authorize keys other than their own to make
+/// calls on their behalf, following a policy stored in state.
+///
+/// An account can add session keys, which can act for it until they expire
+/// and only spend up to a limit, and a recovery key, which gets full control
+/// of the account once it has started a recovery and a delay has passed
+/// without the account removing it.
+///
+/// The plugin must be placed under [`SignerPlugin`](super::SignerPlugin). It
+/// replaces the `Signer` context with the account a call is made for, so the
+/// inner state does not need to know about the policy.
+///
+/// The policy and the inner state are kept in separate substores, like the
+/// fields of a `State` struct, so adding the plugin to an existing app moves
+/// the app's data into a substore.
+///
+/// Session key spending limits only cover calls which charge the coins they
+/// move to the session with [`Session::charge`]. In this crate these are
+/// `Accounts::transfer`, `Accounts::take_as_funding`,
+/// `Accounts::take_as_granted_funding`, `Staking::unbond_self` and
+/// `Staking::take_as_funding`. Any other call which moves value out of the
+/// signer's account must charge the session itself, or session keys can move
+/// value through it without limit.
+#[derive(State, Encode, Decode)]
+pub struct AuthPlugin<T: State> {
+    session_keys: Map<(Address, Address), SessionKey>,
+    recovery_keys: Map<Address, RecoveryKey>,
+    inner: T,
+}
+
+impl<T: State> Deref for AuthPlugin<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// A key which can make calls on behalf of an account for a limited time,
+/// e.g. so a web app can act for a user without the user's main key.
+#[derive(State, Encode, Decode, Clone, Copy, Debug, PartialEq)]
+pub struct SessionKey {
+    /// The block time after which the key can no longer be used, in seconds
+    /// since the Unix epoch.
+    pub expires_at: i64,
+    /// The amount the key can still spend from the account.
+    pub spend_limit: Amount,
+}
+
+/// A key which can take control of an account, `delay_seconds` after it
+/// starts a recovery. The account can cancel a recovery in that time by
+/// removing or replacing the key.
+#[derive(Encode, Decode, Clone, Copy, Debug, PartialEq)]
+pub struct RecoveryKey {
+    pub key: Address,
+    pub delay_seconds: i64,
+    pub started_at: Option<i64>,
+}
+
+impl State for RecoveryKey {
+    type Encoding = Self;
+
+    fn create(_: Store, data: Self::Encoding) -> Result<Self> {
+        Ok(data)
+    }
+
+    fn flush(self) -> Result<Self::Encoding> {
+        Ok(self)
+    }
+}
+
+/// Added to the context while a session key makes a call on behalf of an
+/// account, so the coins it moves out of the account can be counted against
+/// its spending limit.
+pub struct Session {
+    pub key: Address,
+    remaining: Amount,
+}
+
+impl Session {
+    /// Counts `amount` taken from the signer's own holdings against the
+    /// spending limit of the session key making the current call, if any.
+    ///
+    /// Every call which moves value out of the signer's account must charge
+    /// it here, so session keys can not get around their limit through a
+    /// call which forgot to check it.
+    pub fn charge(amount: Amount) -> Result<()> {
+        match Context::resolve::<Session>() {
+            Some(session) => session.spend(amount),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts `amount` against the session key's spending limit, failing if
+    /// the limit would be exceeded.
+    pub fn spend(&mut self, amount: Amount) -> Result<()> {
+        let remaining = u64::from(self.remaining)
+            .checked_sub(amount.into())
+            .ok_or_else(|| Error::Auth("Session key spending limit exceeded".into()))?;
+        self.remaining = remaining.into();
+
+        Ok(())
+    }
+
+    pub fn remaining(&self) -> Amount {
+        self.remaining
+    }
+}
+
+#[derive(Encode, Decode)]
+pub enum AuthCall<T> {
+    /// A call made by the signer for its own account.
+    Direct(T),
+    /// A call made by the signer on behalf of another account which has
+    /// authorized it.
+    OnBehalf(Address, T),
+    /// A change to the signer's own policy.
+    Policy(PolicyCall),
+}
+
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl<T> Send for AuthCall<T> {}
+
+#[derive(Encode, Decode)]
+pub enum PolicyCall {
+    AddSessionKey(Address, SessionKey),
+    RemoveSessionKey(Address),
+    SetRecoveryKey(Address, i64),
+    RemoveRecoveryKey,
+    /// Starts recovering the given account. Must be signed by its recovery
+    /// key.
+    StartRecovery(Address),
+}
+
+fn require_time(now: Option<i64>) -> Result<i64> {
+    now.ok_or_else(|| Error::Auth("No Time context available".into()))
+}
+
+impl<T: State> AuthPlugin<T> {
+    /// Checks that `key` may act for `account` at time `now`, returning the
+    /// session for session keys.
+    fn authorize(
+        &self,
+        account: Address,
+        key: Address,
+        now: Option<i64>,
+    ) -> Result<Option<Session>> {
+        if let Some(session_key) = self.session_keys.get((account, key))? {
+            if require_time(now)? > session_key.expires_at {
+                return Err(Error::Auth("Session key has expired".into()));
+            }
+
+            return Ok(Some(Session {
+                key,
+                remaining: session_key.spend_limit,
+            }));
+        }
+
+        match self.recovery_keys.get(account)? {
+            Some(recovery) if recovery.key == key => match recovery.started_at {
+                Some(started_at) if require_time(now)? >= started_at + recovery.delay_seconds => {
+                    Ok(None)
+                }
+                Some(_) => Err(Error::Auth("Recovery delay has not passed".into())),
+                None => Err(Error::Auth("Recovery has not been started".into())),
+            },
+            _ => Err(Error::Auth("Key is not authorized for this account".into())),
+        }
+    }
+
+    fn update_policy(&mut self, signer: Address, call: PolicyCall, now: Option<i64>) -> Result<()> {
+        match call {
+            PolicyCall::AddSessionKey(key, session_key) => {
+                self.session_keys.insert((signer, key), session_key.into())
+            }
+            PolicyCall::RemoveSessionKey(key) => {
+                self.session_keys.remove((signer, key))?;
+                Ok(())
+            }
+            PolicyCall::SetRecoveryKey(key, delay_seconds) => {
+                if delay_seconds < 0 {
+                    return Err(Error::Auth("Recovery delay must not be negative".into()));
+                }
+
+                self.recovery_keys.insert(
+                    signer,
+                    RecoveryKey {
+                        key,
+                        delay_seconds,
+                        started_at: None,
+                    },
+                )
+            }
+            PolicyCall::RemoveRecoveryKey => {
+                self.recovery_keys.remove(signer)?;
+                Ok(())
+            }
+            PolicyCall::StartRecovery(account) => {
+                let mut recovery = match self.recovery_keys.get(account)? {
+                    Some(recovery) if recovery.key == signer => *recovery,
+                    _ => {
+                        return Err(Error::Auth(
+                            "Signer is not the recovery key for this account".into(),
+                        ))
+                    }
+                };
+                if recovery.started_at.is_some() {
+                    return Ok(());
+                }
+                recovery.started_at = Some(require_time(now)?);
+
+                self.recovery_keys.insert(account, recovery)
+            }
+        }
+    }
+}
+
+impl<T> Call for AuthPlugin<T>
+where
+    T: Call + State,
+{
+    type Call = AuthCall<T::Call>;
+
+    fn call(&mut self, call: Self::Call) -> Result<()> {
+        Context::remove::<Session>();
+        let signer = Context::resolve::<Signer>()
+            .ok_or_else(|| Error::Auth("No Signer context available".into()))?
+            .signer;
+        let now = Context::resolve::<Time>().map(|time| time.seconds);
+
+        let (account, call) = match call {
+            AuthCall::Direct(call) => return self.inner.call(call),
+            AuthCall::OnBehalf(account, call) => (account, call),
+            AuthCall::Policy(call) => {
+                let signer =
+                    signer.ok_or_else(|| Error::Auth("Policy changes must be signed".into()))?;
+                return self.update_policy(signer, call, now);
+            }
+        };
+
+        let key = signer
+            .ok_or_else(|| Error::Auth("Calls on behalf of an account must be signed".into()))?;
+        if key == account {
+            return self.inner.call(call);
+        }
+
+        let session = self.authorize(account, key, now)?;
+        Context::remove::<Signer>();
+        Context::add(Signer {
+            signer: Some(account),
+        });
+
+        let session = match session {
+            Some(session) => session,
+            None => return self.inner.call(call),
+        };
+        Context::add(session);
+        let res = self.inner.call(call);
+        let remaining = Context::resolve::<Session>().map(|session| session.remaining);
+        Context::remove::<Session>();
+
+        // Coins moved before a failure are not rolled back, so the spending
+        // is recorded whether or not the call succeeded.
+        if let (Some(remaining), Some(mut session_key)) =
+            (remaining, self.session_keys.get_mut((account, key))?)
+        {
+            session_key.spend_limit = remaining;
+        }
+
+        res
+    }
+}
+
+impl<T: Query + State> Query for AuthPlugin<T> {
+    type Query = T::Query;
+
+    fn query(&self, query: Self::Query) -> Result<()> {
+        self.inner.query(query)
+    }
+}
+
+pub struct AuthAdapter<T, U: Clone> {
+    parent: U,
+    account: Option<Address>,
+    marker: std::marker::PhantomData<T>,
+}
+
+unsafe impl<T, U: Send + Clone> Send for AuthAdapter<T, U> {}
+
+impl<T, U: Clone> Clone for AuthAdapter<T, U> {
+    fn clone(&self) -> Self {
+        AuthAdapter {
+            parent: self.parent.clone(),
+            account: self.account,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Call, U: AsyncCall<Call = AuthCall<T::Call>> + Clone> AsyncCall for AuthAdapter<T, U>
+where
+    T::Call: Send,
+    U: Send,
+{
+    type Call = T::Call;
+
+    async fn call(&mut self, call: Self::Call) -> Result<()> {
+        let call = match self.account {
+            Some(account) => AuthCall::OnBehalf(account, call),
+            None => AuthCall::Direct(call),
+        };
+
+        self.parent.call(call).await
+    }
+}
+
+pub struct AuthClient<T: Client<AuthAdapter<T, U>>, U: Clone> {
+    inner: T::Client,
+    parent: U,
+}
+
+impl<T: Client<AuthAdapter<T, U>>, U: Clone> AuthClient<T, U> {
+    /// Returns a client which makes its calls on behalf of `account`, which
+    /// must have authorized the signing key.
+    pub fn on_behalf_of(&self, account: Address) -> T::Client {
+        T::create_client(AuthAdapter {
+            parent: self.parent.clone(),
+            account: Some(account),
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<T, U> AuthClient<T, U>
+where
+    T: Client<AuthAdapter<T, U>> + Call,
+    U: AsyncCall<Call = AuthCall<T::Call>> + Clone,
+{
+    /// Allows `key` to make calls on behalf of the signer's account.
+    pub async fn add_session_key(&mut self, key: Address, session_key: SessionKey) -> Result<()> {
+        self.update_policy(PolicyCall::AddSessionKey(key, session_key))
+            .await
+    }
+
+    pub async fn remove_session_key(&mut self, key: Address) -> Result<()> {
+        self.update_policy(PolicyCall::RemoveSessionKey(key)).await
+    }
+
+    /// Sets the key which can recover the signer's account, `delay_seconds`
+    /// after starting a recovery. Replaces any existing recovery key,
+    /// cancelling a recovery in progress.
+    pub async fn set_recovery_key(&mut self, key: Address, delay_seconds: i64) -> Result<()> {
+        self.update_policy(PolicyCall::SetRecoveryKey(key, delay_seconds))
+            .await
+    }
+
+    pub async fn remove_recovery_key(&mut self) -> Result<()> {
+        self.update_policy(PolicyCall::RemoveRecoveryKey).await
+    }
+
+    /// Starts recovering `account`, for which the signer is the recovery key.
+    pub async fn start_recovery(&mut self, account: Address) -> Result<()> {
+        self.update_policy(PolicyCall::StartRecovery(account)).await
+    }
+
+    async fn update_policy(&mut self, call: PolicyCall) -> Result<()> {
+        self.parent.call(AuthCall::Policy(call)).await
+    }
+}
+
+impl<T: Client<AuthAdapter<T, U>>, U: Clone> Clone for AuthClient<T, U>
+where
+    T::Client: Clone,
+{
+    fn clone(&self) -> Self {
+        AuthClient {
+            inner: self.inner.clone(),
+            parent: self.parent.clone(),
+        }
+    }
+}
+
+impl<T: Client<AuthAdapter<T, U>>, U: Clone> Deref for AuthClient<T, U> {
+    type Target = T::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: Client<AuthAdapter<T, U>>, U: Clone> DerefMut for AuthClient<T, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl<T: Client<AuthAdapter<T, U>>, U: Clone + Send> Send for AuthClient<T, U> {}
+
+impl<T: Client<AuthAdapter<T, U>> + State, U: Clone> Client<U> for AuthPlugin<T> {
+    type Client = AuthClient<T, U>;
+
+    fn create_client(parent: U) -> Self::Client {
+        AuthClient {
+            inner: T::create_client(AuthAdapter {
+                parent: parent.clone(),
+                account: None,
+                marker: std::marker::PhantomData,
+            }),
+            parent,
+        }
+    }
+}
+
+impl<T> BeginBlock for AuthPlugin<T>
+where
+    T: BeginBlock + State,
+{
+    fn begin_block(&mut self, ctx: &BeginBlockCtx) -> Result<()> {
+        self.inner.begin_block(ctx)
+    }
+}
+
+impl<T> EndBlock for AuthPlugin<T>
+where
+    T: EndBlock + State,
+{
+    fn end_block(&mut self, ctx: &EndBlockCtx) -> Result<()> {
+        self.inner.end_block(ctx)
+    }
+}
+
+impl<T> InitChain for AuthPlugin<T>
+where
+    T: InitChain + State,
+{
+    fn init_chain(&mut self, ctx: &InitChainCtx) -> Result<()> {
+        self.inner.init_chain(ctx)
+    }
+}
+
+impl<T: State> AppDomain for AuthPlugin<T> {
+    fn app_domain() -> &'static str {
+        T::app_domain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MapStore, Read, Shared};
+
+    /// Charges the amount of each call to the session, then fails if asked to.
+    #[derive(State)]
+    struct Spender {
+        spent: u64,
+    }
+
+    impl Call for Spender {
+        type Call = (u64, bool);
+
+        fn call(&mut self, (amount, fail): Self::Call) -> Result<()> {
+            Session::charge(amount.into())?;
+            self.spent += amount;
+            if fail {
+                return Err(Error::Auth("Failed after spending".into()));
+            }
+
+            Ok(())
+        }
+    }
+
+    fn session_key(expires_at: i64) -> PolicyCall {
+        PolicyCall::AddSessionKey(
+            [2; 32].into(),
+            SessionKey {
+                expires_at,
+                spend_limit: 10.into(),
+            },
+        )
+    }
+
+    #[test]
+    fn session_keys() {
+        let store = Shared::new(MapStore::new());
+        let mut state =
+            AuthPlugin::<u64>::create(Store::new(store.into()), Default::default()).unwrap();
+        let owner: Address = [1; 32].into();
+        let key: Address = [2; 32].into();
+
+        assert!(state.authorize(owner, key, Some(100)).is_err());
+
+        state.update_policy(owner, session_key(200), None).unwrap();
+        let mut session = state.authorize(owner, key, Some(200)).unwrap().unwrap();
+        assert!(state.authorize(owner, key, Some(201)).is_err());
+        assert!(state.authorize(owner, key, None).is_err());
+        assert!(state.authorize(key, owner, Some(100)).is_err());
+
+        session.spend(6.into()).unwrap();
+        assert!(session.spend(5.into()).is_err());
+        session.spend(4.into()).unwrap();
+        assert_eq!(u64::from(session.remaining()), 0);
+
+        state
+            .update_policy(owner, PolicyCall::RemoveSessionKey(key), None)
+            .unwrap();
+        assert!(state.authorize(owner, key, Some(100)).is_err());
+    }
+
+    #[test]
+    fn recovery_keys() {
+        let store = Shared::new(MapStore::new());
+        let mut state =
+            AuthPlugin::<u64>::create(Store::new(store.into()), Default::default()).unwrap();
+        let owner: Address = [1; 32].into();
+        let recovery: Address = [3; 32].into();
+
+        state
+            .update_policy(owner, PolicyCall::SetRecoveryKey(recovery, 50), None)
+            .unwrap();
+        assert!(state.authorize(owner, recovery, Some(1000)).is_err());
+
+        // Only the recovery key can start a recovery.
+        assert!(state
+            .update_policy(owner, PolicyCall::StartRecovery(owner), Some(100))
+            .is_err());
+        state
+            .update_policy(recovery, PolicyCall::StartRecovery(owner), Some(100))
+            .unwrap();
+        assert!(state.authorize(owner, recovery, Some(149)).is_err());
+        assert!(state
+            .authorize(owner, recovery, Some(150))
+            .unwrap()
+            .is_none());
+
+        // Replacing the key cancels the recovery.
+        state
+            .update_policy(owner, PolicyCall::SetRecoveryKey(recovery, 50), None)
+            .unwrap();
+        assert!(state.authorize(owner, recovery, Some(150)).is_err());
+
+        state
+            .update_policy(owner, PolicyCall::RemoveRecoveryKey, None)
+            .unwrap();
+        assert!(state
+            .update_policy(recovery, PolicyCall::StartRecovery(owner), Some(200))
+            .is_err());
+        assert!(state
+            .update_policy(owner, PolicyCall::SetRecoveryKey(recovery, -1), None)
+            .is_err());
+    }
+
+    #[test]
+    fn session_spending() {
+        let store = Shared::new(MapStore::new());
+        let mut state =
+            AuthPlugin::<Spender>::create(Store::new(store.into()), Default::default()).unwrap();
+        let owner: Address = [1; 32].into();
+        let key: Address = [2; 32].into();
+        state.update_policy(owner, session_key(200), None).unwrap();
+        let remaining = |state: &AuthPlugin<Spender>| {
+            u64::from(
+                state
+                    .session_keys
+                    .get((owner, key))
+                    .unwrap()
+                    .unwrap()
+                    .spend_limit,
+            )
+        };
+
+        let mut call = |amount, fail| {
+            Context::add(Signer { signer: Some(key) });
+            let res = state.call(AuthCall::OnBehalf(owner, (amount, fail)));
+            Context::remove::<Signer>();
+            res
+        };
+        Context::add(Time::from_seconds(100));
+        // Spending is recorded even if the call fails afterwards, since the
+        // coins it moved are not rolled back.
+        let failed = call(6, true);
+        let over_limit = call(5, false);
+        let spent = call(4, false);
+        Context::remove::<Time>();
+
+        assert!(failed.is_err());
+        assert!(over_limit.is_err());
+        spent.unwrap();
+        assert_eq!(state.inner.spent, 10);
+        assert_eq!(remaining(&state), 0);
+    }
+
+    #[test]
+    fn store_layout() {
+        let store = Shared::new(MapStore::new());
+        let mut state = AuthPlugin::<Map<u32, u32>>::create(
+            Store::new(store.clone().into()),
+            Default::default(),
+        )
+        .unwrap();
+        state.inner.insert(1, 2).unwrap();
+        state
+            .update_policy([1; 32].into(), session_key(200), None)
+            .unwrap();
+        state.flush().unwrap();
+
+        // The policy and the inner state are in separate substores, so the
+        // inner state can use any key without reaching the policy.
+        let root: Store = Store::new(store.into());
+        let inner = Map::<u32, u32>::create(root.sub(&[2]), ()).unwrap();
+        assert_eq!(*inner.get(1).unwrap().unwrap(), 2);
+        assert!(root.sub(&[0]).get_next(&[]).unwrap().is_some());
+    }
+}
@@ -0,0 +1,517 @@
+use super::{AppDomain, BeginBlockCtx, EndBlockCtx, InitChainCtx};
+use crate::abci::{BeginBlock, EndBlock, InitChain};
+use crate::call::Call;
+use crate::client::{AsyncCall, Client};
+use crate::encoding::{decode_list, encode_list, list_encoding_length, Decode, Encode};
+use crate::query::Query;
+use crate::state::State;
+use crate::store::Store;
+use crate::{Error, Result};
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// A plugin which allows a single transaction to contain several calls to the
+/// inner state, which are run atomically: if any of the calls fails, the
+/// changes made by the earlier calls in the batch are discarded.
+pub struct BatchPlugin<T> {
+    inner: T,
+    store: Store,
+    /// The encoding of the inner state as of the last batch (or of its
+    /// creation), which replaces it if it can not be flushed or recreated
+    /// while a batch is being started.
+    snapshot: Vec<u8>,
+}
+
+impl<T> Deref for BatchPlugin<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+pub enum BatchCall<T> {
+    Single(T),
+    Batch(Vec<T>),
+}
+
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl<T> Send for BatchCall<T> {}
+
+impl<T: Encode> Encode for BatchCall<T> {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        match self {
+            BatchCall::Single(call) => Ok(1 + call.encoding_length()?),
+            BatchCall::Batch(calls) => Ok(1 + list_encoding_length(calls)?),
+        }
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        match self {
+            BatchCall::Single(call) => {
+                dest.write_all(&[0])?;
+                call.encode_into(dest)
+            }
+            BatchCall::Batch(calls) => {
+                dest.write_all(&[1])?;
+                encode_list(calls, dest)
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for BatchCall<T> {
+    fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
+        let variant = u8::decode(&mut reader)?;
+        match variant {
+            0 => Ok(BatchCall::Single(T::decode(reader)?)),
+            1 => Ok(BatchCall::Batch(decode_list(reader)?)),
+            byte => Err(ed::Error::UnexpectedByte(byte)),
+        }
+    }
+}
+
+impl<T> Call for BatchPlugin<T>
+where
+    T: Call + State,
+{
+    type Call = BatchCall<T::Call>;
+
+    fn call(&mut self, call: Self::Call) -> Result<()> {
+        let calls = match call {
+            BatchCall::Single(call) => return self.inner.call(call),
+            BatchCall::Batch(calls) => calls,
+        };
+
+        if calls.is_empty() {
+            return Err(Error::Batch("Batch must contain at least one call".into()));
+        }
+
+        // Flush the inner state so it can be recreated from its encoding if
+        // any call in the batch fails. `State` types only write to the store
+        // when flushed, so recreating drops every change made by the batch.
+        // The state recreated from the previous snapshot takes its place while
+        // it is flushed, and is kept if flushing or recreating fails.
+        let previous = self.restore()?;
+        let snapshot = std::mem::replace(&mut self.inner, previous).flush()?;
+        T::prune(self.store.clone(), &snapshot)?;
+        self.snapshot = snapshot.encode()?;
+
+        let mut inner = self.restore()?;
+        let res = calls.into_iter().try_for_each(|call| inner.call(call));
+        self.inner = match res {
+            Ok(()) => inner,
+            Err(_) => self.restore()?,
+        };
+
+        res
+    }
+}
+
+impl<T: State> BatchPlugin<T> {
+    fn restore(&self) -> Result<T> {
+        T::create(
+            self.store.clone(),
+            Decode::decode(self.snapshot.as_slice())?,
+        )
+    }
+}
+
+impl<T: Query> Query for BatchPlugin<T> {
+    type Query = T::Query;
+
+    fn query(&self, query: Self::Query) -> Result<()> {
+        self.inner.query(query)
+    }
+}
+
+pub struct BatchAdapter<T, U: Clone> {
+    parent: U,
+    marker: std::marker::PhantomData<T>,
+}
+
+unsafe impl<T, U: Send + Clone> Send for BatchAdapter<T, U> {}
+
+impl<T, U: Clone> Clone for BatchAdapter<T, U> {
+    fn clone(&self) -> Self {
+        BatchAdapter {
+            parent: self.parent.clone(),
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Call, U: AsyncCall<Call = BatchCall<T::Call>> + Clone> AsyncCall for BatchAdapter<T, U>
+where
+    T::Call: Send,
+    U: Send,
+{
+    type Call = T::Call;
+
+    async fn call(&mut self, call: Self::Call) -> Result<()> {
+        self.parent.call(BatchCall::Single(call)).await
+    }
+}
+
+/// Records the calls made through a client instead of sending them, so they
+/// can be sent together as a single batch.
+pub struct BatchCollector<T: Call> {
+    calls: Arc<Mutex<Vec<T::Call>>>,
+}
+
+unsafe impl<T: Call> Send for BatchCollector<T> {}
+
+impl<T: Call> Clone for BatchCollector<T> {
+    fn clone(&self) -> Self {
+        BatchCollector {
+            calls: self.calls.clone(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Call> AsyncCall for BatchCollector<T>
+where
+    T::Call: Send,
+{
+    type Call = T::Call;
+
+    async fn call(&mut self, call: Self::Call) -> Result<()> {
+        self.calls.lock().unwrap().push(call);
+        Ok(())
+    }
+}
+
+pub struct BatchClient<T: Client<BatchAdapter<T, U>>, U: Clone + Send> {
+    inner: T::Client,
+    parent: U,
+}
+
+impl<T, U> BatchClient<T, U>
+where
+    T: Client<BatchAdapter<T, U>> + Client<BatchCollector<T>> + Call,
+    U: AsyncCall<Call = BatchCall<T::Call>> + Clone + Send,
+{
+    /// Sends every call made on the client passed to `build` as a single
+    /// transaction. The calls are run in order, and none of them take effect
+    /// unless all of them succeed.
+    pub async fn batch<F, X>(&mut self, build: F) -> Result<()>
+    where
+        F: FnOnce(<T as Client<BatchCollector<T>>>::Client) -> X,
+        X: Future<Output = Result<()>>,
+    {
+        let collector = BatchCollector {
+            calls: Arc::new(Mutex::new(vec![])),
+        };
+        build(T::create_client(collector.clone())).await?;
+
+        let calls = std::mem::take(&mut *collector.calls.lock().unwrap());
+        if calls.is_empty() {
+            return Err(Error::Client("Batch must contain at least one call".into()));
+        }
+
+        self.parent.call(BatchCall::Batch(calls)).await
+    }
+}
+
+impl<T: Client<BatchAdapter<T, U>>, U: Clone + Send> Clone for BatchClient<T, U>
+where
+    T::Client: Clone,
+{
+    fn clone(&self) -> Self {
+        BatchClient {
+            inner: self.inner.clone(),
+            parent: self.parent.clone(),
+        }
+    }
+}
+
+impl<T: Client<BatchAdapter<T, U>>, U: Clone + Send> Deref for BatchClient<T, U> {
+    type Target = T::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: Client<BatchAdapter<T, U>>, U: Clone + Send> DerefMut for BatchClient<T, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl<T: Client<BatchAdapter<T, U>>, U: Clone + Send> Send for BatchClient<T, U> {}
+
+impl<T: Client<BatchAdapter<T, U>>, U: Clone + Send> Client<U> for BatchPlugin<T> {
+    type Client = BatchClient<T, U>;
+
+    fn create_client(parent: U) -> Self::Client {
+        BatchClient {
+            inner: T::create_client(BatchAdapter {
+                parent: parent.clone(),
+                marker: std::marker::PhantomData,
+            }),
+            parent,
+        }
+    }
+}
+
+impl<T> State for BatchPlugin<T>
+where
+    T: State,
+{
+    type Encoding = (T::Encoding,);
+
+    fn create(store: Store, data: Self::Encoding) -> Result<Self> {
+        Ok(Self {
+            snapshot: data.0.encode()?,
+            inner: T::create(store.clone(), data.0)?,
+            store,
+        })
+    }
+
+    fn flush(self) -> Result<Self::Encoding> {
+        Ok((self.inner.flush()?,))
+    }
+
+    fn prune(store: Store, data: &Self::Encoding) -> Result<()> {
+        T::prune(store, &data.0)
+    }
+}
+
+impl<T> From<BatchPlugin<T>> for (T::Encoding,)
+where
+    T: State,
+{
+    fn from(plugin: BatchPlugin<T>) -> Self {
+        (plugin.inner.into(),)
+    }
+}
+
+impl<T> BeginBlock for BatchPlugin<T>
+where
+    T: BeginBlock + State,
+{
+    fn begin_block(&mut self, ctx: &BeginBlockCtx) -> Result<()> {
+        self.inner.begin_block(ctx)
+    }
+}
+
+impl<T> EndBlock for BatchPlugin<T>
+where
+    T: EndBlock + State,
+{
+    fn end_block(&mut self, ctx: &EndBlockCtx) -> Result<()> {
+        self.inner.end_block(ctx)
+    }
+}
+
+impl<T> InitChain for BatchPlugin<T>
+where
+    T: InitChain + State,
+{
+    fn init_chain(&mut self, ctx: &InitChainCtx) -> Result<()> {
+        self.inner.init_chain(ctx)
+    }
+}
+
+impl<T: State> AppDomain for BatchPlugin<T> {
+    fn app_domain() -> &'static str {
+        T::app_domain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::{Deque, Map};
+    use crate::store::{MapStore, Read, Shared};
+
+    #[derive(State)]
+    struct Counter {
+        pub count: u64,
+    }
+
+    #[derive(Encode, Decode)]
+    enum CounterCall {
+        Increment,
+        Fail,
+    }
+
+    impl Call for Counter {
+        type Call = CounterCall;
+
+        fn call(&mut self, call: Self::Call) -> Result<()> {
+            self.count += 1;
+            match call {
+                CounterCall::Increment => Ok(()),
+                CounterCall::Fail => Err(Error::Test("Failed call".into())),
+            }
+        }
+    }
+
+    fn batch_call<T: Encode + Decode>(calls: Vec<T>) -> BatchCall<T> {
+        let bytes = BatchCall::Batch(calls).encode().unwrap();
+        Decode::decode(bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn batch_calls() {
+        let store = Shared::new(MapStore::new());
+        let mut state =
+            BatchPlugin::<Counter>::create(Store::new(store.into()), Default::default()).unwrap();
+
+        state
+            .call(BatchCall::Single(CounterCall::Increment))
+            .unwrap();
+        assert_eq!(state.count, 1);
+
+        state
+            .call(batch_call(vec![
+                CounterCall::Increment,
+                CounterCall::Increment,
+            ]))
+            .unwrap();
+        assert_eq!(state.count, 3);
+
+        // A failing call discards the changes made by the rest of the batch.
+        assert!(state
+            .call(batch_call(vec![CounterCall::Increment, CounterCall::Fail]))
+            .is_err());
+        assert_eq!(state.count, 3);
+
+        assert!(state.call(batch_call(vec![])).is_err());
+        assert_eq!(state.count, 3);
+    }
+
+    /// A counter which fails to flush when its count is 2.
+    struct FlakyCounter {
+        pub count: u64,
+    }
+
+    impl State for FlakyCounter {
+        type Encoding = (u64,);
+
+        fn create(_: Store, data: Self::Encoding) -> Result<Self> {
+            Ok(FlakyCounter { count: data.0 })
+        }
+
+        fn flush(self) -> Result<Self::Encoding> {
+            if self.count == 2 {
+                return Err(Error::Test("Failed flush".into()));
+            }
+            Ok((self.count,))
+        }
+    }
+
+    impl From<FlakyCounter> for (u64,) {
+        fn from(counter: FlakyCounter) -> Self {
+            (counter.count,)
+        }
+    }
+
+    impl Call for FlakyCounter {
+        type Call = CounterCall;
+
+        fn call(&mut self, _: Self::Call) -> Result<()> {
+            self.count += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn batch_failed_flush() {
+        let store = Shared::new(MapStore::new());
+        let mut state =
+            BatchPlugin::<FlakyCounter>::create(Store::new(store.into()), Default::default())
+                .unwrap();
+
+        state
+            .call(BatchCall::Single(CounterCall::Increment))
+            .unwrap();
+        state
+            .call(batch_call(vec![CounterCall::Increment]))
+            .unwrap();
+        assert_eq!(state.count, 2);
+
+        // The state can not be flushed, so it is recreated from the snapshot
+        // taken by the last batch.
+        assert!(state
+            .call(batch_call(vec![CounterCall::Increment]))
+            .is_err());
+        assert_eq!(state.count, 1);
+
+        state
+            .call(BatchCall::Single(CounterCall::Increment))
+            .unwrap();
+        state
+            .call(BatchCall::Single(CounterCall::Increment))
+            .unwrap();
+        assert_eq!(state.count, 3);
+        assert_eq!(state.flush().unwrap(), ((3,),));
+    }
+
+    #[derive(State)]
+    struct Queues {
+        pub queues: Map<u32, Deque<u64>>,
+    }
+
+    #[derive(Encode, Decode)]
+    enum QueuesCall {
+        Push(u32, u64),
+        Fail,
+    }
+
+    impl Call for Queues {
+        type Call = QueuesCall;
+
+        fn call(&mut self, call: Self::Call) -> Result<()> {
+            match call {
+                QueuesCall::Push(key, value) => self
+                    .queues
+                    .entry(key)?
+                    .or_insert_default()?
+                    .push_back(value),
+                QueuesCall::Fail => Err(Error::Test("Failed call".into())),
+            }
+        }
+    }
+
+    #[test]
+    fn batch_rollback_nested_collections() {
+        let store = Shared::new(MapStore::new());
+        let mut state =
+            BatchPlugin::<Queues>::create(Store::new(store.clone().into()), Default::default())
+                .unwrap();
+
+        state
+            .call(batch_call(vec![
+                QueuesCall::Push(1, 10),
+                QueuesCall::Push(1, 11),
+            ]))
+            .unwrap();
+
+        assert!(state
+            .call(batch_call(vec![
+                QueuesCall::Push(1, 12),
+                QueuesCall::Push(2, 20),
+                QueuesCall::Fail,
+            ]))
+            .is_err());
+
+        {
+            let queue = state.queues.get(1).unwrap().unwrap();
+            assert_eq!(queue.len(), 2);
+            assert_eq!(*queue.back().unwrap().unwrap(), 11);
+            assert!(state.queues.get(2).unwrap().is_none());
+        }
+
+        // Only the deque's meta and its two entries from the first batch were
+        // written.
+        state.flush().unwrap();
+        assert_eq!(store.range(..).count(), 3);
+    }
+}
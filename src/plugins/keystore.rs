@@ -0,0 +1,660 @@
+use super::Pubkey;
+use crate::coins::Address;
+use crate::{Error, Result};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use hmac::Hmac;
+// ed25519-dalek and k256 share this trait from the `signature` crate.
+use k256::ecdsa::{self as secp256k1, signature::Signer};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// A source of signatures for a single account, used by
+/// [`SignerClient`](super::SignerClient) to sign calls.
+pub trait Keystore: Send + Sync {
+    /// Returns the public key of the account, which also determines the
+    /// signature scheme.
+    fn pubkey(&self) -> Result<Pubkey>;
+
+    /// Signs `msg` with the account's private key.
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 64]>;
+
+    /// Returns the address of the account.
+    fn address(&self) -> Result<Address> {
+        Ok(self.pubkey()?.address())
+    }
+}
+
+/// A keystore which keeps an ed25519 keypair in memory, mostly useful for
+/// tests.
+pub struct MemKeystore {
+    keypair: Keypair,
+}
+
+impl MemKeystore {
+    pub fn new(keypair: Keypair) -> Self {
+        MemKeystore { keypair }
+    }
+
+    pub fn generate() -> Self {
+        let mut csprng = OsRng {};
+        MemKeystore::new(Keypair::generate(&mut csprng))
+    }
+
+    pub fn from_secret(secret: &[u8; 32]) -> Result<Self> {
+        Ok(MemKeystore::new(keypair_from_secret(secret)?))
+    }
+}
+
+impl Keystore for MemKeystore {
+    fn pubkey(&self) -> Result<Pubkey> {
+        Ok(Pubkey::Ed25519(self.keypair.public.to_bytes()))
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 64]> {
+        Ok(self.keypair.sign(msg).to_bytes())
+    }
+}
+
+fn keypair_from_secret(secret: &[u8; 32]) -> Result<Keypair> {
+    let secret = SecretKey::from_bytes(secret)?;
+    let public = PublicKey::from(&secret);
+
+    Ok(Keypair { secret, public })
+}
+
+/// A keystore which keeps a secp256k1 key in memory, for accounts using
+/// Ethereum or Bitcoin style keys.
+pub struct Secp256k1Keystore {
+    signing_key: secp256k1::SigningKey,
+}
+
+impl Secp256k1Keystore {
+    pub fn generate() -> Self {
+        let mut csprng = OsRng {};
+        loop {
+            let mut secret = [0; 32];
+            csprng.fill_bytes(&mut secret);
+            // Out-of-range scalars are astronomically unlikely, but are
+            // rejected by `from_secret`.
+            if let Ok(keystore) = Secp256k1Keystore::from_secret(&secret) {
+                return keystore;
+            }
+        }
+    }
+
+    pub fn from_secret(secret: &[u8; 32]) -> Result<Self> {
+        // k256 panics rather than erroring on a zero scalar.
+        if secret.iter().all(|byte| *byte == 0) {
+            return Err(Error::Keystore("Invalid secp256k1 private key".into()));
+        }
+
+        let signing_key = secp256k1::SigningKey::from_bytes(secret)
+            .map_err(|_| Error::Keystore("Invalid secp256k1 private key".into()))?;
+
+        Ok(Secp256k1Keystore { signing_key })
+    }
+}
+
+impl Keystore for Secp256k1Keystore {
+    fn pubkey(&self) -> Result<Pubkey> {
+        let mut bytes = [0; 33];
+        bytes.copy_from_slice(&self.signing_key.verifying_key().to_bytes()[..]);
+
+        Ok(Pubkey::Secp256k1(bytes))
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 64]> {
+        let signature: secp256k1::Signature = self.signing_key.sign(msg);
+        let mut bytes = [0; 64];
+        bytes.copy_from_slice(signature.as_ref());
+
+        Ok(bytes)
+    }
+}
+
+const FILE_KEYSTORE_VERSION: u8 = 1;
+const PBKDF2_ROUNDS: u32 = 100_000;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+/// A keystore which keeps its private key in a file, encrypted with
+/// ChaCha20-Poly1305 under a key derived from a password with PBKDF2.
+///
+/// The file contains a version byte, the salt, the nonce and the ciphertext.
+pub struct FileKeystore {
+    keypair: Keypair,
+    path: PathBuf,
+}
+
+impl FileKeystore {
+    /// Generates a new keypair and writes it to `path`, encrypted with
+    /// `password`. Fails if the file already exists.
+    ///
+    /// On Unix, the file is only readable and writable by its owner.
+    pub fn create<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                Error::Keystore(format!("Keystore file already exists: {}", path.display()))
+            }
+            _ => err.into(),
+        })?;
+
+        let mut csprng = OsRng {};
+        let keypair = Keypair::generate(&mut csprng);
+        file.write_all(&encrypt(keypair.secret.as_bytes(), password)?)?;
+
+        Ok(FileKeystore {
+            keypair,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Loads and decrypts the keypair stored at `path`.
+    pub fn open<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let secret = decrypt(&std::fs::read(path)?, password)?;
+
+        Ok(FileKeystore {
+            keypair: keypair_from_secret(&secret)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Opens the keystore at `path`, creating it if it does not exist.
+    pub fn open_or_create<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
+        if path.as_ref().exists() {
+            FileKeystore::open(path, password)
+        } else {
+            FileKeystore::create(path, password)
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+}
+
+impl Keystore for FileKeystore {
+    fn pubkey(&self) -> Result<Pubkey> {
+        Ok(Pubkey::Ed25519(self.keypair.public.to_bytes()))
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 64]> {
+        Ok(self.keypair.sign(msg).to_bytes())
+    }
+}
+
+/// The environment variable holding the password of the [`DefaultKeystore`].
+pub const KEYSTORE_PASSWORD_VAR: &str = "ORGA_KEYSTORE_PASSWORD";
+
+/// The keystore signing clients use unless they are given another one: a
+/// [`FileKeystore`] at `~/.orga/keystore`, encrypted with the password in the
+/// `ORGA_KEYSTORE_PASSWORD` environment variable.
+///
+/// The file is opened, or created if it does not exist, the first time the
+/// keystore is used, so errors loading it are returned by its methods.
+pub struct DefaultKeystore {
+    path: Option<PathBuf>,
+    keystore: Mutex<Option<Arc<FileKeystore>>>,
+}
+
+impl DefaultKeystore {
+    pub fn new() -> Self {
+        let path = home::home_dir().map(|home| home.join(".orga").join("keystore"));
+
+        DefaultKeystore {
+            path,
+            keystore: Mutex::new(None),
+        }
+    }
+
+    /// Uses the keystore file at `path` instead of `~/.orga/keystore`.
+    pub fn at<P: Into<PathBuf>>(path: P) -> Self {
+        DefaultKeystore {
+            path: Some(path.into()),
+            keystore: Mutex::new(None),
+        }
+    }
+
+    fn load(&self) -> Result<Arc<FileKeystore>> {
+        let mut keystore = self.keystore.lock().unwrap();
+        if let Some(keystore) = keystore.as_ref() {
+            return Ok(keystore.clone());
+        }
+
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| Error::Keystore("No home directory set".into()))?;
+        let password = std::env::var(KEYSTORE_PASSWORD_VAR).map_err(|_| {
+            Error::Keystore(format!(
+                "{} must be set to open the keystore at {}",
+                KEYSTORE_PASSWORD_VAR,
+                path.display()
+            ))
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let loaded = Arc::new(FileKeystore::open_or_create(path, &password)?);
+        *keystore = Some(loaded.clone());
+        Ok(loaded)
+    }
+}
+
+impl Default for DefaultKeystore {
+    fn default() -> Self {
+        DefaultKeystore::new()
+    }
+}
+
+impl Keystore for DefaultKeystore {
+    fn pubkey(&self) -> Result<Pubkey> {
+        self.load()?.pubkey()
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 64]> {
+        self.load()?.sign(msg)
+    }
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> Key {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+fn encrypt(secret: &[u8; 32], password: &str) -> Result<Vec<u8>> {
+    let mut csprng = OsRng {};
+    let mut salt = [0; SALT_LENGTH];
+    csprng.fill_bytes(&mut salt);
+    let mut nonce = [0; NONCE_LENGTH];
+    csprng.fill_bytes(&mut nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(password, &salt));
+    let ciphertext = cipher
+        .encrypt(&Nonce::from(nonce), secret.as_ref())
+        .map_err(|_| Error::Keystore("Failed to encrypt private key".into()))?;
+
+    let mut bytes = vec![FILE_KEYSTORE_VERSION];
+    bytes.extend_from_slice(&salt);
+    bytes.extend_from_slice(&nonce);
+    bytes.extend_from_slice(&ciphertext);
+
+    Ok(bytes)
+}
+
+fn decrypt(bytes: &[u8], password: &str) -> Result<[u8; 32]> {
+    if bytes.len() < 1 + SALT_LENGTH + NONCE_LENGTH {
+        return Err(Error::Keystore("Keystore file is too short".into()));
+    }
+    if bytes[0] != FILE_KEYSTORE_VERSION {
+        return Err(Error::Keystore(format!(
+            "Unsupported keystore version: {}",
+            bytes[0]
+        )));
+    }
+
+    let (salt, rest) = bytes[1..].split_at(SALT_LENGTH);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+    let nonce: [u8; NONCE_LENGTH] = nonce.try_into().unwrap();
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(password, salt));
+    let plaintext = cipher
+        .decrypt(&Nonce::from(nonce), ciphertext)
+        .map_err(|_| Error::Keystore("Incorrect password or corrupted keystore".into()))?;
+
+    let mut secret = [0; 32];
+    if plaintext.len() != secret.len() {
+        return Err(Error::Keystore("Invalid private key length".into()));
+    }
+    secret.copy_from_slice(&plaintext);
+
+    Ok(secret)
+}
+
+/// A set of named accounts, each backed by its own keystore.
+///
+/// The keystore for an account can be passed to a
+/// [`SignerClient`](super::SignerClient), so a single process can sign calls
+/// as any of the accounts.
+#[derive(Default)]
+pub struct MultiKeystore {
+    accounts: BTreeMap<String, Arc<dyn Keystore>>,
+}
+
+impl MultiKeystore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Opens every `.key` file in `dir` as a [`FileKeystore`], named after the
+    /// file's stem. All of the files must be encrypted with `password`.
+    pub fn load_dir<P: AsRef<Path>>(dir: P, password: &str) -> Result<Self> {
+        let mut keystore = MultiKeystore::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("key") {
+                continue;
+            }
+
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            keystore.insert(name, FileKeystore::open(&path, password)?)?;
+        }
+
+        Ok(keystore)
+    }
+
+    /// Adds an account. Fails if an account with the same name already exists.
+    pub fn insert<K: Keystore + 'static>(&mut self, name: String, keystore: K) -> Result<()> {
+        if self.accounts.contains_key(&name) {
+            return Err(Error::Keystore(format!("Account {} already exists", name)));
+        }
+
+        self.accounts.insert(name, Arc::new(keystore));
+        Ok(())
+    }
+
+    /// Adds an account with a newly generated in-memory keypair.
+    pub fn generate(&mut self, name: String) -> Result<Arc<dyn Keystore>> {
+        self.insert(name.clone(), MemKeystore::generate())?;
+        self.get(name.as_str())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Keystore>> {
+        self.accounts
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::Keystore(format!("Unknown account: {}", name)))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Arc<dyn Keystore>> {
+        self.accounts.remove(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.accounts.keys().map(|name| name.as_str())
+    }
+}
+
+/// A keystore which delegates signing to an external signer (e.g. a hardware
+/// wallet bridge or a remote signing service), reached through a local
+/// program.
+///
+/// The program is run with the configured arguments followed by a command:
+/// - `pubkey`: the program writes the account's public key to stdout, either
+///   a 32-byte ed25519 key or a 33-byte compressed secp256k1 key.
+/// - `sign`: the program reads the message from stdin and writes the 64-byte
+///   signature to stdout.
+///
+/// Signatures are verified against the public key before being returned.
+pub struct ExternalKeystore {
+    program: PathBuf,
+    args: Vec<String>,
+    pubkey: Pubkey,
+}
+
+impl ExternalKeystore {
+    pub fn new<P: Into<PathBuf>>(program: P, args: Vec<String>) -> Result<Self> {
+        let program = program.into();
+        let pubkey_bytes = run_signer(&program, &args, "pubkey", &[])?;
+        let pubkey = match pubkey_bytes.len() {
+            32 => Pubkey::Ed25519(pubkey_bytes.as_slice().try_into().unwrap()),
+            33 => Pubkey::Secp256k1(pubkey_bytes.as_slice().try_into().unwrap()),
+            len => {
+                return Err(Error::Keystore(format!(
+                    "External signer returned a {}-byte public key",
+                    len
+                )))
+            }
+        };
+
+        Ok(ExternalKeystore {
+            program,
+            args,
+            pubkey,
+        })
+    }
+}
+
+impl Keystore for ExternalKeystore {
+    fn pubkey(&self) -> Result<Pubkey> {
+        Ok(self.pubkey)
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 64]> {
+        let sig_bytes = run_signer(&self.program, &self.args, "sign", msg)?;
+        let signature: [u8; 64] = sig_bytes.as_slice().try_into().map_err(|_| {
+            Error::Keystore(format!(
+                "External signer returned a {}-byte signature",
+                sig_bytes.len()
+            ))
+        })?;
+        self.pubkey.verify(msg, &signature)?;
+
+        Ok(signature)
+    }
+}
+
+fn run_signer(program: &Path, args: &[String], command: &str, input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+
+    // Write the input from another thread while reading the output, so a
+    // signer which writes before reading all of its input can not fill the
+    // stdout pipe and block forever.
+    let mut stdin = child
+        .stdin
+        .take()
+        .expect("Failed to open external signer stdin");
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_slice()));
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::Keystore(format!(
+            "External signer exited with {}",
+            output.status
+        )));
+    }
+
+    let written = writer
+        .join()
+        .expect("External signer input thread panicked");
+    // the signer may exit without reading all of its input
+    if let Err(err) = written {
+        if err.kind() != std::io::ErrorKind::BrokenPipe {
+            return Err(err.into());
+        }
+    }
+
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{Decode, Encode};
+    use tempdir::TempDir;
+
+    fn verify(keystore: &dyn Keystore, msg: &[u8]) {
+        let signature = keystore.sign(msg).unwrap();
+        keystore.pubkey().unwrap().verify(msg, &signature).unwrap();
+    }
+
+    #[test]
+    fn mem_keystore() {
+        let keystore = MemKeystore::from_secret(&[1; 32]).unwrap();
+        verify(&keystore, b"foo");
+
+        let other = MemKeystore::from_secret(&[1; 32]).unwrap();
+        assert_eq!(keystore.pubkey().unwrap(), other.pubkey().unwrap());
+    }
+
+    #[test]
+    fn secp256k1_keystore() {
+        let keystore = Secp256k1Keystore::from_secret(&[1; 32]).unwrap();
+        verify(&keystore, b"foo");
+
+        let pubkey = keystore.pubkey().unwrap();
+        assert!(matches!(pubkey, Pubkey::Secp256k1(_)));
+        assert_ne!(
+            pubkey.address(),
+            MemKeystore::from_secret(&[1; 32])
+                .unwrap()
+                .address()
+                .unwrap()
+        );
+
+        let signature = keystore.sign(b"foo").unwrap();
+        assert!(pubkey.verify(b"bar", &signature).is_err());
+        assert!(Secp256k1Keystore::from_secret(&[0; 32]).is_err());
+
+        let bytes = Some(pubkey).encode().unwrap();
+        assert_eq!(bytes.len(), 1 + 1 + 33);
+        assert_eq!(
+            Option::<Pubkey>::decode(bytes.as_slice()).unwrap(),
+            Some(pubkey)
+        );
+    }
+
+    #[test]
+    fn file_keystore() {
+        let dir = TempDir::new("orga-keystore").unwrap();
+        let path = dir.path().join("alice.key");
+
+        let created = FileKeystore::create(&path, "hunter2").unwrap();
+        assert!(FileKeystore::create(&path, "hunter2").is_err());
+
+        let opened = FileKeystore::open(&path, "hunter2").unwrap();
+        assert_eq!(created.pubkey().unwrap(), opened.pubkey().unwrap());
+        verify(&opened, b"foo");
+
+        assert!(FileKeystore::open(&path, "hunter3").is_err());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 1 + SALT_LENGTH + NONCE_LENGTH + 32 + 16);
+        assert!(!bytes
+            .windows(32)
+            .any(|window| window == created.keypair.secret.as_bytes()));
+    }
+
+    #[test]
+    fn default_keystore() {
+        let dir = TempDir::new("orga-keystore").unwrap();
+        let path = dir.path().join("orga").join("keystore");
+        std::env::set_var(KEYSTORE_PASSWORD_VAR, "hunter2");
+
+        let keystore = DefaultKeystore::at(&path);
+        verify(&keystore, b"foo");
+        assert_eq!(
+            keystore.pubkey().unwrap(),
+            FileKeystore::open(&path, "hunter2")
+                .unwrap()
+                .pubkey()
+                .unwrap()
+        );
+
+        // Errors loading the file are returned instead of panicking.
+        std::fs::write(dir.path().join("corrupt"), b"not a key").unwrap();
+        let keystore = DefaultKeystore::at(dir.path().join("corrupt"));
+        assert!(keystore.pubkey().is_err());
+        assert!(keystore.sign(b"foo").is_err());
+    }
+
+    #[test]
+    fn multi_keystore() {
+        let dir = TempDir::new("orga-keystore").unwrap();
+        let alice = FileKeystore::create(dir.path().join("alice.key"), "pw").unwrap();
+        let bob = FileKeystore::create(dir.path().join("bob.key"), "pw").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"not a key").unwrap();
+
+        let mut keystore = MultiKeystore::load_dir(dir.path(), "pw").unwrap();
+        assert_eq!(keystore.names().collect::<Vec<_>>(), vec!["alice", "bob"]);
+        assert_eq!(
+            keystore.get("alice").unwrap().pubkey().unwrap(),
+            alice.pubkey().unwrap()
+        );
+        assert_eq!(
+            keystore.get("bob").unwrap().pubkey().unwrap(),
+            bob.pubkey().unwrap()
+        );
+
+        let carol = keystore.generate("carol".into()).unwrap();
+        verify(carol.as_ref(), b"foo");
+        assert!(keystore.generate("carol".into()).is_err());
+        assert!(keystore.get("dave").is_err());
+
+        assert!(MultiKeystore::load_dir(dir.path(), "wrong").is_err());
+    }
+
+    #[test]
+    fn external_keystore() {
+        let dir = TempDir::new("orga-keystore").unwrap();
+        let mem = MemKeystore::from_secret(&[2; 32]).unwrap();
+        let msg = b"foo";
+
+        // A local script stands in for the external signer, replying with
+        // precomputed bytes.
+        let octal = |bytes: &[u8]| -> String {
+            bytes.iter().map(|byte| format!("\\{:03o}", byte)).collect()
+        };
+        let script = format!(
+            "cat > /dev/null\ncase \"$1\" in\n  pubkey) printf '{}' ;;\n  sign) printf '{}' ;;\n  *) exit 1 ;;\nesac\n",
+            octal(mem.pubkey().unwrap().as_bytes()),
+            octal(&mem.sign(msg).unwrap()),
+        );
+        let script_path = dir.path().join("signer.sh");
+        std::fs::write(&script_path, script).unwrap();
+
+        let keystore =
+            ExternalKeystore::new("sh", vec![script_path.to_str().unwrap().into()]).unwrap();
+        assert_eq!(keystore.pubkey().unwrap(), mem.pubkey().unwrap());
+        assert_eq!(keystore.sign(msg).unwrap(), mem.sign(msg).unwrap());
+
+        // The script can only sign the precomputed message.
+        assert!(keystore.sign(b"bar").is_err());
+    }
+
+    #[test]
+    fn external_signer_large_output() {
+        // `cat` writes its output while reading its input, which fills the
+        // stdout pipe before the input is fully written.
+        let input = vec![7; 1 << 20];
+        let args = vec!["-c".into(), "cat".into()];
+        let output = run_signer(Path::new("sh"), &args, "sign", &input).unwrap();
+        assert_eq!(output, input);
+    }
+}
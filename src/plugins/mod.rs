@@ -3,6 +3,21 @@ mod signer;
 #[cfg(feature = "abci")]
 pub use signer::*;
 
+#[cfg(feature = "abci")]
+mod keystore;
+#[cfg(feature = "abci")]
+pub use keystore::*;
+
+#[cfg(feature = "abci")]
+mod multisig;
+#[cfg(feature = "abci")]
+pub use multisig::*;
+
+#[cfg(feature = "abci")]
+mod offline;
+#[cfg(feature = "abci")]
+pub use offline::*;
+
 #[cfg(feature = "abci")]
 mod nonce;
 #[cfg(feature = "abci")]
@@ -13,10 +28,27 @@ mod abci;
 #[cfg(feature = "abci")]
 pub use abci::*;
 
+#[cfg(feature = "abci")]
+mod auth;
+#[cfg(feature = "abci")]
+pub use auth::*;
+
 #[cfg(feature = "abci")]
 mod payable;
 #[cfg(feature = "abci")]
 pub use payable::*;
 
+#[cfg(feature = "abci")]
+mod batch;
+#[cfg(feature = "abci")]
+pub use batch::*;
+
 #[cfg(feature = "abci")]
 pub type DefaultPlugins<T> = SignerPlugin<NoncePlugin<PayablePlugin<T>>>;
+
+/// [`DefaultPlugins`] along with [`AuthPlugin`] and [`BatchPlugin`]. These
+/// change the encoding of calls and move the app's state into a substore, so
+/// an existing app can only switch to this stack on a new chain or with a
+/// migration.
+#[cfg(feature = "abci")]
+pub type AuthBatchPlugins<T> = SignerPlugin<NoncePlugin<AuthPlugin<PayablePlugin<BatchPlugin<T>>>>>;
@@ -0,0 +1,487 @@
+use super::{Expiry, Keystore, NonceAccount, Pubkey, SignerCall, SigningDomain};
+use crate::call::Call;
+use crate::client::AsyncCall;
+use crate::coins::Address;
+use crate::encoding::{Decode, Encode};
+use crate::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+
+/// The most keys a multisig account may have, bounding the work needed to
+/// verify its calls.
+pub const MAX_MULTISIG_KEYS: usize = 32;
+
+/// Tag hashed into multisig addresses, distinct from the scheme tags of
+/// single-key addresses.
+const MULTISIG_ADDRESS_TAG: u8 = 2;
+
+/// A k-of-n account: calls are authorized by signatures from at least
+/// `threshold` of its keys.
+///
+/// Keys are kept sorted and unique, so the same set of keys and threshold
+/// always has the same encoding and address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Multisig {
+    threshold: u8,
+    pubkeys: Vec<Pubkey>,
+}
+
+impl Multisig {
+    pub fn new(threshold: u8, mut pubkeys: Vec<Pubkey>) -> Result<Self> {
+        pubkeys.sort();
+        pubkeys.dedup();
+
+        if pubkeys.len() > MAX_MULTISIG_KEYS {
+            return Err(Error::Signer(format!(
+                "Multisig can have at most {} keys",
+                MAX_MULTISIG_KEYS
+            )));
+        }
+        if threshold == 0 || threshold as usize > pubkeys.len() {
+            return Err(Error::Signer(format!(
+                "Invalid multisig threshold: {} of {}",
+                threshold,
+                pubkeys.len()
+            )));
+        }
+
+        Ok(Multisig { threshold, pubkeys })
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn pubkeys(&self) -> &[Pubkey] {
+        self.pubkeys.as_slice()
+    }
+
+    /// Derives the address of the account from its threshold and keys.
+    pub fn address(&self) -> Result<Address> {
+        let mut hasher = Sha256::new();
+        hasher.update([MULTISIG_ADDRESS_TAG]);
+        hasher.update(self.encode()?);
+        let hash: [u8; 32] = hasher.finalize().into();
+
+        Ok(hash.into())
+    }
+}
+
+fn invalid_data(msg: &str) -> ed::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg).into()
+}
+
+impl Encode for Multisig {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        self.pubkeys
+            .iter()
+            .try_fold(2, |len, pubkey| Ok(len + pubkey.encoding_length()?))
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        dest.write_all(&[self.threshold, self.pubkeys.len() as u8])?;
+        for pubkey in self.pubkeys.iter() {
+            pubkey.encode_into(dest)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Decode for Multisig {
+    fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
+        let threshold = u8::decode(&mut reader)?;
+        let count = u8::decode(&mut reader)?;
+        let mut pubkeys: Vec<Pubkey> = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let pubkey = Pubkey::decode(&mut reader)?;
+            if matches!(pubkeys.last(), Some(last) if *last >= pubkey) {
+                return Err(invalid_data("Multisig keys must be sorted and unique"));
+            }
+            pubkeys.push(pubkey);
+        }
+
+        Multisig::new(threshold, pubkeys).map_err(|_| invalid_data("Invalid multisig"))
+    }
+}
+
+impl ed::Terminated for Multisig {}
+
+/// Signatures from some of a multisig account's keys, ordered like the keys
+/// (with `None` for keys which did not sign).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultisigSignatures {
+    multisig: Multisig,
+    signatures: Vec<Option<[u8; 64]>>,
+}
+
+impl MultisigSignatures {
+    pub fn new(multisig: Multisig) -> Self {
+        let signatures = vec![None; multisig.pubkeys.len()];
+        MultisigSignatures {
+            multisig,
+            signatures,
+        }
+    }
+
+    pub fn multisig(&self) -> &Multisig {
+        &self.multisig
+    }
+
+    pub fn count(&self) -> usize {
+        self.signatures.iter().filter(|sig| sig.is_some()).count()
+    }
+
+    /// Checks every signature against `msg` and returns the multisig's
+    /// address if enough of its keys signed.
+    pub fn verify(&self, msg: &[u8]) -> Result<Address> {
+        for (pubkey, signature) in self.multisig.pubkeys.iter().zip(self.signatures.iter()) {
+            if let Some(signature) = signature {
+                pubkey.verify(msg, signature)?;
+            }
+        }
+
+        let count = self.count();
+        if count < self.multisig.threshold as usize {
+            return Err(Error::Signer(format!(
+                "Multisig threshold not met: {} of {} signatures",
+                count, self.multisig.threshold
+            )));
+        }
+
+        self.multisig.address()
+    }
+}
+
+impl Encode for MultisigSignatures {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        self.signatures
+            .iter()
+            .try_fold(self.multisig.encoding_length()?, |len, sig| {
+                Ok(len + sig.encoding_length()?)
+            })
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        self.multisig.encode_into(dest)?;
+        for signature in self.signatures.iter() {
+            signature.encode_into(dest)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Decode for MultisigSignatures {
+    fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
+        let multisig = Multisig::decode(&mut reader)?;
+        let signatures = multisig
+            .pubkeys
+            .iter()
+            .map(|_| Decode::decode(&mut reader))
+            .collect::<ed::Result<_>>()?;
+
+        Ok(MultisigSignatures {
+            multisig,
+            signatures,
+        })
+    }
+}
+
+impl ed::Terminated for MultisigSignatures {}
+
+/// A call to be made by a multisig account, carrying the signatures gathered
+/// so far.
+///
+/// Proposals can be encoded and passed between the account's key holders
+/// offline. Each holder signs their copy, the copies are combined, and once
+/// the threshold is met the proposal can be broadcast as a normal
+/// [`SignerCall`].
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct MultisigProposal {
+    signatures: MultisigSignatures,
+    domain: SigningDomain,
+    expiry: Expiry,
+    call_bytes: Vec<u8>,
+}
+
+impl MultisigProposal {
+    pub fn new(
+        multisig: Multisig,
+        domain: SigningDomain,
+        expiry: Expiry,
+        call_bytes: Vec<u8>,
+    ) -> Self {
+        MultisigProposal {
+            signatures: MultisigSignatures::new(multisig),
+            domain,
+            expiry,
+            call_bytes,
+        }
+    }
+
+    pub fn multisig(&self) -> &Multisig {
+        self.signatures.multisig()
+    }
+
+    /// The chain and app domain the proposal is signed for.
+    pub fn domain(&self) -> &SigningDomain {
+        &self.domain
+    }
+
+    pub fn expiry(&self) -> &Expiry {
+        &self.expiry
+    }
+
+    pub fn call_bytes(&self) -> &[u8] {
+        self.call_bytes.as_slice()
+    }
+
+    /// Adds a signature from `keystore`, which must hold one of the
+    /// multisig's keys.
+    pub fn sign(&mut self, keystore: &dyn Keystore) -> Result<()> {
+        let pubkey = keystore.pubkey()?;
+        let index = self
+            .multisig()
+            .pubkeys
+            .iter()
+            .position(|key| *key == pubkey)
+            .ok_or_else(|| Error::Signer("Key is not part of the multisig".into()))?;
+
+        let msg = self
+            .domain
+            .message(&self.expiry, self.call_bytes.as_slice())?;
+        let signature = keystore.sign(msg.as_slice())?;
+        self.signatures.signatures[index] = Some(signature);
+
+        Ok(())
+    }
+
+    /// Copies over the signatures from another copy of the same proposal.
+    pub fn combine(&mut self, other: &MultisigProposal) -> Result<()> {
+        if self.multisig() != other.multisig()
+            || self.domain != other.domain
+            || self.expiry != other.expiry
+            || self.call_bytes != other.call_bytes
+        {
+            return Err(Error::Signer(
+                "Cannot combine signatures for different proposals".into(),
+            ));
+        }
+
+        for (signature, other_signature) in self
+            .signatures
+            .signatures
+            .iter_mut()
+            .zip(other.signatures.signatures.iter())
+        {
+            if signature.is_none() {
+                *signature = *other_signature;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.signatures.count() >= self.multisig().threshold as usize
+    }
+
+    /// Converts the proposal into a call which can be broadcast, checking that
+    /// it has enough valid signatures.
+    pub fn into_call(self) -> Result<SignerCall> {
+        let msg = self
+            .domain
+            .message(&self.expiry, self.call_bytes.as_slice())?;
+        self.signatures.verify(msg.as_slice())?;
+
+        Ok(SignerCall {
+            signature: None,
+            pubkey: None,
+            multisig: Some(self.signatures),
+            expiry: self.expiry,
+            call_bytes: self.call_bytes,
+        })
+    }
+}
+
+/// Records the call made through a client instead of signing and sending it,
+/// so it can be turned into a [`MultisigProposal`] or an
+/// [`UnsignedTx`](super::UnsignedTx).
+pub struct ProposalAdapter<T: Call> {
+    call_bytes: Arc<Mutex<Option<Vec<u8>>>>,
+    chain_id: String,
+    address: Address,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Call> ProposalAdapter<T> {
+    pub(super) fn new(chain_id: String, address: Address) -> Self {
+        ProposalAdapter {
+            call_bytes: Arc::new(Mutex::new(None)),
+            chain_id,
+            address,
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    pub(super) fn take(&self) -> Result<Vec<u8>> {
+        self.call_bytes
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| Error::Client("Must make a call to propose".into()))
+    }
+}
+
+unsafe impl<T: Call> Send for ProposalAdapter<T> {}
+
+impl<T: Call> Clone for ProposalAdapter<T> {
+    fn clone(&self) -> Self {
+        ProposalAdapter {
+            call_bytes: self.call_bytes.clone(),
+            chain_id: self.chain_id.clone(),
+            address: self.address,
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Call> NonceAccount for ProposalAdapter<T> {
+    async fn chain_id(&mut self) -> Result<String> {
+        Ok(self.chain_id.clone())
+    }
+
+    fn address(&self) -> Result<Address> {
+        Ok(self.address)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Call> AsyncCall for ProposalAdapter<T>
+where
+    T::Call: Send,
+{
+    type Call = T::Call;
+
+    async fn call(&mut self, call: Self::Call) -> Result<()> {
+        let mut call_bytes = self.call_bytes.lock().unwrap();
+        if call_bytes.is_some() {
+            return Err(Error::Client(
+                "Multisig proposals can only contain one call".into(),
+            ));
+        }
+        call_bytes.replace(call.encode()?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{MemKeystore, Secp256k1Keystore};
+    use super::*;
+
+    fn keystores() -> Vec<Box<dyn Keystore>> {
+        vec![
+            Box::new(MemKeystore::from_secret(&[1; 32]).unwrap()),
+            Box::new(MemKeystore::from_secret(&[2; 32]).unwrap()),
+            Box::new(Secp256k1Keystore::from_secret(&[3; 32]).unwrap()),
+        ]
+    }
+
+    fn domain() -> SigningDomain {
+        SigningDomain::new("test-chain".into(), String::new())
+    }
+
+    fn multisig(threshold: u8) -> Multisig {
+        let pubkeys = keystores().iter().map(|k| k.pubkey().unwrap()).collect();
+        Multisig::new(threshold, pubkeys).unwrap()
+    }
+
+    #[test]
+    fn multisig_address() {
+        let mut pubkeys: Vec<_> = keystores().iter().map(|k| k.pubkey().unwrap()).collect();
+        let address = Multisig::new(2, pubkeys.clone())
+            .unwrap()
+            .address()
+            .unwrap();
+
+        pubkeys.reverse();
+        pubkeys.push(pubkeys[0]);
+        let reordered = Multisig::new(2, pubkeys.clone()).unwrap();
+        assert_eq!(reordered.pubkeys().len(), 3);
+        assert_eq!(reordered.address().unwrap(), address);
+
+        assert_ne!(multisig(1).address().unwrap(), address);
+        assert!(Multisig::new(0, pubkeys.clone()).is_err());
+        assert!(Multisig::new(4, pubkeys).is_err());
+
+        let bytes = multisig(2).encode().unwrap();
+        assert_eq!(Multisig::decode(bytes.as_slice()).unwrap(), multisig(2));
+    }
+
+    #[test]
+    fn gather_signatures() {
+        let keystores = keystores();
+        let call_bytes = vec![1, 2, 3];
+        let proposal =
+            MultisigProposal::new(multisig(2), domain(), Expiry::default(), call_bytes.clone());
+
+        // Each key holder signs their own copy of the encoded proposal.
+        let bytes = proposal.encode().unwrap();
+        let mut first = MultisigProposal::decode(bytes.as_slice()).unwrap();
+        first.sign(keystores[0].as_ref()).unwrap();
+        assert!(!first.is_ready());
+        assert!(first.clone().into_call().is_err());
+
+        let mut second = MultisigProposal::decode(bytes.as_slice()).unwrap();
+        second.sign(keystores[2].as_ref()).unwrap();
+
+        first.combine(&second).unwrap();
+        assert!(first.is_ready());
+
+        let outsider = MemKeystore::from_secret(&[4; 32]).unwrap();
+        assert!(first.sign(&outsider).is_err());
+
+        let other = MultisigProposal::new(multisig(2), domain(), Expiry::default(), vec![4, 5, 6]);
+        assert!(first.combine(&other).is_err());
+        let other_chain = SigningDomain::new("other-chain".into(), String::new());
+        let other = MultisigProposal::new(
+            multisig(2),
+            other_chain,
+            Expiry::default(),
+            call_bytes.clone(),
+        );
+        assert!(first.combine(&other).is_err());
+        let expiry = Expiry {
+            valid_until_height: Some(100),
+            valid_until_time: None,
+        };
+        let other = MultisigProposal::new(multisig(2), domain(), expiry, call_bytes.clone());
+        assert!(first.combine(&other).is_err());
+
+        let call = first.into_call().unwrap();
+        let call = SignerCall::decode(call.encode().unwrap().as_slice()).unwrap();
+        let msg = domain()
+            .message(&Expiry::default(), call_bytes.as_slice())
+            .unwrap();
+        assert_eq!(
+            call.verify(msg.as_slice()).unwrap(),
+            Some(multisig(2).address().unwrap())
+        );
+    }
+
+    #[test]
+    fn invalid_signature() {
+        let keystores = keystores();
+        let mut proposal =
+            MultisigProposal::new(multisig(1), domain(), Expiry::default(), vec![1, 2, 3]);
+        proposal.sign(keystores[1].as_ref()).unwrap();
+
+        let call = proposal.into_call().unwrap();
+        let msg = domain().message(&Expiry::default(), &[7, 8, 9]).unwrap();
+        assert!(call.verify(msg.as_slice()).is_err());
+    }
+}
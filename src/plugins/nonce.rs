@@ -1,4 +1,4 @@
-use super::{BeginBlockCtx, EndBlockCtx, InitChainCtx, Signer};
+use super::{AppDomain, BeginBlockCtx, EndBlockCtx, InitChainCtx, Signer};
 use crate::abci::{BeginBlock, EndBlock, InitChain};
 use crate::call::Call;
 use crate::client::Client;
@@ -7,11 +7,14 @@ use crate::collections::Map;
 use crate::context::GetContext;
 use crate::encoding::{Decode, Encode};
 use crate::prelude::AsyncCall;
-use crate::query::Query;
+use crate::query::{self, Query};
 use crate::state::State;
 use crate::{Error, Result};
+use fs2::FileExt;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 const NONCE_INCREASE_LIMIT: u64 = 1000;
 
@@ -80,14 +83,80 @@ where
     }
 }
 
+#[orga::methods]
+impl<T> NoncePlugin<T>
+where
+    T: State,
+{
+    /// Returns the nonce of the last call accepted from `address`. Its next
+    /// signed call must use a higher nonce.
+    #[query]
+    pub fn nonce(&self, address: Address) -> Result<u64> {
+        Ok(self
+            .map
+            .get(address)?
+            .map(|nonce| *nonce)
+            .unwrap_or_default())
+    }
+}
+
+/// The query type of [`NoncePlugin`], reaching either the inner state or the
+/// plugin's own `#[query]` methods.
+///
+/// Queries used to be passed straight to the inner state, so this wrapper
+/// changes the query encoding: clients have to be upgraded along with the
+/// nodes they query, and encoded inner queries must now be wrapped in
+/// `NonceQuery::Inner`.
+#[derive(Encode, Decode)]
+pub enum NonceQuery<T> {
+    Inner(T),
+    Method(Vec<u8>),
+}
+
 impl<T: Query + State> Query for NoncePlugin<T> {
-    type Query = T::Query;
+    type Query = NonceQuery<T::Query>;
 
     fn query(&self, query: Self::Query) -> Result<()> {
-        self.inner.query(query)
+        match query {
+            NonceQuery::Inner(query) => self.inner.query(query),
+            NonceQuery::Method(query_bytes) => query::maybe_method_query(self, query_bytes),
+        }
+    }
+}
+
+/// Implemented by the clients beneath [`NonceClient`] which sign its calls, so
+/// it knows which chain and account to track nonces for.
+#[async_trait::async_trait]
+pub trait NonceAccount {
+    /// Returns the id of the chain calls are signed for, which may be fetched
+    /// from the node on first use.
+    async fn chain_id(&mut self) -> Result<String>;
+
+    fn address(&self) -> Result<Address>;
+
+    /// The directory to keep nonce files in, or `None` to keep them under
+    /// `~/.orga/nonces`.
+    fn nonce_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Fetches the nonce of the last call accepted on chain from the account,
+    /// or `None` if the chain can not be queried, e.g. for calls signed
+    /// offline.
+    async fn chain_nonce(&mut self) -> Result<Option<u64>> {
+        Ok(None)
     }
 }
 
+/// Implemented by the clients which send signed calls to a chain and can query
+/// it, so [`NonceClient`] can resync its local nonce with the chain when a
+/// call is rejected for its nonce.
+#[async_trait::async_trait]
+pub trait NonceSource {
+    /// Fetches the nonce of the last call accepted on chain from `address`.
+    async fn nonce(&mut self, address: Address) -> Result<u64>;
+}
+
 pub struct NonceClient<T, U: Clone> {
     parent: U,
     marker: std::marker::PhantomData<T>,
@@ -105,7 +174,8 @@ impl<T, U: Clone> Clone for NonceClient<T, U> {
 }
 
 #[async_trait::async_trait]
-impl<T: Call, U: AsyncCall<Call = NonceCall<T::Call>> + Clone> AsyncCall for NonceClient<T, U>
+impl<T: Call, U: AsyncCall<Call = NonceCall<T::Call>> + NonceAccount + Clone> AsyncCall
+    for NonceClient<T, U>
 where
     T::Call: Send,
     U: Send,
@@ -113,18 +183,35 @@ where
     type Call = T::Call;
 
     async fn call(&mut self, call: Self::Call) -> Result<()> {
-        // Load nonce from file
-        let nonce = load_nonce()?;
-
-        let res = self.parent.call(NonceCall {
-            inner_call: call,
-            nonce: Some(nonce),
-        });
-
-        // Increment the local nonce
-        write_nonce(nonce + 1)?;
+        let chain_id = self.parent.chain_id().await?;
+        let nonce_dir = self.parent.nonce_dir();
+        let nonce_file = NonceFile::open_under(
+            nonce_dir.as_deref(),
+            chain_id.as_str(),
+            self.parent.address()?,
+        )?;
+        let nonce = nonce_file.next()?;
+
+        let res = self
+            .parent
+            .call(NonceCall {
+                inner_call: call,
+                nonce: Some(nonce),
+            })
+            .await;
+
+        // The node rejected the nonce, e.g. because calls signed with earlier
+        // nonces were dropped or another client signed for the same account,
+        // so the next call continues from the nonce last accepted on chain.
+        if let Err(Error::Nonce(_)) = res {
+            match self.parent.chain_nonce().await {
+                Ok(Some(chain_nonce)) => nonce_file.set(chain_nonce)?,
+                Ok(None) => {}
+                Err(err) => log::warn!("Failed to resync nonce: {}", err),
+            }
+        }
 
-        res.await
+        res
     }
 }
 
@@ -139,30 +226,106 @@ impl<T: Client<NonceClient<T, U>> + State, U: Clone> Client<U> for NoncePlugin<T
     }
 }
 
-fn nonce_path() -> Result<PathBuf> {
-    let orga_home = home::home_dir()
-        .expect("No home directory set")
-        .join(".orga");
-
-    std::fs::create_dir_all(&orga_home)?;
-    Ok(orga_home.join("nonce"))
+/// The nonce of the last call signed by an account on one chain, kept in a
+/// file so it persists between runs.
+///
+/// The file is locked while the nonce is read and updated, so several
+/// processes can safely sign calls for the same account.
+pub struct NonceFile {
+    path: PathBuf,
 }
 
-fn load_nonce() -> Result<u64> {
-    let nonce_path = nonce_path()?;
-    if nonce_path.exists() {
-        let bytes = std::fs::read(&nonce_path)?;
-        Ok(Decode::decode(bytes.as_slice())?)
-    } else {
-        let bytes = 0.encode()?;
-        std::fs::write(&nonce_path, bytes)?;
-        Ok(0)
+impl NonceFile {
+    /// Opens the nonce file for `address` on the chain with id `chain_id`,
+    /// stored under `~/.orga/nonces`.
+    pub fn open(chain_id: &str, address: Address) -> Result<Self> {
+        Self::open_under(None, chain_id, address)
+    }
+
+    /// Opens the nonce file for `address` on the chain with id `chain_id`,
+    /// stored under `root` if it is given, otherwise under `~/.orga/nonces`.
+    pub fn open_under(root: Option<&Path>, chain_id: &str, address: Address) -> Result<Self> {
+        let root = match root {
+            Some(root) => root.to_path_buf(),
+            None => home::home_dir()
+                .expect("No home directory set")
+                .join(".orga")
+                .join("nonces"),
+        };
+
+        Self::open_in(root.join(chain_dir(chain_id)), address)
+    }
+
+    /// Opens the nonce file for `address` in the given directory.
+    pub fn open_in<P: AsRef<Path>>(dir: P, address: Address) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+
+        Ok(NonceFile {
+            path: dir.as_ref().join(address.to_string()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Returns the nonce of the last call signed for the account, or 0 if it
+    /// has not signed any calls.
+    pub fn get(&self) -> Result<u64> {
+        self.update(|nonce| nonce)
+    }
+
+    /// Reserves and returns the nonce to use for the account's next call.
+    pub fn next(&self) -> Result<u64> {
+        self.update(|nonce| nonce + 1)
+    }
+
+    /// Overwrites the stored nonce, e.g. with the nonce last accepted on chain
+    /// after some signed calls failed to be included.
+    pub fn set(&self, nonce: u64) -> Result<()> {
+        self.update(|_| nonce)?;
+
+        Ok(())
+    }
+
+    fn update<F: FnOnce(u64) -> u64>(&self, op: F) -> Result<u64> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        // The lock is released when the file is closed
+        file.lock_exclusive()?;
+
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        let nonce = if bytes.is_empty() {
+            0
+        } else {
+            Decode::decode(bytes.as_slice())?
+        };
+
+        let nonce = op(nonce);
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        file.write_all(nonce.encode()?.as_slice())?;
+        file.sync_all()?;
+
+        Ok(nonce)
     }
 }
 
-fn write_nonce(nonce: u64) -> Result<()> {
-    let nonce_path = nonce_path()?;
-    Ok(std::fs::write(&nonce_path, nonce.encode()?)?)
+/// Returns the name of the directory holding the nonce files of the chain
+/// with id `chain_id`. The id is hex-encoded, so every chain id maps to its
+/// own directory and can not escape the nonce directory.
+fn chain_dir(chain_id: &str) -> String {
+    let hex: String = chain_id
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!("chain-{}", hex)
 }
 
 // TODO: Remove dependency on ABCI for this otherwise-pure plugin.
@@ -194,12 +357,19 @@ where
     }
 }
 
+impl<T: State> AppDomain for NoncePlugin<T> {
+    fn app_domain() -> &'static str {
+        T::app_domain()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::Signer;
     use super::*;
     use crate::context::Context;
     use crate::store::{MapStore, Shared, Store};
+    use std::sync::{Arc, Mutex};
 
     #[derive(State)]
     struct Counter {
@@ -266,6 +436,8 @@ mod tests {
         // Signed, correct nonce
         state.call(nonced_call(1)).unwrap();
         assert_eq!(state.inner.count, 2);
+        assert_eq!(state.nonce([0; 32].into()).unwrap(), 1);
+        assert_eq!(state.nonce([1; 32].into()).unwrap(), 0);
 
         // Signed, but nonce incremented by too much
         assert!(state.call(nonced_call(2000)).is_err());
@@ -277,4 +449,122 @@ mod tests {
         assert!(state.call(unnonced_call()).is_err());
         Context::remove::<Signer>();
     }
+
+    #[test]
+    fn nonce_file() {
+        let dir = tempdir::TempDir::new("nonces").unwrap();
+        let open = |chain: &str, address: [u8; 32]| {
+            NonceFile::open_in(dir.path().join(chain), address.into()).unwrap()
+        };
+
+        let alice = open("chain-a", [1; 32]);
+        assert_eq!(alice.get().unwrap(), 0);
+        assert_eq!(alice.next().unwrap(), 1);
+        assert_eq!(alice.next().unwrap(), 2);
+
+        // Nonces are tracked separately per account and per chain.
+        assert_eq!(open("chain-a", [2; 32]).next().unwrap(), 1);
+        assert_eq!(open("chain-b", [1; 32]).next().unwrap(), 1);
+        assert_eq!(open("chain-a", [1; 32]).get().unwrap(), 2);
+
+        alice.set(7).unwrap();
+        assert_eq!(alice.next().unwrap(), 8);
+
+        // Concurrent writers never reserve the same nonce.
+        let path = dir.path().join("chain-a");
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let file = NonceFile::open_in(path, [1; 32].into()).unwrap();
+                    (0..25).map(|_| file.next().unwrap()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut nonces: Vec<_> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        nonces.sort_unstable();
+        assert_eq!(nonces, (9..109).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn chain_dirs() {
+        assert_eq!(chain_dir("test-1"), "chain-746573742d31");
+        assert_eq!(chain_dir(""), "chain-");
+
+        // Ids which only differ in characters that are not valid in paths
+        // still get their own directories.
+        assert_ne!(chain_dir("a/b"), chain_dir("a_b"));
+        assert_ne!(chain_dir("a b"), chain_dir("a_b"));
+        assert!(!chain_dir("../..").contains('/'));
+    }
+
+    /// Accepts calls like a chain which has accepted the nonces of its account
+    /// up to `nonce`.
+    #[derive(Clone)]
+    struct NonceChain {
+        nonce: Arc<Mutex<u64>>,
+        dir: PathBuf,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncCall for NonceChain {
+        type Call = NonceCall<CounterCall>;
+
+        async fn call(&mut self, call: Self::Call) -> Result<()> {
+            let mut nonce = self.nonce.lock().unwrap();
+            match call.nonce {
+                Some(call_nonce) if call_nonce > *nonce => {
+                    *nonce = call_nonce;
+                    Ok(())
+                }
+                _ => Err(Error::Nonce("Nonce is not valid".into())),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl NonceAccount for NonceChain {
+        async fn chain_id(&mut self) -> Result<String> {
+            Ok("test".into())
+        }
+
+        fn address(&self) -> Result<Address> {
+            Ok([1; 32].into())
+        }
+
+        fn nonce_dir(&self) -> Option<PathBuf> {
+            Some(self.dir.clone())
+        }
+
+        async fn chain_nonce(&mut self) -> Result<Option<u64>> {
+            Ok(Some(*self.nonce.lock().unwrap()))
+        }
+    }
+
+    #[test]
+    fn nonce_client_resync() {
+        let dir = tempdir::TempDir::new("nonces").unwrap();
+        let chain = NonceChain {
+            nonce: Arc::new(Mutex::new(0)),
+            dir: dir.path().to_path_buf(),
+        };
+        let mut client = NonceClient::<Counter, _> {
+            parent: chain.clone(),
+            marker: std::marker::PhantomData,
+        };
+        let mut increment = || futures_lite::future::block_on(client.call(CounterCall::Increment));
+
+        increment().unwrap();
+        assert_eq!(*chain.nonce.lock().unwrap(), 1);
+
+        // Another client signs calls for the same account, so the local nonce
+        // is rejected once and then continues from the chain's.
+        *chain.nonce.lock().unwrap() = 5;
+        assert!(matches!(increment(), Err(Error::Nonce(_))));
+        increment().unwrap();
+        assert_eq!(*chain.nonce.lock().unwrap(), 6);
+    }
 }
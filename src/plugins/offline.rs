@@ -0,0 +1,162 @@
+use super::{Expiry, Keystore, Pubkey, SignerCall, SigningDomain};
+use crate::encoding::{Decode, Encode};
+use crate::{Error, Result};
+
+/// The version of the [`UnsignedTx`] encoding. It is written as the first
+/// byte of every encoded transaction, so files written by one version of a
+/// client are rejected by an incompatible one instead of being misread.
+pub const UNSIGNED_TX_VERSION: u8 = 0;
+
+/// A call which has been built, but not yet signed, so it can be signed on
+/// another machine.
+///
+/// The online machine builds the transaction with
+/// [`SignerPluginClient::build_unsigned`](super::SignerPluginClient::build_unsigned)
+/// and writes its encoding to a file. The machine holding the key decodes it,
+/// signs it with [`sign`](Self::sign), and writes the encoding of the
+/// resulting [`SignerCall`], which is the raw transaction the online machine
+/// then broadcasts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnsignedTx {
+    domain: SigningDomain,
+    expiry: Expiry,
+    pubkey: Pubkey,
+    call_bytes: Vec<u8>,
+}
+
+impl UnsignedTx {
+    pub fn new(domain: SigningDomain, expiry: Expiry, pubkey: Pubkey, call_bytes: Vec<u8>) -> Self {
+        UnsignedTx {
+            domain,
+            expiry,
+            pubkey,
+            call_bytes,
+        }
+    }
+
+    /// The chain and app domain the transaction will be signed for.
+    pub fn domain(&self) -> &SigningDomain {
+        &self.domain
+    }
+
+    pub fn expiry(&self) -> &Expiry {
+        &self.expiry
+    }
+
+    /// The key which must sign the transaction.
+    pub fn pubkey(&self) -> &Pubkey {
+        &self.pubkey
+    }
+
+    pub fn call_bytes(&self) -> &[u8] {
+        self.call_bytes.as_slice()
+    }
+
+    /// Signs the transaction with `keystore`, which must hold the key it was
+    /// built for.
+    pub fn sign(&self, keystore: &dyn Keystore) -> Result<SignerCall> {
+        if keystore.pubkey()? != self.pubkey {
+            return Err(Error::Signer(
+                "Keystore does not hold the key the transaction was built for".into(),
+            ));
+        }
+
+        let msg = self
+            .domain
+            .message(&self.expiry, self.call_bytes.as_slice())?;
+
+        Ok(SignerCall {
+            signature: Some(keystore.sign(msg.as_slice())?),
+            pubkey: Some(self.pubkey),
+            multisig: None,
+            expiry: self.expiry,
+            call_bytes: self.call_bytes.clone(),
+        })
+    }
+}
+
+impl Encode for UnsignedTx {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        Ok(1 + self.domain.encoding_length()?
+            + self.expiry.encoding_length()?
+            + self.pubkey.encoding_length()?
+            + self.call_bytes.len())
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        UNSIGNED_TX_VERSION.encode_into(dest)?;
+        self.domain.encode_into(dest)?;
+        self.expiry.encode_into(dest)?;
+        self.pubkey.encode_into(dest)?;
+        dest.write_all(self.call_bytes.as_slice())?;
+
+        Ok(())
+    }
+}
+
+impl Decode for UnsignedTx {
+    fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
+        let version = u8::decode(&mut reader)?;
+        if version != UNSIGNED_TX_VERSION {
+            return Err(ed::Error::UnexpectedByte(version));
+        }
+
+        let domain = SigningDomain::decode(&mut reader)?;
+        let expiry = Expiry::decode(&mut reader)?;
+        let pubkey = Pubkey::decode(&mut reader)?;
+        let mut call_bytes = vec![];
+        reader.read_to_end(&mut call_bytes)?;
+
+        Ok(UnsignedTx {
+            domain,
+            expiry,
+            pubkey,
+            call_bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MemKeystore;
+    use super::*;
+
+    #[test]
+    fn sign_offline() {
+        let keystore = MemKeystore::from_secret(&[1; 32]).unwrap();
+        let domain = SigningDomain::new("test-chain".into(), String::new());
+        let expiry = Expiry {
+            valid_until_height: Some(100),
+            valid_until_time: None,
+        };
+        let tx = UnsignedTx::new(
+            domain.clone(),
+            expiry,
+            keystore.pubkey().unwrap(),
+            vec![1, 2, 3],
+        );
+
+        // The transaction is passed to the signing machine as bytes, and the
+        // signed call comes back as bytes.
+        let bytes = tx.encode().unwrap();
+        assert_eq!(bytes[0], UNSIGNED_TX_VERSION);
+        let decoded = UnsignedTx::decode(bytes.as_slice()).unwrap();
+        assert_eq!(decoded, tx);
+
+        let signed = decoded.sign(&keystore).unwrap().encode().unwrap();
+        let call = SignerCall::decode(signed.as_slice()).unwrap();
+        let msg = domain.message(&expiry, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            call.verify(msg.as_slice()).unwrap(),
+            Some(keystore.address().unwrap())
+        );
+        assert_eq!(call.call_bytes, vec![1, 2, 3]);
+
+        let other = MemKeystore::from_secret(&[2; 32]).unwrap();
+        assert!(tx.sign(&other).is_err());
+
+        let mut bytes = bytes;
+        bytes[0] = UNSIGNED_TX_VERSION + 1;
+        assert!(UnsignedTx::decode(bytes.as_slice()).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use super::{BeginBlockCtx, EndBlockCtx, InitChainCtx};
+use super::{AppDomain, BeginBlockCtx, EndBlockCtx, InitChainCtx};
 use crate::abci::{BeginBlock, EndBlock, InitChain};
 use crate::call::Call;
 use crate::client::{AsyncCall, Client};
@@ -28,9 +28,15 @@ impl<T: State> Deref for PayablePlugin<T> {
 #[derive(Default)]
 pub struct Paid {
     map: HashMap<TypeId, Amount>,
+    call_bytes: Vec<u8>,
 }
 
 impl Paid {
+    /// The encoding of the call being paid for.
+    pub fn call_bytes(&self) -> &[u8] {
+        &self.call_bytes
+    }
+
     pub fn give<S: Symbol, A: Into<Amount>>(&mut self, amount: A) -> Result<()> {
         let entry = self
             .map
@@ -118,7 +124,10 @@ where
         match call {
             PayableCall::Unpaid(call) => self.inner.call(call),
             PayableCall::Paid(calls) => {
-                Context::add(Paid::default());
+                Context::add(Paid {
+                    call_bytes: calls.paid.encode()?,
+                    ..Default::default()
+                });
                 self.inner.call(calls.payer)?;
                 let res = self.inner.call(calls.paid)?;
                 Ok(res)
@@ -358,3 +367,9 @@ where
         self.inner.init_chain(ctx)
     }
 }
+
+impl<T: State> AppDomain for PayablePlugin<T> {
+    fn app_domain() -> &'static str {
+        T::app_domain()
+    }
+}
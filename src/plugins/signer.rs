@@ -1,4 +1,8 @@
-use super::{BeginBlockCtx, EndBlockCtx, InitChainCtx};
+use super::{
+    BeginBlockCtx, CheckTxCtx, DefaultKeystore, EndBlockCtx, InitChainCtx, Keystore, Multisig,
+    MultisigProposal, MultisigSignatures, NonceAccount, NonceSource, ProposalAdapter, Time,
+    UnsignedTx,
+};
 use crate::abci::{BeginBlock, EndBlock, InitChain};
 use crate::call::Call;
 use crate::client::{AsyncCall, Client};
@@ -9,11 +13,29 @@ use crate::query::Query;
 use crate::state::State;
 use crate::store::Store;
 use crate::{Error, Result};
-use ed25519_dalek::{Keypair, PublicKey, Signature, Signer as Ed25519Signer};
-use std::ops::Deref;
-
+use ed25519_dalek::{Keypair, PublicKey, Signature};
+use k256::ecdsa::{self as secp256k1, signature::Verifier};
+use sha2::{Digest, Sha256};
+use std::convert::{TryFrom, TryInto};
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A plugin which verifies the signature on each call before passing it to
+/// the inner state, with the signer's address available through the
+/// [`Signer`] context.
+///
+/// The plugin's encoding is the chain's [`SigningDomain`] and the height of
+/// the last block, followed by the inner state's encoding. This is a breaking
+/// change to the stored state: data written by earlier versions, which only
+/// stored the inner encoding, can not be loaded, so existing chains have to
+/// restart from a new genesis to upgrade.
 pub struct SignerPlugin<T> {
     inner: T,
+    domain: SigningDomain,
+    height: u64,
 }
 
 impl<T> Deref for SignerPlugin<T> {
@@ -28,35 +50,296 @@ pub struct Signer {
     pub signer: Option<Address>,
 }
 
+/// The public key of a call's signer, tagged with its signature scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pubkey {
+    /// An ed25519 public key, verified with `verify_strict`.
+    Ed25519([u8; 32]),
+    /// A compressed SEC1 secp256k1 public key, verified as an ECDSA signature
+    /// over the SHA-256 hash of the message.
+    Secp256k1([u8; 33]),
+}
+
+impl Pubkey {
+    fn scheme(&self) -> u8 {
+        match self {
+            Pubkey::Ed25519(_) => 0,
+            Pubkey::Secp256k1(_) => 1,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Pubkey::Ed25519(bytes) => bytes,
+            Pubkey::Secp256k1(bytes) => bytes,
+        }
+    }
+
+    /// Derives the address of the key's account: the SHA-256 hash of the
+    /// scheme tag followed by the key bytes, so the same bytes can never
+    /// resolve to the same account under two schemes.
+    pub fn address(&self) -> Address {
+        let mut hasher = Sha256::new();
+        hasher.update([self.scheme()]);
+        hasher.update(self.as_bytes());
+        let hash: [u8; 32] = hasher.finalize().into();
+
+        hash.into()
+    }
+
+    /// Checks that `signature` is a valid signature of `msg` by this key.
+    pub fn verify(&self, msg: &[u8], signature: &[u8; 64]) -> Result<()> {
+        match self {
+            Pubkey::Ed25519(bytes) => {
+                let pubkey = PublicKey::from_bytes(bytes)?;
+                let signature = Signature::from_bytes(signature)?;
+                pubkey.verify_strict(msg, &signature)?;
+            }
+            Pubkey::Secp256k1(bytes) => {
+                let pubkey = secp256k1::VerifyingKey::from_sec1_bytes(bytes)
+                    .map_err(|_| Error::Signer("Invalid secp256k1 public key".into()))?;
+                let signature = secp256k1::Signature::try_from(&signature[..])
+                    .map_err(|_| Error::Signer("Invalid secp256k1 signature".into()))?;
+                pubkey
+                    .verify(msg, &signature)
+                    .map_err(|_| Error::Signer("Invalid secp256k1 signature".into()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Encode for Pubkey {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        Ok(1 + self.as_bytes().len())
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        dest.write_all(&[self.scheme()])?;
+        dest.write_all(self.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl Decode for Pubkey {
+    fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
+        let scheme = u8::decode(&mut reader)?;
+        match scheme {
+            0 => {
+                let mut bytes = [0; 32];
+                reader.read_exact(&mut bytes)?;
+                Ok(Pubkey::Ed25519(bytes))
+            }
+            1 => {
+                let mut bytes = [0; 33];
+                reader.read_exact(&mut bytes)?;
+                Ok(Pubkey::Secp256k1(bytes))
+            }
+            byte => Err(ed::Error::UnexpectedByte(byte)),
+        }
+    }
+}
+
+impl ed::Terminated for Pubkey {}
+
 #[derive(Encode, Decode)]
 pub struct SignerCall {
     pub signature: Option<[u8; 64]>,
-    pub pubkey: Option<[u8; 32]>,
+    pub pubkey: Option<Pubkey>,
+    pub multisig: Option<MultisigSignatures>,
+    pub expiry: Expiry,
     pub call_bytes: Vec<u8>,
 }
 
 impl SignerCall {
-    fn verify(&self) -> Result<Option<Address>> {
-        match (self.pubkey, self.signature) {
-            (Some(pubkey_bytes), Some(signature)) => {
-                let pubkey = PublicKey::from_bytes(&pubkey_bytes)?;
-                let signature = Signature::from_bytes(&signature)?;
-                pubkey.verify_strict(&self.call_bytes, &signature)?;
-
-                Ok(Some(pubkey_bytes.into()))
+    /// Checks the call's signature over `msg`, which should be the message
+    /// built by the chain's [`SigningDomain`] for the call.
+    pub(crate) fn verify(&self, msg: &[u8]) -> Result<Option<Address>> {
+        match (self.pubkey, self.signature, &self.multisig) {
+            (Some(pubkey), Some(signature), None) => {
+                pubkey.verify(msg, &signature)?;
+
+                Ok(Some(pubkey.address()))
             }
-            (None, None) => Ok(None),
+            (None, None, Some(multisig)) => Ok(Some(multisig.verify(msg)?)),
+            (None, None, None) => Ok(None),
             _ => Err(Error::Signer("Malformed transaction".into())),
         }
     }
 }
 
+/// Limits on how long a signed call can wait to be included in a block. A
+/// call is rejected once the chain passes either limit, including when it is
+/// rechecked in the mempool, so it can not land long after it was signed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Expiry {
+    /// The last block height the call can be included in.
+    pub valid_until_height: Option<u64>,
+    /// The last block time the call can be included at, in seconds since the
+    /// Unix epoch.
+    pub valid_until_time: Option<i64>,
+}
+
+impl Expiry {
+    /// Returns an expiry which lets a call be included for the given duration
+    /// from now, as measured by the local clock.
+    pub fn from_now(duration: Duration) -> Result<Self> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::Signer("System time is before the Unix epoch".into()))?;
+        let valid_until = (now + duration)
+            .as_secs()
+            .try_into()
+            .map_err(|_| Error::Signer("Expiry time is too far in the future".into()))?;
+
+        Ok(Expiry {
+            valid_until_height: None,
+            valid_until_time: Some(valid_until),
+        })
+    }
+
+    fn check(&self, height: u64, time: Option<&Time>) -> Result<()> {
+        if let Some(max_height) = self.valid_until_height {
+            if height > max_height {
+                return Err(Error::Signer(format!(
+                    "Call expired at height {}",
+                    max_height
+                )));
+            }
+        }
+
+        if let Some(max_time) = self.valid_until_time {
+            let time = time.ok_or_else(|| Error::Signer("No Time context available".into()))?;
+            if time.seconds > max_time {
+                return Err(Error::Signer(format!("Call expired at time {}", max_time)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies the chain and application a signature is made for, so a call
+/// signed for one chain can not be replayed on another.
+///
+/// The chain id is taken from the genesis [`InitChainCtx`] and the app domain
+/// from the app's [`AppDomain`] impl, and both are fixed when the chain is
+/// initialized.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SigningDomain {
+    chain_id: String,
+    app_domain: String,
+}
+
+impl SigningDomain {
+    pub fn new(chain_id: String, app_domain: String) -> Self {
+        SigningDomain {
+            chain_id,
+            app_domain,
+        }
+    }
+
+    pub fn chain_id(&self) -> &str {
+        self.chain_id.as_str()
+    }
+
+    pub fn app_domain(&self) -> &str {
+        self.app_domain.as_str()
+    }
+
+    /// Returns the bytes which are signed for a call: the encoded domain and
+    /// expiry, followed by the call bytes.
+    pub fn message(&self, expiry: &Expiry, call_bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut msg = self.encode()?;
+        msg.extend_from_slice(expiry.encode()?.as_slice());
+        msg.extend_from_slice(call_bytes);
+
+        Ok(msg)
+    }
+}
+
+fn encode_str<W: std::io::Write>(value: &str, dest: &mut W) -> ed::Result<()> {
+    let len: u8 = value.len().try_into().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Signing domain strings must be at most 255 bytes",
+        )
+    })?;
+    dest.write_all(&[len])?;
+    dest.write_all(value.as_bytes())?;
+
+    Ok(())
+}
+
+fn decode_str<R: std::io::Read>(mut reader: R) -> ed::Result<String> {
+    let len = u8::decode(&mut reader)?;
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes)?;
+
+    String::from_utf8(bytes).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Signing domain strings must be UTF-8",
+        )
+        .into()
+    })
+}
+
+impl Encode for SigningDomain {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        Ok(2 + self.chain_id.len() + self.app_domain.len())
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        encode_str(self.chain_id.as_str(), dest)?;
+        encode_str(self.app_domain.as_str(), dest)
+    }
+}
+
+impl Decode for SigningDomain {
+    fn decode<R: std::io::Read>(mut reader: R) -> ed::Result<Self> {
+        Ok(SigningDomain {
+            chain_id: decode_str(&mut reader)?,
+            app_domain: decode_str(&mut reader)?,
+        })
+    }
+}
+
+impl ed::Terminated for SigningDomain {}
+
+/// An application-level string which is bound into every signature, so
+/// different apps running on chains with the same id do not accept each
+/// other's calls. Apps can implement this to override the empty default.
+pub trait AppDomain {
+    fn app_domain() -> &'static str;
+}
+
+impl<T> AppDomain for T {
+    default fn app_domain() -> &'static str {
+        ""
+    }
+}
+
 impl<T: Call> Call for SignerPlugin<T> {
     type Call = SignerCall;
     fn call(&mut self, call: Self::Call) -> Result<()> {
         Context::remove::<Signer>();
+        let time = Context::resolve::<Time>();
+        // Calls checked for the mempool can only be included in the next block.
+        let height = if Context::resolve::<CheckTxCtx>().is_some() {
+            self.height + 1
+        } else {
+            self.height
+        };
+        call.expiry.check(height, time.as_deref())?;
+
+        let msg = self
+            .domain
+            .message(&call.expiry, call.call_bytes.as_slice())?;
         let signer_ctx = Signer {
-            signer: call.verify()?,
+            signer: call.verify(msg.as_slice())?,
         };
         Context::add(signer_ctx);
         let inner_call = Decode::decode(call.call_bytes.as_slice())?;
@@ -73,10 +356,65 @@ impl<T: Query> Query for SignerPlugin<T> {
     }
 }
 
+/// Implemented by the clients which send signed calls to a chain, so
+/// [`SignerClient`] can fetch the id of the chain to sign for on first use.
+#[async_trait::async_trait]
+pub trait ChainIdSource {
+    async fn chain_id(&mut self) -> Result<String>;
+}
+
+/// Returns the domain stored in `domain`, first filling in its chain id from
+/// `source` if it is empty.
+async fn resolve_domain<U: ChainIdSource>(
+    domain: &Mutex<SigningDomain>,
+    source: &mut U,
+) -> Result<SigningDomain> {
+    let current = domain.lock().unwrap().clone();
+    if !current.chain_id().is_empty() {
+        return Ok(current);
+    }
+
+    let chain_id = source.chain_id().await?;
+    let resolved = SigningDomain::new(chain_id, current.app_domain().to_string());
+    *domain.lock().unwrap() = resolved.clone();
+
+    Ok(resolved)
+}
+
 pub struct SignerClient<T, U: Clone> {
     parent: U,
     marker: std::marker::PhantomData<T>,
-    keypair: Keypair,
+    keystore: Arc<dyn Keystore>,
+    domain: Arc<Mutex<SigningDomain>>,
+    expiry: Expiry,
+    nonce_dir: Option<PathBuf>,
+}
+
+impl<T, U: Clone> SignerClient<T, U> {
+    /// Creates a client which signs calls for `domain`. If the domain's chain
+    /// id is empty, it is fetched from `parent` before the first call.
+    pub fn new(
+        parent: U,
+        keystore: Arc<dyn Keystore>,
+        domain: SigningDomain,
+        expiry: Expiry,
+    ) -> Self {
+        SignerClient {
+            parent,
+            marker: std::marker::PhantomData,
+            keystore,
+            domain: Arc::new(Mutex::new(domain)),
+            expiry,
+            nonce_dir: None,
+        }
+    }
+
+    /// Keeps the nonces of calls signed by this client in `dir` instead of
+    /// under `~/.orga/nonces`.
+    pub fn with_nonce_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.nonce_dir = Some(dir.into());
+        self
+    }
 }
 
 impl<T, U: Clone> Clone for SignerClient<T, U> {
@@ -84,7 +422,10 @@ impl<T, U: Clone> Clone for SignerClient<T, U> {
         SignerClient {
             parent: self.parent.clone(),
             marker: std::marker::PhantomData,
-            keypair: Keypair::from_bytes(&self.keypair.to_bytes()).unwrap(),
+            keystore: self.keystore.clone(),
+            domain: self.domain.clone(),
+            expiry: self.expiry,
+            nonce_dir: self.nonce_dir.clone(),
         }
     }
 }
@@ -92,7 +433,31 @@ impl<T, U: Clone> Clone for SignerClient<T, U> {
 unsafe impl<T, U: Clone + Send> Send for SignerClient<T, U> {}
 
 #[async_trait::async_trait]
-impl<T: Call, U: AsyncCall<Call = SignerCall> + Clone> AsyncCall for SignerClient<T, U>
+impl<T, U: ChainIdSource + NonceSource + Clone + Send> NonceAccount for SignerClient<T, U> {
+    async fn chain_id(&mut self) -> Result<String> {
+        let domain = resolve_domain(&self.domain, &mut self.parent).await?;
+
+        Ok(domain.chain_id().to_string())
+    }
+
+    fn address(&self) -> Result<Address> {
+        self.keystore.address()
+    }
+
+    fn nonce_dir(&self) -> Option<PathBuf> {
+        self.nonce_dir.clone()
+    }
+
+    async fn chain_nonce(&mut self) -> Result<Option<u64>> {
+        let address = self.keystore.address()?;
+
+        Ok(Some(self.parent.nonce(address).await?))
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Call, U: AsyncCall<Call = SignerCall> + ChainIdSource + Clone> AsyncCall
+    for SignerClient<T, U>
 where
     T::Call: Send,
     U: Send,
@@ -101,53 +466,231 @@ where
 
     async fn call(&mut self, call: Self::Call) -> Result<()> {
         let call_bytes = Encode::encode(&call)?;
-        let signature = self.keypair.sign(call_bytes.as_slice()).to_bytes();
-        let pubkey = self.keypair.public.to_bytes();
+        let domain = resolve_domain(&self.domain, &mut self.parent).await?;
+        let msg = domain.message(&self.expiry, call_bytes.as_slice())?;
+        let signature = self.keystore.sign(msg.as_slice())?;
+        let pubkey = self.keystore.pubkey()?;
 
         self.parent
             .call(SignerCall {
                 call_bytes,
                 pubkey: Some(pubkey),
                 signature: Some(signature),
+                multisig: None,
+                expiry: self.expiry,
             })
             .await
     }
 }
 
-impl<T: Client<SignerClient<T, U>>, U: Clone> Client<U> for SignerPlugin<T> {
-    type Client = T::Client;
+pub struct SignerPluginClient<T: Client<SignerClient<T, U>>, U: Clone> {
+    inner: T::Client,
+    parent: U,
+    keystore: Arc<dyn Keystore>,
+    domain: Arc<Mutex<SigningDomain>>,
+    expiry: Expiry,
+    nonce_dir: Option<PathBuf>,
+}
 
-    fn create_client(parent: U) -> Self::Client {
+impl<T: Client<SignerClient<T, U>>, U: Clone> SignerPluginClient<T, U> {
+    /// Returns a client which signs its calls with the given keystore instead
+    /// of the default key.
+    pub fn with_keystore(&self, keystore: Arc<dyn Keystore>) -> T::Client {
         T::create_client(SignerClient {
-            parent,
+            parent: self.parent.clone(),
             marker: std::marker::PhantomData,
-            keypair: load_keypair().expect("Failed to load keypair"),
+            keystore,
+            domain: self.domain.clone(),
+            expiry: self.expiry,
+            nonce_dir: self.nonce_dir.clone(),
         })
     }
+
+    /// Sets the id of the chain calls are signed for, instead of fetching it
+    /// from the node before the first call. This must match the chain id the
+    /// network was started with, or its nodes will reject every signed call.
+    pub fn set_chain_id(&mut self, chain_id: String) {
+        *self.domain.lock().unwrap() = SigningDomain::new(chain_id, T::app_domain().to_string());
+    }
+
+    /// Sets the expiry included in calls signed from now on.
+    pub fn set_expiry(&mut self, expiry: Expiry) {
+        self.expiry = expiry;
+        self.inner = self.with_keystore(self.keystore.clone());
+    }
+
+    /// Keeps the nonces of calls signed from now on in `dir` instead of under
+    /// `~/.orga/nonces`.
+    pub fn set_nonce_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.nonce_dir = Some(dir.into());
+        self.inner = self.with_keystore(self.keystore.clone());
+    }
+
+    /// Signs calls made from now on with `keystore` instead of the
+    /// [`DefaultKeystore`].
+    pub fn set_keystore(&mut self, keystore: Arc<dyn Keystore>) {
+        self.keystore = keystore;
+        self.inner = self.with_keystore(self.keystore.clone());
+    }
+
+    pub fn keystore(&self) -> &Arc<dyn Keystore> {
+        &self.keystore
+    }
+
+    /// The directory nonces are kept in, if set with
+    /// [`SignerPluginClient::set_nonce_dir`].
+    pub fn nonce_dir(&self) -> Option<&Path> {
+        self.nonce_dir.as_deref()
+    }
+}
+
+impl<T: Client<SignerClient<T, U>>, U: ChainIdSource + Clone> SignerPluginClient<T, U> {
+    /// Returns the domain calls are signed for, fetching the chain id from the
+    /// node if it is not known yet.
+    pub async fn domain(&self) -> Result<SigningDomain> {
+        resolve_domain(&self.domain, &mut self.parent.clone()).await
+    }
+}
+
+impl<T, U> SignerPluginClient<T, U>
+where
+    T: Client<SignerClient<T, U>> + Client<ProposalAdapter<T>> + Call,
+    U: AsyncCall<Call = SignerCall> + ChainIdSource + Clone,
+{
+    /// Creates an unsigned proposal for `multisig` containing the call made on
+    /// the client passed to `build`. The proposal can be signed by the key
+    /// holders offline and later sent with `broadcast_multisig`.
+    pub async fn propose_multisig<F, X>(
+        &self,
+        multisig: Multisig,
+        build: F,
+    ) -> Result<MultisigProposal>
+    where
+        F: FnOnce(<T as Client<ProposalAdapter<T>>>::Client) -> X,
+        X: Future<Output = Result<()>>,
+    {
+        let domain = self.domain().await?;
+        let adapter = ProposalAdapter::new(domain.chain_id().to_string(), multisig.address()?);
+        build(T::create_client(adapter.clone())).await?;
+
+        Ok(MultisigProposal::new(
+            multisig,
+            domain,
+            self.expiry,
+            adapter.take()?,
+        ))
+    }
+
+    /// Creates an unsigned transaction for `pubkey` containing the call made
+    /// on the client passed to `build`, so it can be signed on a machine
+    /// which holds the key but is not connected to the network.
+    pub async fn build_unsigned<F, X>(&self, pubkey: Pubkey, build: F) -> Result<UnsignedTx>
+    where
+        F: FnOnce(<T as Client<ProposalAdapter<T>>>::Client) -> X,
+        X: Future<Output = Result<()>>,
+    {
+        let domain = self.domain().await?;
+        let adapter = ProposalAdapter::new(domain.chain_id().to_string(), pubkey.address());
+        build(T::create_client(adapter.clone())).await?;
+
+        Ok(UnsignedTx::new(
+            domain,
+            self.expiry,
+            pubkey,
+            adapter.take()?,
+        ))
+    }
+
+    /// Sends a proposal once it has gathered enough signatures.
+    pub async fn broadcast_multisig(&mut self, proposal: MultisigProposal) -> Result<()> {
+        self.parent.call(proposal.into_call()?).await
+    }
+}
+
+impl<T: Client<SignerClient<T, U>>, U: Clone> Clone for SignerPluginClient<T, U>
+where
+    T::Client: Clone,
+{
+    fn clone(&self) -> Self {
+        SignerPluginClient {
+            inner: self.inner.clone(),
+            parent: self.parent.clone(),
+            keystore: self.keystore.clone(),
+            domain: self.domain.clone(),
+            expiry: self.expiry,
+            nonce_dir: self.nonce_dir.clone(),
+        }
+    }
+}
+
+impl<T: Client<SignerClient<T, U>>, U: Clone> Deref for SignerPluginClient<T, U> {
+    type Target = T::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: Client<SignerClient<T, U>>, U: Clone> DerefMut for SignerPluginClient<T, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl<T: Client<SignerClient<T, U>>, U: Clone + Send> Send for SignerPluginClient<T, U> {}
+
+impl<T: Client<SignerClient<T, U>>, U: Clone> Client<U> for SignerPlugin<T> {
+    type Client = SignerPluginClient<T, U>;
+
+    fn create_client(parent: U) -> Self::Client {
+        // Errors loading the default keystore are returned by the first call
+        // which signs, unless another keystore is set with `set_keystore`.
+        let keystore: Arc<dyn Keystore> = Arc::new(DefaultKeystore::new());
+        // The chain id is left empty, so it is fetched from the node on first
+        // use unless it is set with `set_chain_id`.
+        let domain = SigningDomain::new(String::new(), T::app_domain().to_string());
+        let client = SignerClient::new(parent.clone(), keystore.clone(), domain, Expiry::default());
+
+        SignerPluginClient {
+            domain: client.domain.clone(),
+            inner: T::create_client(client),
+            parent,
+            keystore,
+            expiry: Expiry::default(),
+            nonce_dir: None,
+        }
+    }
 }
 
 impl<T> State for SignerPlugin<T>
 where
     T: State,
 {
-    type Encoding = (T::Encoding,);
+    type Encoding = (SigningDomain, u64, T::Encoding);
     fn create(store: Store, data: Self::Encoding) -> Result<Self> {
         Ok(Self {
-            inner: T::create(store, data.0)?,
+            domain: data.0,
+            height: data.1,
+            inner: T::create(store, data.2)?,
         })
     }
 
     fn flush(self) -> Result<Self::Encoding> {
-        Ok((self.inner.flush()?,))
+        Ok((self.domain, self.height, self.inner.flush()?))
+    }
+
+    fn prune(store: Store, data: &Self::Encoding) -> Result<()> {
+        T::prune(store, &data.2)
     }
 }
 
-impl<T> From<SignerPlugin<T>> for (T::Encoding,)
+impl<T> From<SignerPlugin<T>> for (SigningDomain, u64, T::Encoding)
 where
     T: State,
 {
     fn from(provider: SignerPlugin<T>) -> Self {
-        (provider.inner.into(),)
+        (provider.domain, provider.height, provider.inner.into())
     }
 }
 
@@ -182,6 +725,7 @@ where
     T: BeginBlock + State,
 {
     fn begin_block(&mut self, ctx: &BeginBlockCtx) -> Result<()> {
+        self.height = ctx.height;
         self.inner.begin_block(ctx)
     }
 }
@@ -200,6 +744,7 @@ where
     T: InitChain + State,
 {
     fn init_chain(&mut self, ctx: &InitChainCtx) -> Result<()> {
+        self.domain = SigningDomain::new(ctx.chain_id.clone(), T::app_domain().to_string());
         self.inner.init_chain(ctx)
     }
 }
@@ -276,3 +821,115 @@ where
 //     assert_eq!(state.borrow().inner.last_signer, Some(pub_key));
 // }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::super::Secp256k1Keystore;
+    use super::*;
+    use crate::store::{MapStore, Shared};
+
+    #[test]
+    fn domain_separation() {
+        let keystore = Secp256k1Keystore::from_secret(&[1; 32]).unwrap();
+        let domain = SigningDomain::new("mainnet".into(), "my-app".into());
+        let bytes = domain.encode().unwrap();
+        assert_eq!(SigningDomain::decode(bytes.as_slice()).unwrap(), domain);
+
+        let call_bytes = vec![1, 2, 3];
+        let expiry = Expiry::default();
+        let msg = domain.message(&expiry, call_bytes.as_slice()).unwrap();
+        let call = SignerCall {
+            signature: Some(keystore.sign(msg.as_slice()).unwrap()),
+            pubkey: Some(keystore.pubkey().unwrap()),
+            multisig: None,
+            expiry,
+            call_bytes: call_bytes.clone(),
+        };
+        assert_eq!(
+            call.verify(msg.as_slice()).unwrap(),
+            Some(keystore.address().unwrap())
+        );
+
+        // The same signature is rejected on another chain or by another app.
+        let testnet = SigningDomain::new("testnet".into(), "my-app".into());
+        let msg = testnet.message(&expiry, call_bytes.as_slice()).unwrap();
+        assert!(call.verify(msg.as_slice()).is_err());
+
+        let other_app = SigningDomain::new("mainnet".into(), "other-app".into());
+        let msg = other_app.message(&expiry, call_bytes.as_slice()).unwrap();
+        assert!(call.verify(msg.as_slice()).is_err());
+
+        // Length prefixes keep domains from running into the call bytes.
+        let shifted = SigningDomain::new("mainnet".into(), "my-app\u{1}".into());
+        let msg = shifted.message(&expiry, &[2, 3]).unwrap();
+        assert!(call.verify(msg.as_slice()).is_err());
+
+        let too_long = SigningDomain::new("a".repeat(256), String::new());
+        assert!(too_long.message(&expiry, call_bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn expiry() {
+        let keystore = Secp256k1Keystore::from_secret(&[1; 32]).unwrap();
+        let domain = SigningDomain::new("mainnet".into(), String::new());
+        let expiry = Expiry {
+            valid_until_height: Some(10),
+            valid_until_time: Some(1000),
+        };
+        assert!(expiry.check(10, Some(&Time::from_seconds(1000))).is_ok());
+        assert!(expiry.check(11, Some(&Time::from_seconds(1000))).is_err());
+        assert!(expiry.check(10, Some(&Time::from_seconds(1001))).is_err());
+        assert!(expiry.check(10, None).is_err());
+        assert!(Expiry::default().check(u64::MAX, None).is_ok());
+
+        // The expiry is covered by the signature, so it can not be extended.
+        let msg = domain.message(&expiry, &[1, 2, 3]).unwrap();
+        let mut call = SignerCall {
+            signature: Some(keystore.sign(msg.as_slice()).unwrap()),
+            pubkey: Some(keystore.pubkey().unwrap()),
+            multisig: None,
+            expiry,
+            call_bytes: vec![1, 2, 3],
+        };
+        assert!(call.verify(msg.as_slice()).is_ok());
+
+        call.expiry.valid_until_height = Some(20);
+        let msg = domain.message(&call.expiry, &[1, 2, 3]).unwrap();
+        assert!(call.verify(msg.as_slice()).is_err());
+    }
+
+    #[test]
+    fn check_tx_expiry() {
+        let keystore = Secp256k1Keystore::from_secret(&[1; 32]).unwrap();
+        let domain = SigningDomain::new("mainnet".into(), String::new());
+        let store = Store::new(Shared::new(MapStore::new()).into());
+        let mut state = SignerPlugin::<u64>::create(store, (domain.clone(), 10, 0)).unwrap();
+        let call = |valid_until_height| {
+            let expiry = Expiry {
+                valid_until_height: Some(valid_until_height),
+                valid_until_time: None,
+            };
+            let msg = domain.message(&expiry, &[]).unwrap();
+            SignerCall {
+                signature: Some(keystore.sign(msg.as_slice()).unwrap()),
+                pubkey: Some(keystore.pubkey().unwrap()),
+                multisig: None,
+                expiry,
+                call_bytes: vec![],
+            }
+        };
+
+        // The last block was at height 10, so a call checked now can only be
+        // included at height 11.
+        Context::add(CheckTxCtx);
+        let check_res = (state.call(call(10)), state.call(call(11)));
+        Context::remove::<CheckTxCtx>();
+        assert!(check_res.0.is_err());
+        assert!(check_res.1.is_ok());
+
+        // Delivered calls are checked against the height of their block.
+        assert!(state.call(call(10)).is_ok());
+        assert!(state.call(call(9)).is_err());
+        Context::remove::<Signer>();
+    }
+}
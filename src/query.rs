@@ -1,4 +1,5 @@
-use crate::encoding::{Decode, Encode};
+use crate::context::Context;
+use crate::encoding::{decode_list, encode_list, list_encoding_length, Decode, Encode};
 use crate::{Error, Result};
 use std::error::Error as StdError;
 use std::result::Result as StdResult;
@@ -11,6 +12,113 @@ pub trait Query {
     fn query(&self, query: Self::Query) -> Result<()>;
 }
 
+/// Dispatches queries to the `#[query]` methods of a type. Implemented by
+/// `#[orga::methods]`, and reached through the `Method` variant of the query
+/// enum generated by `#[derive(Query)]`.
+pub trait MethodQuery {
+    type MethodQuery: Encode + Decode;
+
+    fn method_query(&self, query: Self::MethodQuery) -> Result<()>;
+}
+
+/// Holds the encoded result of the `#[query]` method reached by a query, when
+/// added to the context while the query runs. Nodes use it to answer value
+/// queries with the result itself instead of a proof.
+#[derive(Default)]
+pub struct QueryValue {
+    value: Option<Vec<u8>>,
+    error: Option<String>,
+}
+
+impl QueryValue {
+    /// Takes the recorded result, returning the error if the method failed.
+    pub fn take(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.error.take() {
+            Some(err) => Err(Error::Query(err)),
+            None => Ok(self.value.take()),
+        }
+    }
+}
+
+/// Records the result of a `#[query]` method in the [`QueryValue`] context,
+/// if there is one. Results wrapped in `Result`s are unwrapped, recording
+/// their error if they failed, and results which can not be encoded are
+/// skipped.
+#[doc(hidden)]
+pub fn record_value<T>(value: &T) {
+    if Context::resolve::<QueryValue>().is_some() {
+        value.record_result();
+    }
+}
+
+trait RecordResult {
+    fn record_result(&self);
+}
+
+impl<T> RecordResult for T {
+    default fn record_result(&self) {
+        self.record_encoded();
+    }
+}
+
+impl<T, E> RecordResult for StdResult<T, E> {
+    default fn record_result(&self) {
+        if let Ok(inner) = self {
+            inner.record_result();
+        }
+    }
+}
+
+impl<T, E: std::fmt::Display> RecordResult for StdResult<T, E> {
+    fn record_result(&self) {
+        match self {
+            Ok(inner) => inner.record_result(),
+            Err(err) => {
+                if let Some(ctx) = Context::resolve::<QueryValue>() {
+                    ctx.error = Some(err.to_string());
+                }
+            }
+        }
+    }
+}
+
+trait RecordEncoded {
+    fn record_encoded(&self);
+}
+
+impl<T> RecordEncoded for T {
+    default fn record_encoded(&self) {}
+}
+
+impl<T: Encode> RecordEncoded for T {
+    fn record_encoded(&self) {
+        if let (Some(ctx), Ok(bytes)) = (Context::resolve::<QueryValue>(), self.encode()) {
+            ctx.value = Some(bytes);
+        }
+    }
+}
+
+pub fn maybe_method_query<T>(value: &T, query_bytes: Vec<u8>) -> Result<()> {
+    MaybeMethodQuery::maybe_method_query(value, query_bytes)
+}
+
+trait MaybeMethodQuery {
+    fn maybe_method_query(&self, query_bytes: Vec<u8>) -> Result<()>;
+}
+
+impl<T> MaybeMethodQuery for T {
+    default fn maybe_method_query(&self, _query_bytes: Vec<u8>) -> Result<()> {
+        Err(Error::Query("Type has no query methods".into()))
+    }
+}
+
+impl<T: MethodQuery> MaybeMethodQuery for T {
+    fn maybe_method_query(&self, query_bytes: Vec<u8>) -> Result<()> {
+        let query = Decode::decode(query_bytes.as_slice())?;
+        self.method_query(query)
+    }
+}
+
 impl<T: Query> Query for &T {
     type Query = T::Query;
 
@@ -186,3 +294,94 @@ impl<T: Query, const N: usize> Query for [T; N] {
         self[index].query(subquery)
     }
 }
+
+/// Several queries sent together, so they can be answered with a single
+/// proof covering every key they touch.
+pub struct QueryBatch<T>(pub Vec<T>);
+
+impl<T> QueryBatch<T> {
+    /// Runs every query in the batch against `target`, stopping at the first
+    /// one which fails.
+    pub fn query<U: Query<Query = T>>(self, target: &U) -> Result<()> {
+        self.0.into_iter().try_for_each(|query| target.query(query))
+    }
+}
+
+impl<T: Encode> Encode for QueryBatch<T> {
+    fn encoding_length(&self) -> ed::Result<usize> {
+        list_encoding_length(&self.0)
+    }
+
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        encode_list(&self.0, dest)
+    }
+}
+
+impl<T: Decode> Decode for QueryBatch<T> {
+    fn decode<R: std::io::Read>(reader: R) -> ed::Result<Self> {
+        Ok(QueryBatch(decode_list(reader)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct Recorder(RefCell<Vec<u32>>);
+
+    impl Query for Recorder {
+        type Query = u32;
+
+        fn query(&self, query: u32) -> Result<()> {
+            if query == 0 {
+                return Err(Error::Query("Zero".into()));
+            }
+            self.0.borrow_mut().push(query);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn query_batch() {
+        let batch = QueryBatch(vec![1u32, 2, 3]);
+        let bytes = batch.encode().unwrap();
+        assert_eq!(bytes.len(), batch.encoding_length().unwrap());
+
+        let recorder = Recorder(RefCell::new(vec![]));
+        QueryBatch::<u32>::decode(bytes.as_slice())
+            .unwrap()
+            .query(&recorder)
+            .unwrap();
+        assert_eq!(*recorder.0.borrow(), vec![1, 2, 3]);
+
+        assert!(QueryBatch(vec![4u32, 0, 5]).query(&recorder).is_err());
+        assert_eq!(*recorder.0.borrow(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn record_query_value() {
+        record_value(&1u32);
+        Context::add(QueryValue::default());
+
+        record_value(&Ok::<u32, Error>(2));
+        let ctx = Context::resolve::<QueryValue>().unwrap();
+        assert_eq!(ctx.take().unwrap(), Some(2u32.encode().unwrap()));
+
+        record_value(&Recorder(RefCell::new(vec![])));
+        let ctx = Context::resolve::<QueryValue>().unwrap();
+        assert_eq!(ctx.take().unwrap(), None);
+
+        record_value(&Ok::<StdResult<u32, Error>, Error>(Err(Error::Query(
+            "Failed".into(),
+        ))));
+        let ctx = Context::resolve::<QueryValue>().unwrap();
+        assert_eq!(
+            ctx.take().unwrap_err().to_string(),
+            Error::Query("Failed".into()).to_string()
+        );
+        assert_eq!(ctx.take().unwrap(), None);
+
+        Context::remove::<QueryValue>();
+    }
+}
@@ -11,6 +11,11 @@ use std::marker::PhantomData;
 ///
 /// These types can be complex types like collections (e.g. maps), or simple
 /// data types (e.g. account structs).
+///
+/// Implementations must not write to their store before `flush` is called,
+/// keeping any changes in memory until then. Containers rely on this to
+/// discard changes by dropping a value and recreating it from an earlier
+/// encoding, e.g. `BatchPlugin` when a call in a batch fails.
 pub trait State<S = DefaultBackingStore>: Sized {
     /// A type which provides the binary encoding of data stored in the type's
     /// root key/value entry. When being written to a store, `State` values will
@@ -51,6 +56,21 @@ pub trait State<S = DefaultBackingStore>: Sized {
     fn flush(self) -> Result<Self::Encoding>
     where
         S: Write;
+
+    /// Called by the external container after `flush`, with the same substore
+    /// which was passed to `create` and the encoding `flush` returned.
+    ///
+    /// Types whose child key/value entries depend on their data (e.g. enums,
+    /// which store each variant's children under a different prefix) can use
+    /// this to delete entries which are no longer reachable. Containers should
+    /// forward the call to their children. The default implementation is a
+    /// no-op.
+    fn prune(_store: Store<S>, _data: &Self::Encoding) -> Result<()>
+    where
+        S: Write,
+    {
+        Ok(())
+    }
 }
 
 macro_rules! state_impl {
@@ -84,6 +104,39 @@ state_impl!(i128);
 state_impl!(bool);
 state_impl!(());
 
+/// A marker which encodes as the single byte `V`, and fails to decode from any
+/// other byte. `#[derive(State)]` with `#[state(version = V)]` places it at the
+/// start of the derived encoding, so data written by another version of the
+/// type is rejected rather than silently misread.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Version<const V: u8>;
+
+impl<const V: u8> Encode for Version<V> {
+    fn encode_into<W: std::io::Write>(&self, dest: &mut W) -> ed::Result<()> {
+        dest.write_all(&[V])?;
+
+        Ok(())
+    }
+
+    fn encoding_length(&self) -> ed::Result<usize> {
+        Ok(1)
+    }
+}
+
+impl<const V: u8> Decode for Version<V> {
+    fn decode<R: std::io::Read>(mut source: R) -> ed::Result<Self> {
+        let mut byte = [0u8; 1];
+        source.read_exact(&mut byte)?;
+        if byte[0] != V {
+            return Err(ed::Error::UnexpectedByte(byte[0]));
+        }
+
+        Ok(Version)
+    }
+}
+
+impl<const V: u8> ed::Terminated for Version<V> {}
+
 #[derive(Encode, Decode)]
 pub struct EncodedArray<T: State<S>, S, const N: usize> {
     inner: [T::Encoding; N],
@@ -145,6 +198,16 @@ where
             inner: result_array,
         })
     }
+
+    fn prune(store: Store<S>, data: &Self::Encoding) -> Result<()>
+    where
+        S: Write,
+    {
+        for value in data.inner.iter() {
+            T::prune(store.clone(), value)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Encode, Decode, Default)]
@@ -164,6 +227,10 @@ where
     fn flush(self) -> Result<Self::Encoding> {
         Ok(EncodingWrapper(self.into_inner().flush()?))
     }
+
+    fn prune(store: Store, data: &Self::Encoding) -> Result<()> {
+        T::prune(store, &data.0)
+    }
 }
 
 impl<T> From<RefCell<T>> for EncodingWrapper<T::Encoding>
@@ -190,6 +257,10 @@ where
     fn flush(self) -> Result<Self::Encoding> {
         Ok(EncodingWrapper(self.into_inner().flush()?))
     }
+
+    fn prune(store: Store, data: &Self::Encoding) -> Result<()> {
+        T::prune(store, &data.0)
+    }
 }
 
 impl<T> From<UnsafeCell<T>> for EncodingWrapper<T::Encoding>
@@ -251,6 +322,13 @@ where
             inner: (self.0.into(),),
         })
     }
+
+    fn prune(store: Store<S>, data: &Self::Encoding) -> Result<()>
+    where
+        S: Write,
+    {
+        A::prune(store, &data.inner.0)
+    }
 }
 
 macro_rules! state_tuple_impl {
@@ -294,6 +372,14 @@ macro_rules! state_tuple_impl {
                 })
             }
 
+            fn prune(store: Store<S>, data: &Self::Encoding) -> Result<()>
+            where
+                S: Write,
+            {
+                $($type::prune(store.clone(), &data.inner.$indices)?;)*
+                $last_type::prune(store, &data.inner.$length)
+            }
+
         }
     }
 }
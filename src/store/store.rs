@@ -60,6 +60,24 @@ impl<S> Store<S> {
     }
 }
 
+impl<S: Write> Store<S> {
+    /// Deletes every entry within this store's keyspace, including the entry
+    /// at the store's own prefix.
+    #[cfg_attr(test, mutate)]
+    pub fn clear(&mut self) -> Result<()> {
+        let keys: Vec<_> = self
+            .range(..)
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_>>()?;
+
+        for key in keys {
+            self.delete(key.as_slice())?;
+        }
+
+        Ok(())
+    }
+}
+
 impl<S: Read> Read for Store<S> {
     #[inline]
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
@@ -147,4 +165,21 @@ mod test {
         assert!(backing.get(&[1, 3, 1]).unwrap().is_none());
         assert_eq!(backing.get(&[1, 3, 2]).unwrap().unwrap(), vec![5, 0]);
     }
+
+    #[test]
+    fn clear() {
+        let mut backing = MapStore::new();
+        backing.put(vec![0, 0], vec![0]).unwrap();
+        backing.put(vec![1], vec![1]).unwrap();
+        backing.put(vec![1, 0], vec![2]).unwrap();
+        backing.put(vec![1, 1, 0], vec![3]).unwrap();
+        backing.put(vec![2, 0], vec![4]).unwrap();
+
+        Store::new(&mut backing).sub(&[1]).clear().unwrap();
+        assert!(backing.get(&[1]).unwrap().is_none());
+        assert!(backing.get(&[1, 0]).unwrap().is_none());
+        assert!(backing.get(&[1, 1, 0]).unwrap().is_none());
+        assert_eq!(backing.get(&[0, 0]).unwrap().unwrap(), vec![0]);
+        assert_eq!(backing.get(&[2, 0]).unwrap().unwrap(), vec![4]);
+    }
 }
@@ -0,0 +1,1088 @@
+//! Randomized model checking for `State` types.
+//!
+//! A [`Model`](trait.Model.html) pairs a state type with a simple in-memory
+//! reference implementation (e.g. a `Map` with a `BTreeMap`). The
+//! [`Runner`](struct.Runner.html) generates random sequences of operations,
+//! applies each one to both sides, and fails as soon as they disagree. Failing
+//! sequences are shrunk and reported along with the seed which generated them,
+//! so they can be replayed deterministically.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::ops::Bound;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::collections::{Deque, Entry, EntryMap, Map, Next};
+use crate::encoding::{Decode, Encode, Terminated};
+use crate::state::State;
+use crate::store::{Read, Store, Write};
+use crate::{Error, Result};
+
+/// A small deterministic pseudo-random number generator (xorshift64*), used to
+/// generate operations from a seed without depending on an external crate.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Creates a generator from the given seed. Any seed value is valid.
+    pub fn new(seed: u64) -> Self {
+        // splitmix64 step so that small or zero seeds still give a good state
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a value in the range `0..n`. Panics if `n` is zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns `true` with a probability of `numerator / denominator`.
+    pub fn ratio(&mut self, numerator: u64, denominator: u64) -> bool {
+        self.below(denominator) < numerator
+    }
+}
+
+/// Types which can be randomly generated for use as keys or values in
+/// model-checked operations.
+pub trait Arbitrary: Sized {
+    fn arbitrary(rng: &mut Rng) -> Self;
+}
+
+/// Integers are drawn from a small range so that generated keys collide often,
+/// which exercises updates and removals of existing entries.
+const INTEGER_RANGE: u64 = 32;
+
+macro_rules! arbitrary_int_impl {
+    ($type:ty) => {
+        impl Arbitrary for $type {
+            fn arbitrary(rng: &mut Rng) -> Self {
+                rng.below(INTEGER_RANGE) as $type
+            }
+        }
+    };
+}
+
+arbitrary_int_impl!(u8);
+arbitrary_int_impl!(u16);
+arbitrary_int_impl!(u32);
+arbitrary_int_impl!(u64);
+arbitrary_int_impl!(u128);
+arbitrary_int_impl!(i8);
+arbitrary_int_impl!(i16);
+arbitrary_int_impl!(i32);
+arbitrary_int_impl!(i64);
+arbitrary_int_impl!(i128);
+
+impl Arbitrary for bool {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        rng.ratio(1, 2)
+    }
+}
+
+impl<A: Arbitrary, B: Arbitrary> Arbitrary for (A, B) {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        (A::arbitrary(rng), B::arbitrary(rng))
+    }
+}
+
+/// A state type under test paired with a reference model.
+///
+/// Implementations apply each operation to both the state and the model, and
+/// return an error if their results differ. Operations must be deterministic:
+/// replaying the same operations on a freshly created instance must produce
+/// the same results.
+pub trait Model {
+    type Op: Clone + Debug;
+
+    /// Generates a random operation. The current state of the model may be
+    /// used to bias generation (e.g. towards existing keys).
+    fn random_op(&self, rng: &mut Rng) -> Self::Op;
+
+    /// Applies the operation to the state and the model, returning an error if
+    /// they disagree.
+    fn apply(&mut self, op: &Self::Op) -> Result<()>;
+}
+
+/// Returns an error describing a mismatch between the state and its model.
+pub fn mismatch<T: Debug>(context: &str, actual: T, expected: T) -> Error {
+    Error::Test(format!(
+        "{}: got {:?}, expected {:?}",
+        context, actual, expected
+    ))
+}
+
+/// Runs randomized operation sequences against a [`Model`](trait.Model.html).
+#[derive(Clone, Debug)]
+pub struct Runner {
+    seed: u64,
+    runs: u64,
+    steps: usize,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Runner {
+            seed: 0,
+            runs: 100,
+            steps: 100,
+        }
+    }
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the seed of the first run. Each run uses the next seed, so a
+    /// failure can be replayed with `.seed(failing_seed).runs(1)`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the number of operation sequences to run.
+    pub fn runs(mut self, runs: u64) -> Self {
+        self.runs = runs;
+        self
+    }
+
+    /// Sets the number of operations in each sequence.
+    pub fn steps(mut self, steps: usize) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Runs the configured sequences, calling `create` for a fresh instance of
+    /// the model for each one.
+    ///
+    /// Errors and panics raised while applying operations are treated as
+    /// failures. The returned error includes the seed and a shrunk sequence of
+    /// operations which still reproduces the failure.
+    pub fn run<M, F>(&self, mut create: F) -> Result<()>
+    where
+        M: Model,
+        F: FnMut() -> Result<M>,
+    {
+        for seed in self.seed..self.seed.saturating_add(self.runs) {
+            let mut rng = Rng::new(seed);
+            let mut model = create()?;
+            let mut ops = Vec::with_capacity(self.steps);
+
+            for _ in 0..self.steps {
+                let op = model.random_op(&mut rng);
+                ops.push(op.clone());
+                if let Err(err) = apply_caught(&mut model, &op) {
+                    let (ops, err) = shrink(&mut create, ops, err)?;
+                    return Err(Error::Test(format!(
+                        "Model check failed with seed {} after {} operations: {}\n{:#?}",
+                        seed,
+                        ops.len(),
+                        err,
+                        ops
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Applies an operation, converting any panic into an error.
+fn apply_caught<M: Model>(model: &mut M, op: &M::Op) -> Result<()> {
+    panic::catch_unwind(AssertUnwindSafe(|| model.apply(op))).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(Error::Test(format!("Panicked: {}", message)))
+    })
+}
+
+/// Replays the given operations on a fresh instance, returning the first error.
+fn replay<M, F>(create: &mut F, ops: &[M::Op]) -> Result<Option<Error>>
+where
+    M: Model,
+    F: FnMut() -> Result<M>,
+{
+    let mut model = create()?;
+    for op in ops {
+        if let Err(err) = apply_caught(&mut model, op) {
+            return Ok(Some(err));
+        }
+    }
+    Ok(None)
+}
+
+/// Removes operations one at a time from a failing sequence, keeping each
+/// removal for which the sequence still fails.
+fn shrink<M, F>(create: &mut F, mut ops: Vec<M::Op>, mut err: Error) -> Result<(Vec<M::Op>, Error)>
+where
+    M: Model,
+    F: FnMut() -> Result<M>,
+{
+    let mut i = ops.len();
+    while i > 0 {
+        i -= 1;
+        let mut candidate = ops.clone();
+        candidate.remove(i);
+        if let Some(candidate_err) = replay(create, &candidate)? {
+            ops = candidate;
+            err = candidate_err;
+        }
+    }
+
+    Ok((ops, err))
+}
+
+/// Generates random bounds for a range query over keys.
+fn random_range<K: Arbitrary + Ord + Clone>(rng: &mut Rng) -> (Bound<K>, Bound<K>) {
+    let (mut a, mut b) = (K::arbitrary(rng), K::arbitrary(rng));
+    if b < a {
+        std::mem::swap(&mut a, &mut b);
+    }
+    let start = if rng.ratio(1, 2) {
+        Bound::Included(a.clone())
+    } else {
+        Bound::Unbounded
+    };
+    // an empty range with both ends excluded is invalid
+    let end = match rng.below(3) {
+        0 => Bound::Unbounded,
+        1 if a != b => Bound::Excluded(b),
+        _ => Bound::Included(b),
+    };
+    (start, end)
+}
+
+/// Operations generated for [`MapModel`](struct.MapModel.html).
+#[derive(Clone, Debug)]
+pub enum MapOp<K, V> {
+    Insert(K, V),
+    Remove(K),
+    Get(K),
+    GetMut(K, V),
+    ContainsKey(K),
+    Iter,
+    Range(Bound<K>, Bound<K>),
+    /// Flushes the map to its store and recreates it from the store.
+    Flush,
+}
+
+/// Checks a [`Map`](../collections/struct.Map.html) against a `BTreeMap`.
+///
+/// Values are simple state types whose encoding is the value itself (e.g.
+/// integers).
+pub struct MapModel<K, V, S> {
+    store: Store<S>,
+    map: Option<Map<K, V, S>>,
+    model: BTreeMap<K, V>,
+    on_flush: Option<Box<dyn FnMut() -> Result<()>>>,
+}
+
+impl<K, V, S> MapModel<K, V, S>
+where
+    K: Encode + Terminated,
+    V: State<S>,
+    S: Read,
+{
+    /// Creates an empty map in the given store. The store should not contain
+    /// any entries.
+    pub fn new(store: Store<S>) -> Result<Self> {
+        Ok(MapModel {
+            map: Some(Map::create(store.clone(), ())?),
+            store,
+            model: BTreeMap::new(),
+            on_flush: None,
+        })
+    }
+
+    /// Sets a callback which is called after each `Flush` operation, e.g. to
+    /// write a `BufStore` to its underlying store or commit a `MerkStore`.
+    pub fn on_flush<F: FnMut() -> Result<()> + 'static>(mut self, on_flush: F) -> Self {
+        self.on_flush = Some(Box::new(on_flush));
+        self
+    }
+
+    fn map(&mut self) -> &mut Map<K, V, S> {
+        self.map.as_mut().unwrap()
+    }
+}
+
+impl<K, V, S> MapModel<K, V, S>
+where
+    K: Encode + Decode + Terminated + Next + Clone + Ord + Debug,
+    V: State<S, Encoding = V> + Clone + PartialEq + Debug,
+    S: Read + Write,
+{
+    fn check_entries(&mut self, start: Bound<K>, end: Bound<K>) -> Result<()> {
+        let expected: Vec<_> = self
+            .model
+            .range((start.clone(), end.clone()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let actual = self
+            .map()
+            .range((start, end))?
+            .map(|entry| entry.map(|(k, v)| ((*k).clone(), (*v).clone())))
+            .collect::<Result<Vec<_>>>()?;
+
+        if actual != expected {
+            return Err(mismatch("Entries", actual, expected));
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V, S> Model for MapModel<K, V, S>
+where
+    K: Encode + Decode + Terminated + Next + Clone + Ord + Debug + Arbitrary,
+    V: State<S, Encoding = V> + Clone + PartialEq + Debug + Arbitrary,
+    S: Read + Write,
+{
+    type Op = MapOp<K, V>;
+
+    fn random_op(&self, rng: &mut Rng) -> Self::Op {
+        match rng.below(16) {
+            0..=3 => MapOp::Insert(K::arbitrary(rng), V::arbitrary(rng)),
+            4..=6 => MapOp::Remove(K::arbitrary(rng)),
+            7..=8 => MapOp::Get(K::arbitrary(rng)),
+            9..=10 => MapOp::GetMut(K::arbitrary(rng), V::arbitrary(rng)),
+            11 => MapOp::ContainsKey(K::arbitrary(rng)),
+            12 => MapOp::Iter,
+            13..=14 => {
+                let (start, end) = random_range(rng);
+                MapOp::Range(start, end)
+            }
+            _ => MapOp::Flush,
+        }
+    }
+
+    fn apply(&mut self, op: &Self::Op) -> Result<()> {
+        match op.clone() {
+            MapOp::Insert(key, value) => {
+                self.map().insert(key.clone(), value.clone())?;
+                self.model.insert(key, value);
+            }
+            MapOp::Remove(key) => {
+                let actual = self.map().remove(key.clone())?.map(|v| (*v).clone());
+                let expected = self.model.remove(&key);
+                if actual != expected {
+                    return Err(mismatch("Removed value", actual, expected));
+                }
+            }
+            MapOp::Get(key) => {
+                let actual = self.map().get(key.clone())?.map(|v| (*v).clone());
+                let expected = self.model.get(&key).cloned();
+                if actual != expected {
+                    return Err(mismatch("Value", actual, expected));
+                }
+            }
+            MapOp::GetMut(key, value) => {
+                let actual = match self.map().get_mut(key.clone())? {
+                    Some(mut child) => {
+                        let prev = (*child).clone();
+                        *child = value.clone();
+                        Some(prev)
+                    }
+                    None => None,
+                };
+                let expected = self.model.get_mut(&key).map(|v| {
+                    let prev = v.clone();
+                    *v = value;
+                    prev
+                });
+                if actual != expected {
+                    return Err(mismatch("Mutable value", actual, expected));
+                }
+            }
+            MapOp::ContainsKey(key) => {
+                let actual = self.map().contains_key(key.clone())?;
+                let expected = self.model.contains_key(&key);
+                if actual != expected {
+                    return Err(mismatch("Contains key", actual, expected));
+                }
+            }
+            MapOp::Iter => self.check_entries(Bound::Unbounded, Bound::Unbounded)?,
+            MapOp::Range(start, end) => self.check_entries(start, end)?,
+            MapOp::Flush => {
+                self.map.take().unwrap().flush()?;
+                if let Some(on_flush) = self.on_flush.as_mut() {
+                    on_flush()?;
+                }
+                self.map = Some(Map::create(self.store.clone(), ())?);
+                self.check_entries(Bound::Unbounded, Bound::Unbounded)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Operations generated for [`DequeModel`](struct.DequeModel.html).
+#[derive(Clone, Debug)]
+pub enum DequeOp<T> {
+    PushBack(T),
+    PushFront(T),
+    PopBack,
+    PopFront,
+    Get(u64),
+    GetMut(u64, T),
+    /// Flushes the deque to its store and recreates it from the store.
+    Flush,
+}
+
+/// Checks a [`Deque`](../collections/struct.Deque.html) against a `VecDeque`.
+pub struct DequeModel<T, S> {
+    store: Store<S>,
+    deque: Option<Deque<T, S>>,
+    model: VecDeque<T>,
+    on_flush: Option<Box<dyn FnMut() -> Result<()>>>,
+}
+
+impl<T: State<S>, S: Read> DequeModel<T, S> {
+    /// Creates an empty deque in the given store. The store should not contain
+    /// any entries.
+    pub fn new(store: Store<S>) -> Result<Self> {
+        Ok(DequeModel {
+            deque: Some(Deque::create(store.clone(), Default::default())?),
+            store,
+            model: VecDeque::new(),
+            on_flush: None,
+        })
+    }
+
+    /// Sets a callback which is called after each `Flush` operation, e.g. to
+    /// write a `BufStore` to its underlying store or commit a `MerkStore`.
+    pub fn on_flush<F: FnMut() -> Result<()> + 'static>(mut self, on_flush: F) -> Self {
+        self.on_flush = Some(Box::new(on_flush));
+        self
+    }
+
+    fn deque(&mut self) -> &mut Deque<T, S> {
+        self.deque.as_mut().unwrap()
+    }
+}
+
+impl<T, S> Model for DequeModel<T, S>
+where
+    T: State<S, Encoding = T> + Clone + PartialEq + Debug + Arbitrary,
+    S: Read + Write,
+{
+    type Op = DequeOp<T>;
+
+    fn random_op(&self, rng: &mut Rng) -> Self::Op {
+        // indexes may go slightly past the end to cover missing elements
+        let index = rng.below(self.model.len() as u64 + 2);
+        match rng.below(12) {
+            0..=2 => DequeOp::PushBack(T::arbitrary(rng)),
+            3..=4 => DequeOp::PushFront(T::arbitrary(rng)),
+            5 => DequeOp::PopBack,
+            6..=7 => DequeOp::PopFront,
+            8..=9 => DequeOp::Get(index),
+            10 => DequeOp::GetMut(index, T::arbitrary(rng)),
+            _ => DequeOp::Flush,
+        }
+    }
+
+    fn apply(&mut self, op: &Self::Op) -> Result<()> {
+        match op.clone() {
+            DequeOp::PushBack(value) => {
+                self.deque().push_back(value.clone())?;
+                self.model.push_back(value);
+            }
+            DequeOp::PushFront(value) => {
+                self.deque().push_front(value.clone())?;
+                self.model.push_front(value);
+            }
+            DequeOp::PopBack => {
+                let actual = self.deque().pop_back()?.map(|v| (*v).clone());
+                let expected = self.model.pop_back();
+                if actual != expected {
+                    return Err(mismatch("Popped back", actual, expected));
+                }
+            }
+            DequeOp::PopFront => {
+                let actual = self.deque().pop_front()?.map(|v| (*v).clone());
+                let expected = self.model.pop_front();
+                if actual != expected {
+                    return Err(mismatch("Popped front", actual, expected));
+                }
+            }
+            DequeOp::Get(index) => {
+                let actual = self.deque().get(index)?.map(|v| (*v).clone());
+                let expected = self.model.get(index as usize).cloned();
+                if actual != expected {
+                    return Err(mismatch("Value", actual, expected));
+                }
+            }
+            DequeOp::GetMut(index, value) => {
+                let actual = match self.deque().get_mut(index)? {
+                    Some(mut child) => {
+                        let prev = (*child).clone();
+                        *child = value.clone();
+                        Some(prev)
+                    }
+                    None => None,
+                };
+                let expected = self.model.get_mut(index as usize).map(|v| {
+                    let prev = v.clone();
+                    *v = value;
+                    prev
+                });
+                if actual != expected {
+                    return Err(mismatch("Mutable value", actual, expected));
+                }
+            }
+            DequeOp::Flush => {
+                let meta = self.deque.take().unwrap().flush()?;
+                if let Some(on_flush) = self.on_flush.as_mut() {
+                    on_flush()?;
+                }
+                self.deque = Some(Deque::create(self.store.clone(), meta)?);
+            }
+        }
+
+        let actual_len = self.deque().len();
+        let expected_len = self.model.len() as u64;
+        if actual_len != expected_len {
+            return Err(mismatch("Length", actual_len, expected_len));
+        }
+
+        Ok(())
+    }
+}
+
+/// Operations generated for [`EntryMapModel`](struct.EntryMapModel.html).
+#[derive(Clone, Debug)]
+pub enum EntryMapOp<T, K> {
+    Insert(T),
+    Delete(T),
+    Contains(T),
+    ContainsEntryKey(T),
+    Iter,
+    Range(Bound<K>, Bound<K>),
+    /// Flushes the map to its store and recreates it from the store.
+    Flush,
+}
+
+/// Checks an [`EntryMap`](../collections/struct.EntryMap.html) against a
+/// `BTreeMap` of the entries' keys and values.
+pub struct EntryMapModel<T: Entry, S> {
+    store: Store<S>,
+    map: Option<EntryMap<T, S>>,
+    model: BTreeMap<T::Key, T::Value>,
+    on_flush: Option<Box<dyn FnMut() -> Result<()>>>,
+}
+
+impl<T, S> EntryMapModel<T, S>
+where
+    T: Entry,
+    T::Key: Encode + Terminated,
+    T::Value: State<S>,
+    S: Read,
+{
+    /// Creates an empty entry map in the given store. The store should not
+    /// contain any entries.
+    pub fn new(store: Store<S>) -> Result<Self> {
+        Ok(EntryMapModel {
+            map: Some(EntryMap::create(store.clone(), ())?),
+            store,
+            model: BTreeMap::new(),
+            on_flush: None,
+        })
+    }
+
+    /// Sets a callback which is called after each `Flush` operation, e.g. to
+    /// write a `BufStore` to its underlying store or commit a `MerkStore`.
+    pub fn on_flush<F: FnMut() -> Result<()> + 'static>(mut self, on_flush: F) -> Self {
+        self.on_flush = Some(Box::new(on_flush));
+        self
+    }
+
+    fn map(&mut self) -> &mut EntryMap<T, S> {
+        self.map.as_mut().unwrap()
+    }
+}
+
+impl<T, S> EntryMapModel<T, S>
+where
+    T: Entry + Clone + PartialEq + Debug,
+    T::Key: Encode + Decode + Terminated + Next + Clone + Ord,
+    T::Value: State<S> + Clone,
+    S: Read,
+{
+    fn check_entries(&mut self, start: Bound<T::Key>, end: Bound<T::Key>) -> Result<()> {
+        let expected: Vec<_> = self
+            .model
+            .range((start.clone(), end.clone()))
+            .map(|(k, v)| T::from_entry((k.clone(), v.clone())))
+            .collect();
+        let actual = self
+            .map()
+            .range((start, end))?
+            .map(|entry| entry.map(|entry| (*entry).clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        if actual != expected {
+            return Err(mismatch("Entries", actual, expected));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, S> Model for EntryMapModel<T, S>
+where
+    T: Entry + Clone + PartialEq + Debug + Arbitrary,
+    T::Key: Encode + Decode + Terminated + Next + Clone + Ord + Debug + Arbitrary,
+    T::Value: State<S> + Clone + Eq,
+    S: Read + Write,
+{
+    type Op = EntryMapOp<T, T::Key>;
+
+    fn random_op(&self, rng: &mut Rng) -> Self::Op {
+        match rng.below(14) {
+            0..=3 => EntryMapOp::Insert(T::arbitrary(rng)),
+            4..=5 => EntryMapOp::Delete(T::arbitrary(rng)),
+            6..=7 => EntryMapOp::Contains(T::arbitrary(rng)),
+            8..=9 => EntryMapOp::ContainsEntryKey(T::arbitrary(rng)),
+            10 => EntryMapOp::Iter,
+            11..=12 => {
+                let (start, end) = random_range(rng);
+                EntryMapOp::Range(start, end)
+            }
+            _ => EntryMapOp::Flush,
+        }
+    }
+
+    fn apply(&mut self, op: &Self::Op) -> Result<()> {
+        match op.clone() {
+            EntryMapOp::Insert(entry) => {
+                self.map().insert(entry.clone())?;
+                let (key, value) = entry.into_entry();
+                self.model.insert(key, value);
+            }
+            EntryMapOp::Delete(entry) => {
+                self.map().delete(entry.clone())?;
+                let (key, _) = entry.into_entry();
+                self.model.remove(&key);
+            }
+            EntryMapOp::Contains(entry) => {
+                let actual = self.map().contains(entry.clone())?;
+                let (key, value) = entry.into_entry();
+                let expected = self.model.get(&key) == Some(&value);
+                if actual != expected {
+                    return Err(mismatch("Contains", actual, expected));
+                }
+            }
+            EntryMapOp::ContainsEntryKey(entry) => {
+                let actual = self.map().contains_entry_key(entry.clone())?;
+                let (key, _) = entry.into_entry();
+                let expected = self.model.contains_key(&key);
+                if actual != expected {
+                    return Err(mismatch("Contains entry key", actual, expected));
+                }
+            }
+            EntryMapOp::Iter => self.check_entries(Bound::Unbounded, Bound::Unbounded)?,
+            EntryMapOp::Range(start, end) => self.check_entries(start, end)?,
+            EntryMapOp::Flush => {
+                self.map.take().unwrap().flush()?;
+                if let Some(on_flush) = self.on_flush.as_mut() {
+                    on_flush()?;
+                }
+                self.map = Some(EntryMap::create(self.store.clone(), ())?);
+                self.check_entries(Bound::Unbounded, Bound::Unbounded)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "abci")]
+pub use pool::{PoolModel, PoolOp};
+
+/// Model checking for [`Pool`](../coins/struct.Pool.html), which is only
+/// available with the `abci` feature like the rest of `coins`.
+#[cfg(feature = "abci")]
+mod pool {
+    use rust_decimal::prelude::ToPrimitive;
+    use rust_decimal::Decimal as NumDecimal;
+
+    use super::*;
+    use crate::coins::{Adjust, Balance, Decimal, Give, Pool, Share, Symbol, Take};
+
+    /// Operations generated for [`PoolModel`](struct.PoolModel.html).
+    #[derive(Clone, Debug)]
+    pub enum PoolOp<K> {
+        /// Adds the amount to a child's balance.
+        Deposit(K, u64),
+        /// Takes the amount from a child's balance, failing if the balance is too
+        /// low.
+        Withdraw(K, u64),
+        /// Adds the amount to the pool, increasing every child's balance
+        /// proportionally.
+        Give(u64),
+        /// Takes the amount from the pool, decreasing every child's balance
+        /// proportionally.
+        Take(u64),
+        /// Multiplies every balance by `numerator / denominator`.
+        Adjust(u64, u64),
+        Get(K),
+        Iter,
+        /// Flushes the pool to its store and recreates it from the store.
+        Flush,
+    }
+
+    /// Checks a [`Pool`](../coins/struct.Pool.html) of `Share`s against a
+    /// `BTreeMap` of balances.
+    ///
+    /// The pool applies proportional changes through multipliers, so balances are
+    /// compared with a small relative tolerance. Withdrawals whose outcome would
+    /// depend on that rounding are skipped, as are pool-wide gives and takes which
+    /// would leave the pool empty or start from an empty pool.
+    pub struct PoolModel<K: Encode + Terminated, S: Symbol> {
+        store: Store,
+        pool: Option<Pool<K, Share<S>, S>>,
+        model: BTreeMap<K, NumDecimal>,
+        on_flush: Option<Box<dyn FnMut() -> Result<()>>>,
+    }
+
+    impl<K: Encode + Terminated, S: Symbol> PoolModel<K, S> {
+        /// Creates an empty pool in the given store. The store should not contain
+        /// any entries.
+        pub fn new(store: Store) -> Result<Self> {
+            Ok(PoolModel {
+                pool: Some(Pool::create(store.clone(), Default::default())?),
+                store,
+                model: BTreeMap::new(),
+                on_flush: None,
+            })
+        }
+
+        /// Sets a callback which is called after each `Flush` operation, e.g. to
+        /// write a `BufStore` to its underlying store or commit a `MerkStore`.
+        pub fn on_flush<F: FnMut() -> Result<()> + 'static>(mut self, on_flush: F) -> Self {
+            self.on_flush = Some(Box::new(on_flush));
+            self
+        }
+
+        fn pool(&mut self) -> &mut Pool<K, Share<S>, S> {
+            self.pool.as_mut().unwrap()
+        }
+
+        fn total(&self) -> NumDecimal {
+            self.model
+                .values()
+                .fold(NumDecimal::default(), |a, b| a + *b)
+        }
+
+        fn scale(&mut self, multiplier: NumDecimal) {
+            for balance in self.model.values_mut() {
+                *balance *= multiplier;
+            }
+        }
+    }
+
+    /// Returns whether two balances are equal within the tolerance used by
+    /// [`PoolModel`](struct.PoolModel.html).
+    fn is_close(actual: NumDecimal, expected: NumDecimal) -> bool {
+        let tolerance = NumDecimal::new(1, 9) * expected.abs().max(1.into());
+        (actual - expected).abs() <= tolerance
+    }
+
+    fn check_close(context: &str, actual: NumDecimal, expected: NumDecimal) -> Result<()> {
+        if !is_close(actual, expected) {
+            return Err(mismatch(context, actual, expected));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the whole units of a balance, or zero if it is negative.
+    fn units(balance: NumDecimal) -> u64 {
+        balance.floor().to_u64().unwrap_or(0)
+    }
+
+    impl<K, S> PoolModel<K, S>
+    where
+        K: Encode + Decode + Terminated + Next + Clone + Ord + Debug,
+        S: Symbol,
+    {
+        fn check_balances(&mut self) -> Result<()> {
+            let pool = self.pool.as_ref().unwrap();
+            check_close("Pool balance", pool.balance()?.0, self.total())?;
+
+            for (key, expected) in self.model.iter() {
+                let balance: Decimal = pool.get(key.clone())?.balance()?;
+                check_close("Balance", balance.0, *expected)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl<K, S> Model for PoolModel<K, S>
+    where
+        K: Encode + Decode + Terminated + Next + Clone + Ord + Debug + Arbitrary,
+        S: Symbol,
+    {
+        type Op = PoolOp<K>;
+
+        fn random_op(&self, rng: &mut Rng) -> Self::Op {
+            let key = K::arbitrary(rng);
+            let balance = self.model.get(&key).copied().unwrap_or_default();
+            match rng.below(16) {
+                0..=4 => PoolOp::Deposit(key, u64::arbitrary(rng)),
+                // amounts may go slightly past the balance to cover failures
+                5..=7 => PoolOp::Withdraw(key, rng.below(units(balance) + 4)),
+                8..=9 => PoolOp::Give(u64::arbitrary(rng)),
+                10 => PoolOp::Take(rng.below(units(self.total()) + 1)),
+                11 => PoolOp::Adjust(rng.below(4) + 1, rng.below(4) + 1),
+                12..=13 => PoolOp::Get(key),
+                14 => PoolOp::Iter,
+                _ => PoolOp::Flush,
+            }
+        }
+
+        fn apply(&mut self, op: &Self::Op) -> Result<()> {
+            match op.clone() {
+                PoolOp::Deposit(key, amount) => {
+                    self.pool().get_mut(key.clone())?.add(amount)?;
+                    *self.model.entry(key).or_default() += NumDecimal::from(amount);
+                }
+                PoolOp::Withdraw(key, amount) => {
+                    let expected = self.model.get(&key).copied().unwrap_or_default();
+                    let amount_decimal = NumDecimal::from(amount);
+                    if amount > 0 && is_close(amount_decimal, expected) {
+                        return Ok(());
+                    }
+
+                    let taken = self.pool().get_mut(key.clone())?.take(amount).is_ok();
+                    let expected_taken = amount_decimal <= expected;
+                    if taken != expected_taken {
+                        return Err(mismatch("Withdrawal succeeded", taken, expected_taken));
+                    }
+                    let balance = self.model.entry(key).or_default();
+                    if taken {
+                        *balance -= amount_decimal;
+                    }
+                }
+                PoolOp::Give(amount) => {
+                    let total = self.total();
+                    if total < 1.into() {
+                        return Ok(());
+                    }
+
+                    self.pool().add(amount)?;
+                    self.scale((total + NumDecimal::from(amount)) / total);
+                }
+                PoolOp::Take(amount) => {
+                    let total = self.total();
+                    if NumDecimal::from(amount) + NumDecimal::from(1) > total {
+                        return Ok(());
+                    }
+
+                    self.pool().take(amount)?.burn();
+                    self.scale((total - NumDecimal::from(amount)) / total);
+                }
+                PoolOp::Adjust(numerator, denominator) => {
+                    let multiplier = NumDecimal::from(numerator) / NumDecimal::from(denominator);
+                    self.pool().adjust(Decimal(multiplier))?;
+                    self.scale(multiplier);
+                }
+                PoolOp::Get(key) => {
+                    let balance: Decimal = self.pool().get(key.clone())?.balance()?;
+                    let expected = self.model.get(&key).copied().unwrap_or_default();
+                    check_close("Balance", balance.0, expected)?;
+                }
+                PoolOp::Iter => {
+                    let actual = self
+                        .pool()
+                        .iter()?
+                        .map(|entry| entry.map(|(key, _)| (*key).clone()))
+                        .collect::<Result<Vec<_>>>()?;
+                    let expected: Vec<_> = self.model.keys().cloned().collect();
+                    if actual != expected {
+                        return Err(mismatch("Keys", actual, expected));
+                    }
+                }
+                PoolOp::Flush => {
+                    let encoding = self.pool.take().unwrap().flush()?;
+                    if let Some(on_flush) = self.on_flush.as_mut() {
+                        on_flush()?;
+                    }
+                    self.pool = Some(Pool::create(self.store.clone(), encoding)?);
+                }
+            }
+
+            self.check_balances()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::store::{MapStore, Shared};
+
+        #[derive(Encode, Decode, Debug, Clone)]
+        struct Simp;
+        impl Symbol for Simp {}
+
+        impl State for Simp {
+            type Encoding = Self;
+
+            fn create(_: Store, data: Self::Encoding) -> Result<Self> {
+                Ok(data)
+            }
+
+            fn flush(self) -> Result<Self::Encoding> {
+                Ok(self)
+            }
+        }
+
+        #[test]
+        fn pool_mapstore() {
+            Runner::new()
+                .run(|| {
+                    PoolModel::<u32, Simp>::new(Store::new(Shared::new(MapStore::new()).into()))
+                })
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{BufStore, MapStore, Shared};
+
+    #[test]
+    fn rng_is_deterministic() {
+        let mut a = Rng::new(123);
+        let mut b = Rng::new(123);
+        let mut c = Rng::new(124);
+        let a: Vec<_> = (0..8).map(|_| a.next_u64()).collect();
+        let b: Vec<_> = (0..8).map(|_| b.next_u64()).collect();
+        let c: Vec<_> = (0..8).map(|_| c.next_u64()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn map_mapstore() {
+        Runner::new()
+            .run(|| MapModel::<u32, u32, _>::new(Store::new(MapStore::new())))
+            .unwrap();
+    }
+
+    #[test]
+    fn map_bufstore() {
+        Runner::new()
+            .run(|| {
+                let buf = Shared::new(BufStore::wrap(MapStore::new()));
+                let mut flush_buf = buf.clone();
+                Ok(MapModel::<u16, u64, _>::new(Store::new(buf))?
+                    .on_flush(move || flush_buf.borrow_mut().flush()))
+            })
+            .unwrap();
+    }
+
+    #[cfg(all(feature = "merk", feature = "abci"))]
+    #[test]
+    fn map_merkstore() {
+        use crate::abci::ABCIStore;
+        use crate::merk::MerkStore;
+        use tempdir::TempDir;
+
+        Runner::new()
+            .runs(10)
+            .run(|| {
+                let home = TempDir::new("orga-model-check").unwrap();
+                let merk = Shared::new(MerkStore::new(home.path().into()));
+                let mut commit_merk = merk.clone();
+                let mut height = 0;
+                let model = MapModel::<u32, u32, _>::new(Store::new(merk))?;
+                Ok(model.on_flush(move || {
+                    // keep the directory alive as long as the store
+                    let _home = &home;
+                    height += 1;
+                    commit_merk.borrow_mut().commit(height)
+                }))
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn deque_mapstore() {
+        Runner::new()
+            .run(|| DequeModel::<u32, _>::new(Store::new(MapStore::new())))
+            .unwrap();
+    }
+
+    #[derive(Entry, Clone, Debug, PartialEq)]
+    struct Pair {
+        #[key]
+        key: u32,
+        value: u32,
+    }
+
+    impl Arbitrary for Pair {
+        fn arbitrary(rng: &mut Rng) -> Self {
+            let (key, value) = Arbitrary::arbitrary(rng);
+            Pair { key, value }
+        }
+    }
+
+    #[test]
+    fn entry_map_mapstore() {
+        Runner::new()
+            .run(|| EntryMapModel::<Pair, _>::new(Store::new(MapStore::new())))
+            .unwrap();
+    }
+
+    struct Broken(MapModel<u32, u32, MapStore>);
+
+    impl Model for Broken {
+        type Op = MapOp<u32, u32>;
+
+        fn random_op(&self, rng: &mut Rng) -> Self::Op {
+            self.0.random_op(rng)
+        }
+
+        fn apply(&mut self, op: &Self::Op) -> Result<()> {
+            // inserts of the value 7 only reach the model
+            if let MapOp::Insert(key, 7) = op {
+                self.0.model.insert(*key, 7);
+                return Ok(());
+            }
+            self.0.apply(op)
+        }
+    }
+
+    #[test]
+    fn reports_shrunk_failure() {
+        let err = Runner::new()
+            .run(|| Ok(Broken(MapModel::new(Store::new(MapStore::new()))?)))
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("Model check failed with seed"));
+        assert!(message.contains("Insert("));
+        // the shrunk sequence is the insert of 7 plus the read which saw it
+        assert!(message.contains("after 2 operations"), "{}", message);
+    }
+}
@@ -1,10 +1,11 @@
 #![feature(trivial_bounds)]
 
 use orga::collections::Entry;
+use orga::collections::Map;
 use orga::collections::Next;
 use orga::encoding::{Decode, Encode};
 use orga::state::State;
-use orga::store::{MapStore, Shared, Store};
+use orga::store::{MapStore, Read, Shared, Store, Write};
 
 #[derive(Encode, Decode, PartialEq, Debug)]
 struct Foo<T>
@@ -33,12 +34,12 @@ struct MyStruct {
 #[derive(State)]
 struct MyStruct2(u32, u32);
 
-// #[derive(State)]
-// enum MyEnum {
-//     Unit,
-//     Tuple(u32, u32),
-//     Named { foo: u32 },
-// }
+#[derive(State)]
+enum MyEnum {
+    Unit,
+    Tuple(u32, u32),
+    Named { foo: Map<u32, u32> },
+}
 
 #[test]
 fn struct_state() {
@@ -59,6 +60,161 @@ fn struct_state() {
     assert_eq!(bytes, vec![0, 0, 0, 123, 0, 0, 0, 5, 0, 0, 0, 6]);
 }
 
+#[test]
+fn enum_state() {
+    let mapstore = Shared::new(MapStore::new());
+    let store = Store::new(mapstore.into());
+
+    let state = MyEnum::create(store.clone(), Default::default()).unwrap();
+    assert!(matches!(state, MyEnum::Unit));
+
+    let mut state = MyEnum::create(store.clone(), my_enum_state::Encoding::Named(())).unwrap();
+    if let MyEnum::Named { foo } = &mut state {
+        foo.insert(1, 2).unwrap();
+    }
+    let data = state.flush().unwrap();
+    MyEnum::prune(store.clone(), &data).unwrap();
+    assert_eq!(data.encode().unwrap(), vec![2]);
+    // variant index 2, field index 0, then the map key
+    assert!(store.get(&[2, 0, 0, 0, 0, 1]).unwrap().is_some());
+
+    let state = MyEnum::create(store.clone(), my_enum_state::Encoding::Tuple(5, 6)).unwrap();
+    let data = state.flush().unwrap();
+    MyEnum::prune(store.clone(), &data).unwrap();
+    assert_eq!(data.encode().unwrap(), vec![1, 0, 0, 0, 5, 0, 0, 0, 6]);
+    assert!(store.get(&[2, 0, 0, 0, 0, 1]).unwrap().is_none());
+}
+
+#[test]
+fn enum_state_prune_on_variant_change() {
+    let mapstore = Shared::new(MapStore::new());
+    let mut store = Store::new(mapstore.into());
+    let map_store = store.clone();
+    let create_map = move || Map::<u32, MyEnum>::create(map_store.clone(), ()).unwrap();
+
+    let mut map = create_map();
+    map.insert(1, my_enum_state::Encoding::Named(())).unwrap();
+    if let MyEnum::Named { foo } = &mut *map.get_mut(1).unwrap().unwrap() {
+        foo.insert(2, 3).unwrap();
+    }
+    map.flush().unwrap();
+    // map key, variant index 2, field index 0, then the inner map key
+    let foo_key = [0, 0, 0, 1, 2, 0, 0, 0, 0, 2];
+    assert!(store.get(&foo_key).unwrap().is_some());
+
+    // inactive variants are not cleared while the variant is unchanged
+    let unreachable_key = [0, 0, 0, 1, 1, 0];
+    store.put(unreachable_key.to_vec(), vec![0]).unwrap();
+    let mut map = create_map();
+    if let MyEnum::Named { foo } = &mut *map.get_mut(1).unwrap().unwrap() {
+        foo.insert(4, 5).unwrap();
+    }
+    map.flush().unwrap();
+    let foo_key_2 = [0, 0, 0, 1, 2, 0, 0, 0, 0, 4];
+    assert!(store.get(&foo_key_2).unwrap().is_some());
+    assert!(store.get(&unreachable_key).unwrap().is_some());
+
+    let mut map = create_map();
+    map.insert(1, my_enum_state::Encoding::Tuple(5, 6)).unwrap();
+    map.flush().unwrap();
+    assert!(store.get(&foo_key).unwrap().is_none());
+    assert!(store.get(&foo_key_2).unwrap().is_none());
+}
+
+#[test]
+fn enum_state_prune_in_shared_store() {
+    let mapstore = Shared::new(MapStore::new());
+    let store = Store::new(mapstore.into());
+    let map_store = store.clone();
+    let create_map = move || Map::<u32, (u8, MyEnum)>::create(map_store.clone(), ()).unwrap();
+    let encoding = |bytes: &[u8]| <(u8, MyEnum) as State>::Encoding::decode(bytes).unwrap();
+
+    // the tuple's first byte is written where the enum's encoding would
+    // otherwise start
+    let mut map = create_map();
+    map.insert(1, encoding(&[0, 2])).unwrap();
+    if let (_, MyEnum::Named { foo }) = &mut *map.get_mut(1).unwrap().unwrap() {
+        foo.insert(2, 3).unwrap();
+    }
+    map.flush().unwrap();
+    let foo_key = [0, 0, 0, 1, 2, 0, 0, 0, 0, 2];
+    assert!(store.get(&foo_key).unwrap().is_some());
+
+    let mut map = create_map();
+    map.insert(1, encoding(&[2, 1, 0, 0, 0, 5, 0, 0, 0, 6]))
+        .unwrap();
+    map.flush().unwrap();
+    assert!(store.get(&foo_key).unwrap().is_none());
+}
+
+#[derive(State)]
+struct WithStatus {
+    count: u32,
+    status: MyEnum,
+}
+
+#[test]
+fn enum_field_state_prune() {
+    let mapstore = Shared::new(MapStore::new());
+    let mut store = Store::new(mapstore.into());
+
+    let mut state =
+        WithStatus::create(store.clone(), (0, my_enum_state::Encoding::Named(()))).unwrap();
+    if let MyEnum::Named { foo } = &mut state.status {
+        foo.insert(2, 3).unwrap();
+    }
+    let data = state.flush().unwrap();
+    WithStatus::prune(store.clone(), &data).unwrap();
+    // field index 1, variant index 2, field index 0, then the map key
+    let foo_key = [1, 2, 0, 0, 0, 0, 2];
+    assert!(store.get(&foo_key).unwrap().is_some());
+
+    // inactive variants are not cleared while the variant is unchanged
+    let unreachable_key = [1, 1, 0];
+    store.put(unreachable_key.to_vec(), vec![0]).unwrap();
+    let state = WithStatus::create(store.clone(), data).unwrap();
+    let data = state.flush().unwrap();
+    WithStatus::prune(store.clone(), &data).unwrap();
+    assert!(store.get(&foo_key).unwrap().is_some());
+    assert!(store.get(&unreachable_key).unwrap().is_some());
+
+    let state = WithStatus::create(store.clone(), (0, my_enum_state::Encoding::Unit)).unwrap();
+    let data = state.flush().unwrap();
+    WithStatus::prune(store.clone(), &data).unwrap();
+    assert!(store.get(&foo_key).unwrap().is_none());
+}
+
+#[derive(State)]
+#[state(version = 1)]
+struct AttrStruct {
+    #[state(prefix = b"acc")]
+    accounts: Map<u32, u32>,
+    #[state(skip)]
+    cache: Vec<u32>,
+    count: u32,
+}
+
+#[test]
+fn struct_state_attrs() {
+    let mapstore = Shared::new(MapStore::new());
+    let store = Store::new(mapstore.into());
+
+    let mut state = AttrStruct::create(store.clone(), Default::default()).unwrap();
+    assert!(state.cache.is_empty());
+
+    state.accounts.insert(1, 2).unwrap();
+    state.cache.push(3);
+    state.count = 4;
+
+    let data = state.flush().unwrap();
+    let bytes = data.encode().unwrap();
+    assert_eq!(bytes, vec![1, 0, 0, 0, 4]);
+    assert!(store.get(b"acc\0\0\0\x01").unwrap().is_some());
+
+    let other_version = vec![2, 0, 0, 0, 4];
+    assert!(<AttrStruct as State>::Encoding::decode(other_version.as_slice()).is_err());
+}
+
 #[derive(State, PartialEq, Debug)]
 struct GenericStruct<T: State>
 where
@@ -33,6 +33,7 @@ where
 #[derive(Query, Call)]
 pub struct TupleStruct(pub u32);
 
+#[orga::methods]
 impl<T> Foo<T> {
     pub fn _no_attr(&self) {}
 
@@ -49,9 +50,23 @@ impl<T> Foo<T> {
         &123
     }
 
-    // TODO:
-    // #[query]
-    // pub fn ref_input(&self, _n: &u32) {}
+    #[query]
+    pub fn ref_input(&self, _n: &u32) {}
+
+    #[query]
+    pub fn slice_input(&self, bytes: &[u8]) -> u32 {
+        bytes.len() as u32
+    }
+
+    #[query(instance(u32), instance(u64))]
+    pub fn generic_method<U: Into<u64>>(&self, n: U) -> u64 {
+        n.into()
+    }
+
+    #[query(instance(u8))]
+    pub fn impl_trait_input(&self, n: impl Into<u32>) -> u32 {
+        n.into()
+    }
 
     #[query]
     pub fn generic_input(&self, _t: T) -> u32 {
@@ -72,9 +87,15 @@ impl<T> Foo<T> {
         self.a as u32 + n
     }
 
-    // TODO:
-    // #[call]
-    // pub fn ref_input_call(&self, _n: &u32) {}
+    #[call]
+    pub fn ref_input_call(&mut self, n: &mut u32) {
+        *n += 1;
+    }
+
+    #[call(instance(u8))]
+    pub fn generic_method_call<U: Into<u32>>(&mut self, n: U) -> u32 {
+        n.into()
+    }
 
     #[call]
     pub fn generic_input_call(&mut self, _t: T) -> u32 {
@@ -92,16 +113,20 @@ impl<T> Foo<T> {
     ) -> orga::Result<Option<orga::collections::ChildMut<u64, u32>>> {
         self.bar.deque.get_mut(123)
     }
-}
 
-impl<T: Clone + Default> Foo<T> {
     #[query]
-    pub fn generic_output(&self) -> T {
+    pub fn generic_output(&self) -> T
+    where
+        T: Clone + Default,
+    {
         self.b.clone().unwrap_or_default()
     }
 
     #[call]
-    pub fn generic_output_call(&mut self) -> Option<&mut T> {
+    pub fn generic_output_call(&mut self) -> Option<&mut T>
+    where
+        T: Clone + Default,
+    {
         self.b.as_mut()
     }
 }
@@ -115,6 +140,13 @@ fn _exhaustive_match_query<T: Query>(query: foo_query::Query<T>) {
         Query::FieldA(subquery) => _assert_type::<()>(subquery),
         Query::FieldB(subquery) => _assert_type::<T::Query>(subquery),
         Query::FieldBar(subquery) => _assert_type::<bar_query::Query>(subquery),
+        Query::Method(subquery) => _assert_type::<Vec<u8>>(subquery),
+    }
+}
+
+fn _exhaustive_match_method_query<T>(query: foo_methods::Query<T>) {
+    use foo_methods::Query;
+    match query {
         Query::MethodBasic(subquery) => _assert_type::<Vec<u8>>(subquery),
         Query::MethodInputAndOutput(n, subquery) => {
             _assert_type::<u32>(n);
@@ -123,6 +155,26 @@ fn _exhaustive_match_query<T: Query>(query: foo_query::Query<T>) {
         Query::MethodRefOutput(subquery) => {
             _assert_type::<Vec<u8>>(subquery);
         }
+        Query::MethodRefInput(n, subquery) => {
+            _assert_type::<u32>(n);
+            _assert_type::<Vec<u8>>(subquery);
+        }
+        Query::MethodSliceInput(bytes, subquery) => {
+            _assert_type::<Vec<u8>>(bytes);
+            _assert_type::<Vec<u8>>(subquery);
+        }
+        Query::MethodGenericMethodU32(n, subquery) => {
+            _assert_type::<u32>(n);
+            _assert_type::<Vec<u8>>(subquery);
+        }
+        Query::MethodGenericMethodU64(n, subquery) => {
+            _assert_type::<u64>(n);
+            _assert_type::<Vec<u8>>(subquery);
+        }
+        Query::MethodImplTraitInput(n, subquery) => {
+            _assert_type::<u8>(n);
+            _assert_type::<Vec<u8>>(subquery);
+        }
         Query::MethodGenericInput(t, subquery) => {
             _assert_type::<T>(t);
             _assert_type::<Vec<u8>>(subquery);
@@ -133,18 +185,33 @@ fn _exhaustive_match_query<T: Query>(query: foo_query::Query<T>) {
     }
 }
 
-fn _exhaustive_match_call<T: Call>(call: foo_call::Call<T>) {
+fn _exhaustive_match_call(call: foo_call::Call) {
     use foo_call::Call;
     match call {
         Call::Noop => {}
         Call::FieldA(subcall) => _assert_type::<Vec<u8>>(subcall),
         Call::FieldB(subcall) => _assert_type::<Vec<u8>>(subcall),
         Call::FieldBar(subcall) => _assert_type::<Vec<u8>>(subcall),
+        Call::Method(subcall) => _assert_type::<Vec<u8>>(subcall),
+    }
+}
+
+fn _exhaustive_match_method_call<T>(call: foo_methods::Call<T>) {
+    use foo_methods::Call;
+    match call {
         Call::MethodBasicCall(subcall) => _assert_type::<Vec<u8>>(subcall),
         Call::MethodInputAndOutputCall(n, subcall) => {
             _assert_type::<u32>(n);
             _assert_type::<Vec<u8>>(subcall);
         }
+        Call::MethodRefInputCall(n, subcall) => {
+            _assert_type::<u32>(n);
+            _assert_type::<Vec<u8>>(subcall);
+        }
+        Call::MethodGenericMethodCall(n, subcall) => {
+            _assert_type::<u8>(n);
+            _assert_type::<Vec<u8>>(subcall);
+        }
         Call::MethodGenericInputCall(t, subcall) => {
             _assert_type::<T>(t);
             _assert_type::<Vec<u8>>(subcall);
@@ -153,3 +220,192 @@ fn _exhaustive_match_call<T: Call>(call: foo_call::Call<T>) {
         Call::MethodGenericOutputCall(subcall) => _assert_type::<Vec<u8>>(subcall),
     }
 }
+
+#[derive(Query, Call)]
+pub struct Counter {
+    pub count: u32,
+}
+
+#[orga::methods]
+impl Counter {
+    #[call]
+    pub fn increment(&mut self, n: &u32) -> orga::Result<()> {
+        self.count += n;
+        Ok(())
+    }
+
+    #[query]
+    pub fn count_plus(&self, n: u32) -> u32 {
+        self.count + n
+    }
+}
+
+#[test]
+fn method_call_and_query() {
+    use orga::call::MethodCall;
+    use orga::encoding::Encode;
+
+    let mut counter = Counter { count: 0 };
+
+    counter
+        .method_call(counter_methods::Call::MethodIncrement(2, vec![]))
+        .unwrap();
+    assert_eq!(counter.count, 2);
+
+    let call = counter_methods::Call::MethodIncrement(3, vec![])
+        .encode()
+        .unwrap();
+    counter.call(counter_call::Call::Method(call)).unwrap();
+    assert_eq!(counter.count, 5);
+
+    let query = counter_methods::Query::MethodCountPlus(1, vec![])
+        .encode()
+        .unwrap();
+    counter.query(counter_query::Query::Method(query)).unwrap();
+}
+
+#[test]
+fn method_call_without_methods() {
+    let mut tuple = TupleStruct(1);
+    assert!(tuple
+        .call(tuple_struct_call::Call::Method(vec![]))
+        .is_err());
+}
+
+#[derive(Query, Call)]
+pub enum Status {
+    Pending(Counter),
+    Passed { votes: u32 },
+    Rejected,
+}
+
+fn _exhaustive_match_enum_query(query: status_query::Query) {
+    use status_query::Query;
+    match query {
+        Query::This => {}
+        Query::PendingField0(_) => {}
+        Query::PassedFieldVotes(_) => {}
+        Query::Method(_) => {}
+    }
+}
+
+fn _exhaustive_match_enum_call(call: status_call::Call) {
+    use status_call::Call;
+    match call {
+        Call::Noop => {}
+        Call::PendingField0(_) => {}
+        Call::PassedFieldVotes(_) => {}
+        Call::Method(_) => {}
+    }
+}
+
+#[test]
+fn enum_call_and_query_route_to_active_variant() {
+    use orga::encoding::Encode;
+
+    let mut status = Status::Pending(Counter { count: 0 });
+
+    let method_call = counter_methods::Call::MethodIncrement(2, vec![])
+        .encode()
+        .unwrap();
+    let subcall = counter_call::Call::Method(method_call).encode().unwrap();
+    status
+        .call(status_call::Call::PendingField0(subcall))
+        .unwrap();
+    assert!(matches!(status, Status::Pending(Counter { count: 2 })));
+
+    let subquery = counter_query::Query::This;
+    status
+        .query(status_query::Query::PendingField0(subquery))
+        .unwrap();
+
+    assert!(status
+        .call(status_call::Call::PassedFieldVotes(vec![]))
+        .is_err());
+    assert!(status
+        .query(status_query::Query::PassedFieldVotes(()))
+        .is_err());
+}
+
+#[derive(Query, Call)]
+pub struct WithSkipped {
+    pub a: u32,
+    #[call(skip)]
+    #[query(skip)]
+    pub cache: Vec<u32>,
+    #[call(skip)]
+    pub b: u32,
+}
+
+fn _exhaustive_match_skipped_query(query: with_skipped_query::Query) {
+    use with_skipped_query::Query;
+    match query {
+        Query::This => {}
+        Query::FieldA(_) => {}
+        Query::FieldB(_) => {}
+        Query::Method(_) => {}
+    }
+}
+
+fn _exhaustive_match_skipped_call(call: with_skipped_call::Call) {
+    use with_skipped_call::Call;
+    match call {
+        Call::Noop => {}
+        Call::FieldA(_) => {}
+        Call::Method(_) => {}
+    }
+}
+
+#[derive(Query, Call)]
+pub struct Recorder {
+    pub total: u64,
+}
+
+#[orga::methods]
+impl Recorder {
+    #[call(instance(u8), instance(u32))]
+    pub fn add<U: Into<u64>>(&mut self, n: U) {
+        self.total += n.into();
+    }
+
+    #[call(instance(u16))]
+    pub fn add_impl_trait(&mut self, n: impl Into<u64>) {
+        self.total += n.into();
+    }
+
+    #[query(instance(u32))]
+    pub fn total_is<U: Into<u64>>(&self, n: U) -> orga::Result<()> {
+        if self.total != n.into() {
+            return Err(orga::Error::Query("Wrong total".into()));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn generic_method_dispatch() {
+    use orga::call::MethodCall;
+    use orga::query::MethodQuery;
+
+    let mut recorder = Recorder { total: 0 };
+
+    recorder
+        .method_call(recorder_methods::Call::MethodAddU8(1, vec![]))
+        .unwrap();
+    assert_eq!(recorder.total, 1);
+    recorder
+        .method_call(recorder_methods::Call::MethodAddU32(10, vec![]))
+        .unwrap();
+    assert_eq!(recorder.total, 11);
+    recorder
+        .method_call(recorder_methods::Call::MethodAddImplTrait(100, vec![]))
+        .unwrap();
+    assert_eq!(recorder.total, 111);
+
+    recorder
+        .method_query(recorder_methods::Query::MethodTotalIs(111, vec![]))
+        .unwrap();
+    assert!(recorder
+        .method_query(recorder_methods::Query::MethodTotalIs(1, vec![]))
+        .is_err());
+}